@@ -0,0 +1,162 @@
+//! CPU use and receive latency of the PCAN driver, run against the stand-in
+//! PCAN-Basic from `tests/pcan_stub`:
+//!
+//!     cargo bench --bench pcan_receive
+//!
+//! An idle channel is read first, showing that `read_frames` waits instead of
+//! spinning. Frames are then sent at 1 kHz from a second channel and the time
+//! from `send_frame` until `read_frames` returns them is measured.
+use crosscan::can::CanFrame;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use win_can_utils::CanDriver;
+use win_can_utils::PcanDriver;
+use win_can_utils::drivers::pcan::set_pcan_library_path;
+
+const IDLE: Duration = Duration::from_secs(1);
+const FRAMES: u32 = 1000;
+const PERIOD: Duration = Duration::from_millis(1);
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    set_pcan_library_path(pcan_stub::library_path())?;
+    let mut tx = open("USBBUS1").await?;
+    let mut rx = open("USBBUS2").await?;
+
+    // Idle channel.
+    let cpu = cpu_time();
+    let started = Instant::now();
+    let mut reads = 0;
+    while started.elapsed() < IDLE {
+        rx.read_frames().await?;
+        reads += 1;
+    }
+    println!(
+        "idle:  {} read_frames calls in {:?}, CPU {}",
+        reads,
+        started.elapsed(),
+        cpu_percent(cpu, started.elapsed())
+    );
+
+    // Frames at 1 kHz, numbered in their first four bytes.
+    let sent = Arc::new(Mutex::new(Vec::with_capacity(FRAMES as usize)));
+    let sender = {
+        let sent = sent.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(PERIOD);
+            for seq in 0..FRAMES {
+                ticker.tick().await;
+                let frame = CanFrame::new(0x100, &seq.to_le_bytes()).unwrap();
+                sent.lock().unwrap().push(Instant::now());
+                tx.send_frame(&frame).await.unwrap();
+            }
+            tx
+        })
+    };
+
+    let cpu = cpu_time();
+    let started = Instant::now();
+    let mut latencies = Vec::with_capacity(FRAMES as usize);
+    let deadline = started + PERIOD * FRAMES + Duration::from_secs(2);
+    while latencies.len() < FRAMES as usize && Instant::now() < deadline {
+        for frame in rx.read_frames().await? {
+            let received = Instant::now();
+            let seq = u32::from_le_bytes(frame.data()[..4].try_into().unwrap());
+            latencies.push(received - sent.lock().unwrap()[seq as usize]);
+        }
+    }
+    let elapsed = started.elapsed();
+    let mut tx = sender.await.unwrap();
+
+    latencies.sort();
+    // Quantile of the latencies, or "n/a" when no frame arrived.
+    let at = |q: f64| {
+        latencies
+            .get(((latencies.len().max(1) - 1) as f64 * q) as usize)
+            .map_or("n/a".to_string(), |latency| format!("{:?}", latency))
+    };
+    println!(
+        "1 kHz: {}/{} frames, latency p50 {} p99 {} max {}, CPU {}",
+        latencies.len(),
+        FRAMES,
+        at(0.5),
+        at(0.99),
+        at(1.0),
+        cpu_percent(cpu, elapsed)
+    );
+
+    tx.close_channel().await?;
+    rx.close_channel().await?;
+    Ok(())
+}
+
+async fn open(channel: &str) -> std::io::Result<PcanDriver> {
+    let mut driver = PcanDriver::open(channel).await?;
+    driver.set_bitrate(500_000).await?;
+    driver.open_channel().await?;
+    Ok(driver)
+}
+
+/// CPU time used by the process so far, user and system.
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    let micros = |tv: libc::timeval| tv.tv_sec as u64 * 1_000_000 + tv.tv_usec as u64;
+    Some(Duration::from_micros(
+        micros(usage.ru_utime) + micros(usage.ru_stime),
+    ))
+}
+
+/// CPU time used by the process so far, user and system.
+#[cfg(windows)]
+fn cpu_time() -> Option<Duration> {
+    use std::ffi::c_void;
+
+    #[repr(C)]
+    #[derive(Default, Clone, Copy)]
+    struct FileTime {
+        low: u32,
+        high: u32,
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+        fn GetProcessTimes(
+            process: *mut c_void,
+            creation: *mut FileTime,
+            exit: *mut FileTime,
+            kernel: *mut FileTime,
+            user: *mut FileTime,
+        ) -> i32;
+    }
+
+    let mut times = [FileTime::default(); 4];
+    let [creation, exit, kernel, user] = &mut times;
+    // SAFETY: the pseudo handle needs no closing and every pointer is valid.
+    if unsafe { GetProcessTimes(GetCurrentProcess(), creation, exit, kernel, user) } == 0 {
+        return None;
+    }
+    // FILETIMEs count 100 ns intervals.
+    let nanos = |t: &FileTime| (((t.high as u64) << 32) | t.low as u64) * 100;
+    Some(Duration::from_nanos(nanos(kernel) + nanos(user)))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+/// CPU used since `start` in percent of one core over `elapsed`.
+fn cpu_percent(start: Option<Duration>, elapsed: Duration) -> String {
+    match (start, cpu_time()) {
+        (Some(start), Some(now)) => format!(
+            "{:.1}%",
+            (now - start).as_secs_f64() * 100.0 / elapsed.as_secs_f64()
+        ),
+        _ => "n/a".to_string(),
+    }
+}
@@ -1,7 +1,18 @@
 fn main() {
-    // Tell Rust/Cargo to look in ./libs for libraries
-    println!("cargo:rustc-link-search=native=libs");
+    println!("cargo:rerun-if-changed=build.rs");
 
-    // Tell it to link with PCANBasic.lib
-    println!("cargo:rustc-link-lib=PCANBasic");
+    // PCAN-Basic is loaded at runtime (see drivers::pcan). Linking the import
+    // library is only needed when the `pcan-static` feature is enabled.
+    if std::env::var_os("CARGO_FEATURE_PCAN_STATIC").is_some() {
+        // Tell Rust/Cargo to look in ./libs and the crate root for libraries
+        println!("cargo:rustc-link-search=native=libs");
+        println!("cargo:rustc-link-search=native=.");
+
+        // Tell it to link with PCANBasic.lib (libpcanbasic.so on Linux)
+        if std::env::var("CARGO_CFG_WINDOWS").is_ok() {
+            println!("cargo:rustc-link-lib=PCANBasic");
+        } else {
+            println!("cargo:rustc-link-lib=pcanbasic");
+        }
+    }
 }
@@ -0,0 +1,720 @@
+/// Broadcast manager: cyclic transmission scheduled next to the driver and
+/// content filtered reception, in the spirit of the SocketCAN BCM's
+/// `TX_SETUP`/`TX_DELETE` and `RX_SETUP`/`RX_CHANGED`/`RX_TIMEOUT`.
+///
+/// `canserver` offers it to local clients on the channel's `bcm` IPC
+/// endpoint, speaking one JSON [`BcmRequest`] per line and answering each
+/// with a [`BcmReply`] line. Receive subscriptions push [`BcmEvent`] lines in
+/// between. Jobs and subscriptions belong to the connection that set them
+/// up: endless jobs and all subscriptions stop when it closes, jobs with a
+/// count run to completion.
+use crosscan::can::CanFrame;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines};
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio::sync::{Mutex, broadcast};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::drivers::CanDriver;
+use crate::thread_manager_async::{IpcClientStream, IpcListener, connect_ipc, pipe_path};
+
+/// Suffix of the IPC endpoint, e.g. `\\.\pipe\can_can0_bcm`.
+pub const BCM_PIPE_SUFFIX: &str = "bcm";
+
+/// Shortest period a cyclic job may have; faster ones would starve the
+/// driver lock the receive loop needs.
+pub const MIN_CYCLIC_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Identifier and extended flag, the key of cyclic jobs.
+pub type FrameKey = (u32, bool);
+
+/// A request to the broadcast manager.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BcmRequest {
+    /// Send `frame` every `interval_us`, `count` times or until deleted.
+    /// Replaces a job with the same id and restarts its timer.
+    TxSetup {
+        frame: CanFrame,
+        interval_us: u64,
+        #[serde(default)]
+        count: Option<u64>,
+    },
+    /// Change the frame a job sends, keeping its timing.
+    TxUpdate { frame: CanFrame },
+    /// Stop a job.
+    TxDelete { id: u32, extended: bool },
+    /// Watch received frames with `id`, or every id when it is absent, and
+    /// report them as [`BcmEvent::RxChanged`]: the first frame of an id and
+    /// then only those whose length or data differ from the previous one in
+    /// the bits set in `mask`. Data bytes beyond the mask are compared in
+    /// full. `all_frames` reports every frame instead.
+    ///
+    /// With `timeout_us`, [`BcmEvent::RxTimeout`] is sent once when an id
+    /// goes that long without a frame; a watched id is armed right away,
+    /// ids seen by an any-id subscription from their first frame on.
+    /// Replaces a subscription for the same id.
+    RxSetup {
+        #[serde(default)]
+        id: Option<u32>,
+        #[serde(default)]
+        extended: bool,
+        #[serde(default)]
+        mask: Option<Vec<u8>>,
+        #[serde(default)]
+        timeout_us: Option<u64>,
+        #[serde(default)]
+        all_frames: bool,
+    },
+    /// Stop a subscription.
+    RxDelete {
+        #[serde(default)]
+        id: Option<u32>,
+        #[serde(default)]
+        extended: bool,
+    },
+}
+
+/// The answer to a [`BcmRequest`].
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BcmReply {
+    Ok,
+    Error { message: String },
+}
+
+/// A notification pushed to a client with receive subscriptions.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum BcmEvent {
+    /// A frame passed a subscription's content filter.
+    RxChanged { frame: CanFrame },
+    /// No frame with this id arrived within the subscription's timeout.
+    RxTimeout { id: u32, extended: bool },
+}
+
+/// Any line the server sends.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ServerLine {
+    Reply(BcmReply),
+    Event(BcmEvent),
+}
+
+/// A cyclic transmission.
+struct CyclicJob {
+    frame: Arc<std::sync::Mutex<CanFrame>>,
+    task: JoinHandle<()>,
+    endless: bool,
+}
+
+/// The cyclic jobs of one client. Endless jobs are aborted when dropped.
+pub struct CyclicTasks {
+    driver: Arc<Mutex<Box<dyn CanDriver>>>,
+    jobs: HashMap<FrameKey, CyclicJob>,
+}
+
+impl CyclicTasks {
+    pub fn new(driver: Arc<Mutex<Box<dyn CanDriver>>>) -> Self {
+        Self {
+            driver,
+            jobs: HashMap::new(),
+        }
+    }
+
+    /// Send `frame` every `interval`, `count` times or until deleted,
+    /// replacing any job for the same id. A zero interval sends it once;
+    /// others below [`MIN_CYCLIC_INTERVAL`] are refused.
+    pub fn setup(
+        &mut self,
+        frame: CanFrame,
+        interval: Duration,
+        count: Option<u64>,
+    ) -> Result<(), String> {
+        if !interval.is_zero() && interval < MIN_CYCLIC_INTERVAL {
+            return Err(format!(
+                "interval {:?} is below the minimum of {:?}",
+                interval, MIN_CYCLIC_INTERVAL
+            ));
+        }
+        let key = (frame.id(), frame.is_extended());
+        if let Some(job) = self.jobs.remove(&key) {
+            job.task.abort();
+        }
+        let count = if interval.is_zero() { Some(1) } else { count };
+        if count == Some(0) {
+            return Ok(());
+        }
+
+        let frame = Arc::new(std::sync::Mutex::new(frame));
+        let task = tokio::spawn(cyclic_send(
+            self.driver.clone(),
+            frame.clone(),
+            interval,
+            count,
+        ));
+        if !interval.is_zero() {
+            let endless = count.is_none();
+            self.jobs.insert(
+                key,
+                CyclicJob {
+                    frame,
+                    task,
+                    endless,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// Change the frame of a running job without disturbing its timing.
+    pub fn update(&mut self, frame: CanFrame) -> Result<(), String> {
+        let job = self
+            .jobs
+            .get(&(frame.id(), frame.is_extended()))
+            .filter(|job| !job.task.is_finished())
+            .ok_or_else(|| "no such cyclic frame".to_string())?;
+        *job.frame.lock().unwrap() = frame;
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: FrameKey) -> Result<(), String> {
+        let job = self
+            .jobs
+            .remove(&key)
+            .ok_or_else(|| "no such cyclic frame".to_string())?;
+        job.task.abort();
+        Ok(())
+    }
+}
+
+impl Drop for CyclicTasks {
+    fn drop(&mut self) {
+        for job in self.jobs.values().filter(|job| job.endless) {
+            job.task.abort();
+        }
+    }
+}
+
+async fn cyclic_send(
+    driver: Arc<Mutex<Box<dyn CanDriver>>>,
+    frame: Arc<std::sync::Mutex<CanFrame>>,
+    interval: Duration,
+    count: Option<u64>,
+) {
+    // tokio rejects a zero period; such a job only sends once anyway.
+    let mut ticker = tokio::time::interval(interval.max(MIN_CYCLIC_INTERVAL));
+    // Stay on the original time grid instead of drifting after a late tick.
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut sent = 0;
+    while count.is_none_or(|count| sent < count) {
+        ticker.tick().await;
+        let frame = frame.lock().unwrap().clone();
+        if let Err(e) = driver.lock().await.send_frame(&frame).await {
+            log::warn!("bcm: cyclic send of {:X} failed: {}", frame.id(), e);
+        }
+        sent += 1;
+    }
+}
+
+/// One receive subscription: its filter settings and what it has seen.
+struct RxSubscription {
+    mask: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    all_frames: bool,
+    /// Last reported length and data per id.
+    last: HashMap<FrameKey, Vec<u8>>,
+    /// When each armed id times out.
+    deadlines: HashMap<FrameKey, Instant>,
+}
+
+impl RxSubscription {
+    fn changed(&self, key: &FrameKey, data: &[u8]) -> bool {
+        let Some(last) = self.last.get(key) else {
+            return true;
+        };
+        if last.len() != data.len() {
+            return true;
+        }
+        last.iter().zip(data).enumerate().any(|(i, (old, new))| {
+            let mask = self.mask.as_ref().and_then(|m| m.get(i)).copied();
+            (old ^ new) & mask.unwrap_or(0xFF) != 0
+        })
+    }
+}
+
+/// The receive subscriptions of one client, keyed by watched id; `None`
+/// watches every id without a subscription of its own.
+#[derive(Default)]
+pub struct RxSubscriptions {
+    subscriptions: HashMap<Option<FrameKey>, RxSubscription>,
+}
+
+impl RxSubscriptions {
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    pub fn setup(
+        &mut self,
+        key: Option<FrameKey>,
+        mask: Option<Vec<u8>>,
+        timeout: Option<Duration>,
+        all_frames: bool,
+    ) {
+        let mut deadlines = HashMap::new();
+        if let (Some(key), Some(timeout)) = (key, timeout) {
+            deadlines.insert(key, Instant::now() + timeout);
+        }
+        self.subscriptions.insert(
+            key,
+            RxSubscription {
+                mask,
+                timeout,
+                all_frames,
+                last: HashMap::new(),
+                deadlines,
+            },
+        );
+    }
+
+    pub fn delete(&mut self, key: Option<FrameKey>) -> Result<(), String> {
+        self.subscriptions
+            .remove(&key)
+            .map(|_| ())
+            .ok_or_else(|| "no such subscription".to_string())
+    }
+
+    /// Pass a received frame through the filters, re-arming its timeout.
+    pub fn on_frame(&mut self, frame: &CanFrame) -> Option<BcmEvent> {
+        let key = (frame.id(), frame.is_extended());
+        let subscription = match self.subscriptions.get_mut(&Some(key)) {
+            Some(subscription) => subscription,
+            None => self.subscriptions.get_mut(&None)?,
+        };
+
+        if let Some(timeout) = subscription.timeout {
+            subscription.deadlines.insert(key, Instant::now() + timeout);
+        }
+        let data = frame.data();
+        if !subscription.changed(&key, data) && !subscription.all_frames {
+            return None;
+        }
+        subscription.last.insert(key, data.to_vec());
+        Some(BcmEvent::RxChanged {
+            frame: frame.clone(),
+        })
+    }
+
+    /// The earliest pending timeout.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.subscriptions
+            .values()
+            .flat_map(|subscription| subscription.deadlines.values())
+            .min()
+            .copied()
+    }
+
+    /// Report the ids whose timeout passed; each stays quiet until its next frame.
+    pub fn expire(&mut self, now: Instant) -> Vec<BcmEvent> {
+        let mut events = Vec::new();
+        for subscription in self.subscriptions.values_mut() {
+            subscription.deadlines.retain(|&(id, extended), deadline| {
+                if *deadline > now {
+                    return true;
+                }
+                events.push(BcmEvent::RxTimeout { id, extended });
+                false
+            });
+        }
+        events
+    }
+}
+
+/// Serve the broadcast manager for `channel_name` to local clients.
+/// Receive subscriptions are fed from `frames`.
+pub async fn serve(
+    channel_name: String,
+    driver: Arc<Mutex<Box<dyn CanDriver>>>,
+    frames: broadcast::Sender<CanFrame>,
+) -> std::io::Result<()> {
+    let mut listener = IpcListener::bind(&pipe_path(&channel_name, BCM_PIPE_SUFFIX))?;
+    loop {
+        let stream = listener.accept().await?;
+        log::info!("bcm: client connected");
+        let tasks = CyclicTasks::new(driver.clone());
+        let frames = frames.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_client(stream, tasks, frames).await {
+                log::warn!("bcm: client: {}", e);
+            }
+            log::info!("bcm: client disconnected");
+        });
+    }
+}
+
+async fn serve_client<S: AsyncRead + AsyncWrite>(
+    stream: S,
+    mut tasks: CyclicTasks,
+    frames: broadcast::Sender<CanFrame>,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut subscriptions = RxSubscriptions::default();
+    // Only subscribed to received frames while there is something to watch.
+    let mut received: Option<broadcast::Receiver<CanFrame>> = None;
+
+    loop {
+        let deadline = subscriptions.next_deadline();
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let result = serde_json::from_str::<BcmRequest>(&line)
+                    .map_err(|e| format!("malformed request: {}", e))
+                    .and_then(|request| handle(&mut tasks, &mut subscriptions, request));
+                let reply = match result {
+                    Ok(()) => BcmReply::Ok,
+                    Err(message) => BcmReply::Error { message },
+                };
+                if subscriptions.is_empty() {
+                    received = None;
+                } else if received.is_none() {
+                    received = Some(frames.subscribe());
+                }
+                write_line(&mut writer, &reply).await?;
+            }
+            frame = recv_frame(&mut received) => match frame {
+                Ok(frame) => {
+                    if let Some(event) = subscriptions.on_frame(&frame) {
+                        write_line(&mut writer, &event).await?;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    log::warn!("bcm: client too slow, {} frames dropped", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                for event in subscriptions.expire(Instant::now()) {
+                    write_line(&mut writer, &event).await?;
+                }
+            }
+        }
+    }
+}
+
+/// The next received frame, or never without a subscription.
+async fn recv_frame(
+    received: &mut Option<broadcast::Receiver<CanFrame>>,
+) -> Result<CanFrame, broadcast::error::RecvError> {
+    match received {
+        Some(received) => received.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn write_line<W: AsyncWrite + Unpin, T: Serialize>(
+    writer: &mut W,
+    value: &T,
+) -> std::io::Result<()> {
+    let mut data = serde_json::to_vec(value)?;
+    data.push(b'\n');
+    writer.write_all(&data).await?;
+    writer.flush().await
+}
+
+fn handle(
+    tasks: &mut CyclicTasks,
+    subscriptions: &mut RxSubscriptions,
+    request: BcmRequest,
+) -> Result<(), String> {
+    match request {
+        BcmRequest::TxSetup {
+            frame,
+            interval_us,
+            count,
+        } => tasks.setup(frame, Duration::from_micros(interval_us), count),
+        BcmRequest::TxUpdate { frame } => tasks.update(frame),
+        BcmRequest::TxDelete { id, extended } => tasks.delete((id, extended)),
+        BcmRequest::RxSetup {
+            id,
+            extended,
+            mask,
+            timeout_us,
+            all_frames,
+        } => {
+            let timeout = timeout_us.filter(|&us| us > 0).map(Duration::from_micros);
+            subscriptions.setup(id.map(|id| (id, extended)), mask, timeout, all_frames);
+            Ok(())
+        }
+        BcmRequest::RxDelete { id, extended } => subscriptions.delete(id.map(|id| (id, extended))),
+    }
+}
+
+/// Client side of the broadcast manager endpoint.
+pub struct BcmClient {
+    lines: Lines<BufReader<ReadHalf<IpcClientStream>>>,
+    writer: WriteHalf<IpcClientStream>,
+    /// Events that arrived while waiting for a reply.
+    events: VecDeque<BcmEvent>,
+}
+
+impl BcmClient {
+    /// Connect to the broadcast manager of the server for `channel_name`.
+    pub async fn connect(channel_name: &str) -> std::io::Result<Self> {
+        let stream = connect_ipc(channel_name, BCM_PIPE_SUFFIX).await?;
+        let (reader, writer) = tokio::io::split(stream);
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            events: VecDeque::new(),
+        })
+    }
+
+    /// Send a request and wait for its reply; error replies become errors.
+    pub async fn request(&mut self, request: &BcmRequest) -> std::io::Result<()> {
+        write_line(&mut self.writer, request).await?;
+        loop {
+            match self.read_line().await? {
+                ServerLine::Reply(BcmReply::Ok) => return Ok(()),
+                ServerLine::Reply(BcmReply::Error { message }) => {
+                    return Err(std::io::Error::new(std::io::ErrorKind::Other, message));
+                }
+                ServerLine::Event(event) => self.events.push_back(event),
+            }
+        }
+    }
+
+    /// Wait for the next event of the receive subscriptions.
+    pub async fn next_event(&mut self) -> std::io::Result<BcmEvent> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        loop {
+            if let ServerLine::Event(event) = self.read_line().await? {
+                return Ok(event);
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> std::io::Result<ServerLine> {
+        let line = self.lines.next_line().await?.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "broadcast manager closed the connection",
+            )
+        })?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::VirtualDriver;
+
+    const ID: FrameKey = (0x100, false);
+
+    fn frame(id: u32, data: &[u8]) -> CanFrame {
+        CanFrame::new(id, data).unwrap()
+    }
+
+    fn changed(event: Option<BcmEvent>) -> Option<Vec<u8>> {
+        match event {
+            Some(BcmEvent::RxChanged { frame }) => Some(frame.data().to_vec()),
+            Some(other) => panic!("unexpected event {:?}", other),
+            None => None,
+        }
+    }
+
+    #[test]
+    fn mask_limits_which_bits_count_as_a_change() {
+        let mut subscriptions = RxSubscriptions::default();
+        subscriptions.setup(Some(ID), Some(vec![0x0F]), None, false);
+
+        assert!(changed(subscriptions.on_frame(&frame(0x100, &[0x01, 0xAA]))).is_some());
+        // Only the masked out high nibble differs.
+        assert!(changed(subscriptions.on_frame(&frame(0x100, &[0x11, 0xAA]))).is_none());
+        assert_eq!(
+            changed(subscriptions.on_frame(&frame(0x100, &[0x12, 0xAA]))),
+            Some(vec![0x12, 0xAA])
+        );
+        // Bytes beyond the mask are compared in full.
+        assert!(changed(subscriptions.on_frame(&frame(0x100, &[0x12, 0xAB]))).is_some());
+        // So is the length.
+        assert!(changed(subscriptions.on_frame(&frame(0x100, &[0x12]))).is_some());
+        assert!(changed(subscriptions.on_frame(&frame(0x100, &[0x12]))).is_none());
+    }
+
+    #[test]
+    fn all_frames_reports_repeats() {
+        let mut subscriptions = RxSubscriptions::default();
+        subscriptions.setup(Some(ID), None, None, true);
+
+        assert!(subscriptions.on_frame(&frame(0x100, &[1])).is_some());
+        assert!(subscriptions.on_frame(&frame(0x100, &[1])).is_some());
+        assert!(subscriptions.on_frame(&frame(0x101, &[1])).is_none());
+    }
+
+    #[test]
+    fn per_id_subscription_takes_precedence_over_any_id() {
+        let mut subscriptions = RxSubscriptions::default();
+        subscriptions.setup(None, None, None, false);
+        subscriptions.setup(Some(ID), Some(vec![0x00]), None, false);
+
+        // The per-id mask ignores the whole byte.
+        assert!(subscriptions.on_frame(&frame(0x100, &[1])).is_some());
+        assert!(subscriptions.on_frame(&frame(0x100, &[2])).is_none());
+        // Other ids, and the same id as an extended one, go to the any-id one.
+        assert!(subscriptions.on_frame(&frame(0x200, &[1])).is_some());
+        assert!(subscriptions.on_frame(&frame(0x200, &[2])).is_some());
+        let extended = CanFrame::new_eff(0x100, &[1]).unwrap();
+        assert!(subscriptions.on_frame(&extended).is_some());
+        assert!(subscriptions.on_frame(&extended).is_none());
+
+        subscriptions.delete(Some(ID)).unwrap();
+        assert!(subscriptions.on_frame(&frame(0x100, &[3])).is_some());
+        assert!(subscriptions.delete(Some(ID)).is_err());
+    }
+
+    #[test]
+    fn timeouts_fire_once_and_rearm_on_the_next_frame() {
+        let timeout = Duration::from_millis(100);
+        let mut subscriptions = RxSubscriptions::default();
+        subscriptions.setup(Some(ID), None, Some(timeout), false);
+
+        let deadline = subscriptions.next_deadline().unwrap();
+        assert!(
+            subscriptions
+                .expire(deadline - Duration::from_millis(1))
+                .is_empty()
+        );
+        let events = subscriptions.expire(deadline);
+        assert!(matches!(
+            events[..],
+            [BcmEvent::RxTimeout {
+                id: 0x100,
+                extended: false
+            }]
+        ));
+        assert!(subscriptions.next_deadline().is_none());
+        assert!(subscriptions.expire(deadline + timeout).is_empty());
+
+        subscriptions.on_frame(&frame(0x100, &[1]));
+        let rearmed = subscriptions.next_deadline().unwrap();
+        assert!(rearmed > deadline);
+        assert_eq!(subscriptions.expire(rearmed).len(), 1);
+    }
+
+    #[test]
+    fn any_id_timeouts_start_with_the_first_frame() {
+        let mut subscriptions = RxSubscriptions::default();
+        subscriptions.setup(None, None, Some(Duration::from_millis(100)), false);
+        assert!(subscriptions.next_deadline().is_none());
+
+        subscriptions.on_frame(&CanFrame::new_eff(0x1234, &[]).unwrap());
+        let deadline = subscriptions.next_deadline().unwrap();
+        let events = subscriptions.expire(deadline);
+        assert!(matches!(
+            events[..],
+            [BcmEvent::RxTimeout {
+                id: 0x1234,
+                extended: true
+            }]
+        ));
+    }
+
+    /// Cyclic tasks sending on `bus`, and a node watching it.
+    async fn tasks_on(bus: &str) -> (CyclicTasks, VirtualDriver) {
+        let mut sender = VirtualDriver::open(bus).await.unwrap();
+        sender.open_channel().await.unwrap();
+        let mut watcher = VirtualDriver::open(bus).await.unwrap();
+        watcher.open_channel().await.unwrap();
+        let driver: Box<dyn CanDriver> = Box::new(sender);
+        (CyclicTasks::new(Arc::new(Mutex::new(driver))), watcher)
+    }
+
+    /// Everything `watcher` receives until the bus has been quiet for a read.
+    async fn drain(watcher: &mut VirtualDriver) -> Vec<CanFrame> {
+        let mut frames = Vec::new();
+        loop {
+            let received = watcher.read_frames().await.unwrap();
+            if received.is_empty() {
+                return frames;
+            }
+            frames.extend(received);
+        }
+    }
+
+    #[tokio::test]
+    async fn zero_interval_sends_once() {
+        let (mut tasks, mut watcher) = tasks_on("test-bcm-once").await;
+
+        tasks
+            .setup(frame(0x100, &[1]), Duration::ZERO, None)
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(drain(&mut watcher).await.len(), 1);
+        // Nothing is left to update or delete.
+        assert!(tasks.update(frame(0x100, &[2])).is_err());
+        assert!(tasks.delete(ID).is_err());
+    }
+
+    #[tokio::test]
+    async fn intervals_under_a_millisecond_are_refused() {
+        let (mut tasks, _watcher) = tasks_on("test-bcm-minimum").await;
+
+        let fast = tasks.setup(frame(0x100, &[1]), Duration::from_micros(500), None);
+        assert!(fast.is_err());
+        assert!(tasks.update(frame(0x100, &[2])).is_err());
+    }
+
+    #[tokio::test]
+    async fn update_replaces_the_data_and_delete_stops() {
+        let (mut tasks, mut watcher) = tasks_on("test-bcm-update").await;
+
+        tasks
+            .setup(frame(0x100, &[1]), Duration::from_millis(2), None)
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        tasks.update(frame(0x100, &[2])).unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        tasks.delete(ID).unwrap();
+
+        let frames = drain(&mut watcher).await;
+        assert!(frames.len() >= 2);
+        assert_eq!(frames[0].data(), [1]);
+        assert_eq!(frames.last().unwrap().data(), [2]);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(drain(&mut watcher).await.is_empty());
+        assert!(tasks.delete(ID).is_err());
+    }
+
+    #[tokio::test]
+    async fn counted_jobs_outlive_their_client() {
+        let (mut tasks, mut watcher) = tasks_on("test-bcm-counted").await;
+
+        tasks
+            .setup(frame(0x100, &[1]), Duration::from_millis(2), Some(3))
+            .unwrap();
+        tasks
+            .setup(frame(0x200, &[1]), Duration::from_millis(2), None)
+            .unwrap();
+        drop(tasks);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        let frames = drain(&mut watcher).await;
+        assert_eq!(frames.iter().filter(|f| f.id() == 0x100).count(), 3);
+        // The endless job was aborted, at most after its first frame.
+        assert!(frames.iter().filter(|f| f.id() == 0x200).count() <= 1);
+    }
+}
@@ -0,0 +1,290 @@
+use clap::Parser;
+use std::collections::HashMap;
+use std::io::Write;
+use std::process;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant, MissedTickBehavior, sleep};
+use win_can_utils::bcm::{BcmClient, BcmEvent, BcmRequest, FrameKey};
+use win_can_utils::busload::{BusLoadMeter, LoadInterval, Stuffing, percent};
+use win_can_utils::thread_manager_async::{CanServerConfig, read_server_config};
+
+/// Width of the -b bargraph, one character per 5 percent.
+const BARGRAPH_WIDTH: usize = 20;
+
+/// Measure CAN bus load, like can-utils' `canbusload`.
+///
+/// Every frame counts with its exact length on the wire at the bitrate the
+/// channel's server advertises, or the one given as `<ifname>@<bitrate>`.
+/// CAN FD frames spend their data phase at the data bitrate, given as
+/// `<ifname>@<bitrate>,<data bitrate>`. Frames are taken from the server's
+/// broadcast manager, so candump can run alongside.
+#[derive(Debug, Parser)]
+#[command(name = "canbusload")]
+struct Args {
+    /// count the stuff bits each frame actually needs (default: worst case)
+    #[arg(short = 'e', conflicts_with = "ignore_stuffing")]
+    exact: bool,
+
+    /// ignore stuff bits
+    #[arg(short = 'i')]
+    ignore_stuffing: bool,
+
+    /// show a bargraph in 5% steps
+    #[arg(short = 'b')]
+    bargraph: bool,
+
+    /// redraw the terminal for each interval
+    #[arg(short = 'r')]
+    redraw: bool,
+
+    /// measurement interval in milli seconds
+    #[arg(long = "interval", value_name = "ms", default_value_t = 1000, value_parser = clap::value_parser!(u64).range(1..))]
+    interval_ms: u64,
+
+    /// show the <n> ids using the most bus time per channel (0: none)
+    #[arg(long = "ids", value_name = "n", default_value_t = 5)]
+    ids: usize,
+
+    /// CAN channels, optionally with the bitrates to assume: <ifname>[@<bitrate>[,<data bitrate>]]
+    #[arg(required = true, value_name = "IF[@BITRATE[,DBITRATE]]", num_args = 1.., value_parser = parse_channel)]
+    channels: Vec<ChannelSpec>,
+}
+
+#[derive(Debug, Clone)]
+struct ChannelSpec {
+    name: String,
+    rates: Option<(u32, Option<u32>)>,
+}
+
+/// A measured channel: its meter, filled while connected, and the peaks so far.
+struct Channel {
+    name: String,
+    meter: Arc<Mutex<Option<BusLoadMeter>>>,
+    peak: f64,
+    id_peaks: HashMap<FrameKey, f64>,
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+
+    let stuffing = if args.exact {
+        Stuffing::Exact
+    } else if args.ignore_stuffing {
+        Stuffing::Ignore
+    } else {
+        Stuffing::WorstCase
+    };
+
+    let mut channels = Vec::new();
+    for spec in &args.channels {
+        let meter = Arc::new(Mutex::new(None));
+        tokio::spawn(measure(spec.clone(), stuffing, meter.clone()));
+        channels.push(Channel {
+            name: spec.name.clone(),
+            meter,
+            peak: 0.0,
+            id_peaks: HashMap::new(),
+        });
+    }
+
+    let mut ticker = tokio::time::interval(Duration::from_millis(args.interval_ms));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker.tick().await;
+    let mut started = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+        let elapsed = started.elapsed();
+        started = Instant::now();
+
+        if args.redraw {
+            print!("\x1b[2J\x1b[H");
+        }
+        for channel in &mut channels {
+            let taken = channel.meter.lock().unwrap().as_mut().map(|meter| {
+                let label = match meter.data_bitrate() {
+                    Some(data_bitrate) => {
+                        format!("{}@{},{}", channel.name, meter.bitrate(), data_bitrate)
+                    }
+                    None => format!("{}@{}", channel.name, meter.bitrate()),
+                };
+                (label, meter.take())
+            });
+            match taken {
+                Some((label, interval)) => report(channel, &label, &interval, elapsed, &args),
+                None => println!(" {:<16} (not connected)", channel.name),
+            }
+        }
+        if args.redraw || args.ids > 0 || channels.len() > 1 {
+            println!();
+        }
+        let _ = std::io::stdout().flush();
+    }
+
+    for channel in &channels {
+        println!(" {:<16} peak {:5.1}%", channel.name, channel.peak);
+    }
+    Ok(())
+}
+
+/// Print one interval of a channel and update its peaks.
+fn report(
+    channel: &mut Channel,
+    label: &str,
+    interval: &LoadInterval,
+    elapsed: Duration,
+    args: &Args,
+) {
+    let load = interval.load(elapsed);
+    channel.peak = channel.peak.max(load);
+
+    let mut line = format!(
+        " {:<16} {:>7} frames {:>9} bits {:5.1}% (peak {:5.1}%)",
+        label, interval.frames, interval.bits, load, channel.peak
+    );
+    if args.bargraph {
+        let filled = ((load / 5.0) as usize).min(BARGRAPH_WIDTH);
+        line.push_str(&format!(
+            " |{}{}|",
+            "X".repeat(filled),
+            ".".repeat(BARGRAPH_WIDTH - filled)
+        ));
+    }
+    println!("{}", line);
+
+    for (key, usage) in &interval.per_id {
+        let peak = channel.id_peaks.entry(*key).or_default();
+        *peak = peak.max(percent(usage.busy, elapsed));
+    }
+    if args.ids == 0 {
+        return;
+    }
+
+    let mut busiest: Vec<_> = interval.per_id.iter().collect();
+    busiest.sort_by(|a, b| b.1.busy.cmp(&a.1.busy).then(a.0.cmp(b.0)));
+    for (key, usage) in busiest.into_iter().take(args.ids) {
+        let (id, extended) = *key;
+        let id = if extended {
+            format!("{:08X}", id)
+        } else {
+            format!("{:03X}", id)
+        };
+        println!(
+            "   {:<14} {:>7} frames {:>9} bits {:5.1}% (peak {:5.1}%)",
+            id,
+            usage.frames,
+            usage.bits,
+            percent(usage.busy, elapsed),
+            channel.id_peaks[key]
+        );
+    }
+}
+
+/// Feed a channel's frames into its meter, reconnecting if its server restarts.
+async fn measure(spec: ChannelSpec, stuffing: Stuffing, meter: Arc<Mutex<Option<BusLoadMeter>>>) {
+    loop {
+        let mut client = connect_bcm_retry(&spec.name).await;
+        let (bitrate, data_bitrate) = match spec.rates {
+            Some(rates) => rates,
+            None => match read_server_config(&spec.name).await {
+                Ok(config) => advertised_rates(&spec.name, &config),
+                Err(e) => {
+                    eprintln!("Unable to read the configuration of {}: {}", spec.name, e);
+                    sleep(Duration::from_millis(500)).await;
+                    continue;
+                }
+            },
+        };
+
+        let request = BcmRequest::RxSetup {
+            id: None,
+            extended: false,
+            mask: None,
+            timeout_us: None,
+            all_frames: true,
+        };
+        if let Err(e) = client.request(&request).await {
+            eprintln!("Unable to subscribe to {}: {}", spec.name, e);
+            sleep(Duration::from_millis(500)).await;
+            continue;
+        }
+        *meter.lock().unwrap() = Some(BusLoadMeter::new(bitrate, data_bitrate, stuffing));
+
+        loop {
+            match client.next_event().await {
+                Ok(BcmEvent::RxChanged { frame }) => {
+                    if let Some(meter) = meter.lock().unwrap().as_mut() {
+                        meter.add(&frame);
+                    }
+                }
+                Ok(BcmEvent::RxTimeout { .. }) => {}
+                Err(e) => {
+                    eprintln!("Error reading from {}: {}", spec.name, e);
+                    break;
+                }
+            }
+        }
+        *meter.lock().unwrap() = None;
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// The bitrates a server advertises; exits if it does not know its bitrate.
+fn advertised_rates(channel: &str, config: &CanServerConfig) -> (u32, Option<u32>) {
+    match config.bitrate {
+        Some(bitrate) if bitrate > 0 => (bitrate, config.data_bitrate.filter(|&rate| rate > 0)),
+        _ => {
+            eprintln!(
+                "The server for {} does not know its bitrate, give it as {}@<bitrate>",
+                channel, channel
+            );
+            process::exit(1);
+        }
+    }
+}
+
+async fn connect_bcm_retry(channel: &str) -> BcmClient {
+    eprintln!("Attempting to connect to {} server", channel);
+
+    loop {
+        match BcmClient::connect(channel).await {
+            Ok(client) => {
+                eprintln!("Connected to {} server", channel);
+                return client;
+            }
+            Err(_) => {
+                sleep(Duration::from_millis(500)).await;
+            }
+        };
+    }
+}
+
+fn parse_channel(s: &str) -> Result<ChannelSpec, String> {
+    let Some((name, rates)) = s.split_once('@') else {
+        return Ok(ChannelSpec {
+            name: s.to_string(),
+            rates: None,
+        });
+    };
+    let parse_rate = |rate: &str| {
+        rate.parse::<u32>()
+            .ok()
+            .filter(|&rate| rate > 0)
+            .ok_or_else(|| format!("invalid bitrate '{}'", rate))
+    };
+    let (bitrate, data_bitrate) = match rates.split_once(',') {
+        Some((bitrate, data_bitrate)) => (parse_rate(bitrate)?, Some(parse_rate(data_bitrate)?)),
+        None => (parse_rate(rates)?, None),
+    };
+    if name.is_empty() {
+        return Err(format!("missing channel name in '{}'", s));
+    }
+    Ok(ChannelSpec {
+        name: name.to_string(),
+        rates: Some((bitrate, data_bitrate)),
+    })
+}
@@ -1,22 +1,30 @@
 use clap::{ArgAction, Parser};
-use crosscan::{CanInterface, can::CanFrame, win_can::WindowsCan};
-use futures::future::join_all;
+use crosscan::can::CanFrame;
+use futures::future::try_join_all;
+use std::collections::HashSet;
 use tokio::task;
 use tokio::time::{Duration, sleep};
 
-use std::num::ParseIntError;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
-
-/// Minimal clap-based parser for `candump` (argument parsing only).
+use win_can_utils::bcm::{BcmClient, BcmEvent, BcmRequest, FrameKey};
+use win_can_utils::can_log::{format_line, log_file_name, parse_data};
+use win_can_utils::drivers::timestamp_us;
+use win_can_utils::filter::FilterSet;
+use win_can_utils::log_writer::{LogWriter, RotationOptions};
+use win_can_utils::thread_manager_async::FrameClient;
+
+/// Command line of `candump`: dump, filter and log the traffic of one or
+/// more channels served by canserver.
 ///
-/// This mirrors the common candump command-line switches (parsing only)
-/// and provides a structured configuration you can hook into real
-/// candump-like behaviour later.
+/// The switches mirror can-utils' candump; options marked "Not implemented
+/// yet" are accepted but ignored.
 ///
 /// See: candump manpage for option semantics.
 #[derive(Debug, Parser)]
 #[command(name = "candump-rs")]
-#[command(about = "candump-compatible CLI parser (args only)")]
+#[command(about = "Dump, filter and log CAN traffic, like can-utils' candump")]
 pub struct Args {
     /// timestamp type: a (absolute), d (delta), z (zero), A (absolute w date)
     #[arg(short = 't', value_name = "type")]
@@ -42,22 +50,51 @@ pub struct Args {
     #[arg(short = 'S', action = ArgAction::SetTrue, hide = true)]
     pub swap_bytes: bool,
 
-    /// silent mode level: 0 (off), 1 (animation), 2 (silent) (Not implemented yet)
-    #[arg(short = 's', value_name = "level", hide = true)]
+    /// silent mode level: 0 (off), 1 (animation), 2 (silent)
+    #[arg(short = 's', value_name = "level", value_parser = clap::value_parser!(u8).range(0..=2))]
     pub silent_level: Option<u8>,
 
-    /// log CAN frames into file (same as -f but toggles logging flag) (Not implemented yet)
-    #[arg(short = 'l', action = ArgAction::SetTrue, hide = true)]
+    /// log CAN frames into file candump-<date>_<time>.log (sets '-s 2' by default)
+    #[arg(short = 'l', action = ArgAction::SetTrue)]
     pub log: bool,
 
-    /// log filename (sets silent mode 2 by default in original candump) (Not implemented yet)
-    #[arg(short = 'f', value_name = "fname", hide = true)]
+    /// log CAN frames into file <fname> (sets '-s 2' by default)
+    #[arg(short = 'f', value_name = "fname")]
     pub logfile: Option<String>,
 
-    /// use log file format on stdout (Not implemented yet)
-    #[arg(short = 'L', action = ArgAction::SetTrue, hide = true)]
+    /// use log file format on stdout
+    #[arg(short = 'L', action = ArgAction::SetTrue)]
     pub stdout_logformat: bool,
 
+    /// start a new log file segment before one exceeds <size> bytes (k, M, G suffixes)
+    #[arg(long = "rotate-size", value_name = "size", value_parser = parse_size)]
+    pub rotate_size: Option<u64>,
+
+    /// start a new log file segment every <time> (s, m, h, d suffixes; seconds by default)
+    #[arg(long = "rotate-time", value_name = "time", value_parser = parse_duration)]
+    pub rotate_time: Option<Duration>,
+
+    /// gzip log file segments once closed
+    #[arg(long = "compress", action = ArgAction::SetTrue)]
+    pub compress: bool,
+
+    /// delete the oldest closed log file segments beyond <size> bytes in total (k, M, G suffixes)
+    #[arg(long = "max-total", value_name = "size", value_parser = parse_size)]
+    pub max_total: Option<u64>,
+
+    /// only show frames whose data changed since the last frame with the same id
+    #[arg(long = "changes-only", action = ArgAction::SetTrue)]
+    pub changes_only: bool,
+
+    /// with --changes-only, only compare the data bits set in <mask>, e.g. FF00FF
+    #[arg(long = "change-mask", value_name = "mask", requires = "changes_only", value_parser = parse_mask)]
+    // Spelled out so clap takes one value rather than a list of bytes.
+    pub change_mask: Option<::std::vec::Vec<u8>>,
+
+    /// report ids that were received but then stay silent for <msecs>
+    #[arg(long = "timeout-alarm", value_name = "msecs", value_parser = clap::value_parser!(u64).range(1..))]
+    pub timeout_alarm: Option<u64>,
+
     /// terminate after reception of <count> CAN frames (Not implemented yet)
     #[arg(short = 'n', value_name = "count", hide = true)]
     pub count: Option<u64>,
@@ -182,129 +219,65 @@ impl TimestampCtx {
     }
 }
 
-/// A parsed filter from the candump filter grammar.
-#[derive(Debug, Clone)]
-pub enum Filter {
-    /// <can_id>:<can_mask>
-    Match { id: u32, mask: u32 },
-    /// <can_id>~<can_mask>
-    NotMatch { id: u32, mask: u32 },
-    /// #<error_mask>
-    ErrorMask(u32),
-    /// Join flag: 'j' or 'J' means join filters (logical AND)
-    Join,
-}
-
-impl Filter {
-    /// Returns true if this filter matches the given CAN ID
-    pub fn matches(&self, can_id: u32) -> bool {
-        match *self {
-            Filter::Match { id, mask } => (can_id & mask) == (id & mask),
-            Filter::NotMatch { id, mask } => (can_id & mask) != (id & mask),
-            Filter::ErrorMask(_) => false, // error filters are not for normal CAN IDs
-            Filter::Join => true,          // Join is logical AND, doesn't filter on its own
-        }
-    }
-}
-
 pub struct CandumpInterface {
     pub ifname: String,
-    pub filters: Vec<Filter>,
-    pub pipe: WindowsCan,
+    pub filters: FilterSet,
+    pub source: FrameSource,
 }
 
 impl CandumpInterface {
-    pub async fn parse_and_connect(spec: &str) -> Result<Self, String> {
+    /// Connect to the server's frame pipe, or to its broadcast manager when
+    /// `rx_setup` asks for content filtering or timeout alarms.
+    pub async fn parse_and_connect(
+        spec: &str,
+        rx_setup: Option<&BcmRequest>,
+    ) -> Result<Self, String> {
         // format: ifname[,filter]*
-        let mut parts = spec.split(',');
-        let ifname = parts
-            .next()
-            .ok_or_else(|| "empty interface specification".to_string())?
-            .to_string();
-        let mut filters = Vec::new();
-        for token in parts {
-            let token = token.trim();
-            if token.is_empty() {
-                continue;
-            }
-            if token.eq_ignore_ascii_case("j") {
-                filters.push(Filter::Join);
-                continue;
-            }
-            if let Some(rest) = token.strip_prefix('#') {
-                let val =
-                    parse_hex(rest).map_err(|e| format!("invalid error mask '{}': {}", rest, e))?;
-                filters.push(Filter::ErrorMask(val));
-                continue;
+        let (ifname, filters) = FilterSet::parse_spec(spec)?;
+
+        let source = match rx_setup {
+            Some(request) => {
+                let mut client = connect_bcm_retry(ifname.as_str()).await;
+                client
+                    .request(request)
+                    .await
+                    .map_err(|e| format!("receive filter setup failed: {}", e))?;
+                FrameSource::Bcm(client)
             }
-            if let Some((a, b)) = token.split_once(':') {
-                let id = parse_hex(a).map_err(|e| format!("invalid can_id '{}': {}", a, e))?;
-                let mask = parse_hex(b).map_err(|e| format!("invalid can_mask '{}': {}", b, e))?;
-                filters.push(Filter::Match { id, mask });
-                continue;
-            }
-            if let Some((a, b)) = token.split_once('~') {
-                let id = parse_hex(a).map_err(|e| format!("invalid can_id '{}': {}", a, e))?;
-                let mask = parse_hex(b).map_err(|e| format!("invalid can_mask '{}': {}", b, e))?;
-                filters.push(Filter::NotMatch { id, mask });
-                continue;
-            }
-            // If token looks like plain hex (e.g. 12345678), treat as id with default mask 0xFFFFFFFF
-            if is_hex(token) {
-                let id =
-                    parse_hex(token).map_err(|e| format!("invalid hex id '{}': {}", token, e))?;
-                filters.push(Filter::Match {
-                    id,
-                    mask: 0xFFFFFFFF,
-                });
-                continue;
-            }
-
-            return Err(format!("unrecognized filter token: '{}'", token));
-        }
-
-        let pipe = connect_pipe_retry(ifname.as_str()).await;
+            None => FrameSource::Pipe(connect_pipe_retry(ifname.as_str()).await),
+        };
 
         Ok(CandumpInterface {
             ifname,
             filters,
-            pipe,
+            source,
         })
     }
+}
 
-    /// Returns true if the given CAN ID passes all filters for this interface
-    pub fn filter_check(&self, can_id: u32) -> bool {
-        let mut matched = false;
-        let mut join_mode = false;
-        for f in &self.filters {
-            match f {
-                Filter::Join => join_mode = true,
-                Filter::ErrorMask(_) => {}
-                _ => {
-                    if join_mode {
-                        matched &= f.matches(can_id);
-                    } else {
-                        matched |= f.matches(can_id);
-                    }
-                }
-            }
-        }
-        matched
-    }
+/// Where an interface's frames come from.
+pub enum FrameSource {
+    /// Every frame, from the server's frame pipe.
+    Pipe(FrameClient),
+    /// Frames and timeouts from a receive subscription.
+    Bcm(BcmClient),
 }
 
-fn is_hex(s: &str) -> bool {
-    s.chars().all(|c| c.is_ascii_hexdigit())
+/// What a frame source delivers.
+pub enum Received {
+    Frame(CanFrame),
+    Timeout(FrameKey),
 }
 
-fn parse_hex(s: &str) -> Result<u32, ParseIntError> {
-    // Accept leading 0x/0X or plain hex
-    if let Some(rest) = s.strip_prefix("0x") {
-        u32::from_str_radix(rest, 16)
-    } else if let Some(rest) = s.strip_prefix("0X") {
-        u32::from_str_radix(rest, 16)
-    } else {
-        u32::from_str_radix(s, 16)
+impl FrameSource {
+    async fn read(&mut self) -> std::io::Result<Received> {
+        match self {
+            FrameSource::Pipe(pipe) => pipe.read_frame().await.map(Received::Frame),
+            FrameSource::Bcm(client) => Ok(match client.next_event().await? {
+                BcmEvent::RxChanged { frame } => Received::Frame(frame),
+                BcmEvent::RxTimeout { id, extended } => Received::Timeout((id, extended)),
+            }),
+        }
     }
 }
 
@@ -312,11 +285,47 @@ fn parse_hex(s: &str) -> Result<u32, ParseIntError> {
 async fn main() -> std::io::Result<()> {
     let args = Args::parse();
 
-    // Quick sanity: if -l or -f was given, mimic candump behaviour: sets silent mode 2 by default
-    let _silent_level = if args.log || args.logfile.is_some() {
-        Some(2)
-    } else {
-        args.silent_level
+    let log_path = args
+        .logfile
+        .clone()
+        .or_else(|| args.log.then(|| log_file_name(SystemTime::now())));
+
+    // Logging into a file silences stdout unless -s says otherwise.
+    let silent_level = args
+        .silent_level
+        .unwrap_or(if log_path.is_some() { 2 } else { 0 });
+
+    let rotation = RotationOptions {
+        max_size: args.rotate_size,
+        max_age: args.rotate_time,
+        compress: args.compress,
+        max_total: args.max_total,
+    };
+    if log_path.is_none()
+        && (rotation.rotates() || rotation.compress || rotation.max_total.is_some())
+    {
+        eprintln!("log rotation options need a log file (-l or -f)");
+        std::process::exit(1);
+    }
+
+    let log_file = match &log_path {
+        Some(path) => match LogWriter::create(path, rotation) {
+            Ok(writer) => {
+                eprintln!("Enabling Logfile '{}'", writer.path().display());
+                Some(Arc::new(Mutex::new(Some(writer))))
+            }
+            Err(e) => {
+                eprintln!("error creating logfile '{}': {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let output = Output {
+        silent_level,
+        stdout_logformat: args.stdout_logformat,
+        log_file,
     };
 
     let ts_mode = args
@@ -324,10 +333,21 @@ async fn main() -> std::io::Result<()> {
         .and_then(TimestampMode::from_char)
         .unwrap_or(TimestampMode::None);
 
+    // Content filtering and timeout alarms run in the server, on an any-id
+    // receive subscription.
+    let rx_setup =
+        (args.changes_only || args.timeout_alarm.is_some()).then(|| BcmRequest::RxSetup {
+            id: None,
+            extended: false,
+            mask: args.change_mask.clone(),
+            timeout_us: args.timeout_alarm.map(|ms| ms * 1000),
+            all_frames: !args.changes_only,
+        });
+
     // Parse interfaces
     let mut interfaces = Vec::new();
     for spec in &args.interfaces {
-        match CandumpInterface::parse_and_connect(spec).await {
+        match CandumpInterface::parse_and_connect(spec, rx_setup.as_ref()).await {
             Ok(i) => {
                 interfaces.push(i);
             }
@@ -338,45 +358,187 @@ async fn main() -> std::io::Result<()> {
         }
     }
 
-    let _ = run_interfaces(interfaces, ts_mode, args.hardware_ts).await;
+    let log_file = output.log_file.clone();
+    let result = tokio::select! {
+        result = run_interfaces(interfaces, ts_mode, args.hardware_ts, output) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    // Flush the last segment so a capture stopped by Ctrl+C or an error is complete.
+    let writer = log_file.and_then(|log| log.lock().unwrap().take());
+    if let Err(e) = writer.map_or(Ok(()), LogWriter::finish) {
+        eprintln!("Error closing logfile: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 
     Ok(())
 }
 
-async fn connect_pipe_retry(channel: &str) -> WindowsCan {
-    println!("Attempting to connect to {} server", channel);
+/// Parse a byte count with an optional k, M or G (binary) suffix.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, scale) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("invalid size '{}'", s))
+}
+
+/// Parse a duration in seconds with an optional s, m, h or d suffix.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (digits, scale) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        Some((i, 'd')) => (&s[..i], 24 * 60 * 60),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .filter(|&n| n > 0)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid time '{}'", s))
+}
+
+/// Parse a change mask: hex bytes, optionally separated by dots.
+fn parse_mask(s: &str) -> Result<Vec<u8>, String> {
+    parse_data(s)
+        .filter(|mask| !mask.is_empty())
+        .ok_or_else(|| format!("invalid mask '{}'", s))
+}
+
+/// Log files always carry absolute timestamps: the hardware one with -H,
+/// otherwise the time of reception.
+fn log_timestamp(frame: &CanFrame, hardware_ts: bool) -> u64 {
+    match frame.timestamp() {
+        Some(ts) if hardware_ts => ts,
+        _ => timestamp_us(),
+    }
+}
+
+async fn connect_pipe_retry(channel: &str) -> FrameClient {
+    eprintln!("Attempting to connect to {} server", channel);
 
     loop {
-        match WindowsCan::open_read_only(channel) {
+        match FrameClient::open_read_only(channel).await {
             Ok(pipe) => {
-                println!("Connected to {} server", channel);
+                eprintln!("Connected to {} server", channel);
                 return pipe;
             }
             Err(_) => {
-                println!("Unable to connect. Is a server running for {}?", channel);
+                eprintln!("Unable to connect. Is a server running for {}?", channel);
                 sleep(Duration::from_millis(500)).await;
             }
         };
     }
 }
 
+async fn connect_bcm_retry(channel: &str) -> BcmClient {
+    eprintln!("Attempting to connect to {} broadcast manager", channel);
+
+    loop {
+        match BcmClient::connect(channel).await {
+            Ok(client) => {
+                eprintln!("Connected to {} broadcast manager", channel);
+                return client;
+            }
+            Err(_) => {
+                eprintln!("Unable to connect. Is a server running for {}?", channel);
+                sleep(Duration::from_millis(500)).await;
+            }
+        };
+    }
+}
+
+/// Where received frames go: stdout (per the silent level) and the log file.
+#[derive(Clone)]
+struct Output {
+    silent_level: u8,
+    stdout_logformat: bool,
+    /// Taken and finished on exit.
+    log_file: Option<Arc<Mutex<Option<LogWriter>>>>,
+}
+
+/// Characters cycled through by silent mode 1.
+const ANIMATION: [char; 4] = ['|', '/', '-', '\\'];
+
 // Assuming interfaces is a Vec<Interface> or similar
 async fn run_interfaces(
     interfaces: Vec<CandumpInterface>,
     ts_mode: TimestampMode,
     hardware_ts: bool,
+    output: Output,
 ) -> anyhow::Result<()> {
     let mut handles = Vec::new();
 
     for mut interface in interfaces.into_iter() {
         // Clone or move anything needed into the task
         let mut ts_ctx = TimestampCtx::new(ts_mode, hardware_ts);
+        let output = output.clone();
+        let mut animation = 0;
+        // Ids that passed the filters, the only ones whose timeouts are shown.
+        let mut shown: HashSet<FrameKey> = HashSet::new();
 
         let handle = task::spawn(async move {
             loop {
-                match interface.pipe.read_frame().await {
-                    Ok(frame) => {
-                        if interface.filters.len() > 0 && !interface.filter_check(frame.id()) {
+                match interface.source.read().await {
+                    Ok(Received::Timeout(key)) => {
+                        if shown.contains(&key) {
+                            eprintln!(
+                                "{}: no frame with id {} received in time",
+                                interface.ifname,
+                                format_id(key)
+                            );
+                        }
+                    }
+                    Ok(Received::Frame(frame)) => {
+                        if !interface.filters.matches(&frame) {
+                            continue;
+                        }
+                        shown.insert((frame.id(), frame.is_extended()));
+
+                        let log_line =
+                            (output.log_file.is_some() || output.stdout_logformat).then(|| {
+                                format_line(
+                                    log_timestamp(&frame, hardware_ts),
+                                    &interface.ifname,
+                                    &frame,
+                                )
+                            });
+
+                        if let (Some(log), Some(line)) = (&output.log_file, &log_line) {
+                            let mut writer = log.lock().unwrap();
+                            let result = writer.as_mut().map_or(Ok(()), |w| w.write_line(line));
+                            if let Err(e) = result {
+                                anyhow::bail!("Error writing logfile: {}", e);
+                            }
+                        }
+
+                        match output.silent_level {
+                            0 => {}
+                            1 => {
+                                print!("{}\x08", ANIMATION[animation % ANIMATION.len()]);
+                                let _ = std::io::stdout().flush();
+                                animation += 1;
+                                continue;
+                            }
+                            _ => continue,
+                        }
+
+                        if output.stdout_logformat {
+                            println!("{}", log_line.unwrap_or_default());
                             continue;
                         }
 
@@ -385,12 +547,7 @@ async fn run_interfaces(
                             format!("({:03}.{:06}) ", t / 1_000_000, t % 1_000_000)
                         });
 
-                        // CAN ID string
-                        let id = if frame.is_extended() {
-                            format!("{:08X}", frame.id())
-                        } else {
-                            format!("{:03X}", frame.id())
-                        };
+                        let id = format_id((frame.id(), frame.is_extended()));
 
                         println!(
                             "{}{} {:>08}   [{}]  {}",
@@ -408,7 +565,7 @@ async fn run_interfaces(
                     }
                     Err(e) => {
                         eprintln!("Error reading from {}: {:?}", interface.ifname, e);
-                        break;
+                        return Ok(());
                     }
                 }
             }
@@ -417,8 +574,18 @@ async fn run_interfaces(
         handles.push(handle);
     }
 
-    // Wait for all tasks to finish (they won’t unless error occurs)
-    join_all(handles).await;
+    // Wait for all tasks to finish (they won’t unless error occurs); a
+    // failing log file stops them all.
+    try_join_all(handles.into_iter().map(|handle| async { handle.await? })).await?;
 
     Ok(())
 }
+
+/// CAN ID string: 3 hex digits for standard, 8 for extended ids.
+fn format_id((id, extended): FrameKey) -> String {
+    if extended {
+        format!("{:08X}", id)
+    } else {
+        format!("{:03X}", id)
+    }
+}
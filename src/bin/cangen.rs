@@ -0,0 +1,492 @@
+use clap::Parser;
+use crosscan::can::CanFrame;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{Duration, Instant, sleep_until};
+use win_can_utils::busload::{Stuffing, frame_bits};
+use win_can_utils::can_log::{build_frame, format_frame};
+use win_can_utils::thread_manager_async::FrameClient;
+
+/// Valid CAN FD payload lengths, indexed by DLC.
+const CANFD_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// Generate CAN traffic for load and robustness tests, like can-utils' `cangen`.
+#[derive(Debug, Parser)]
+#[command(name = "cangen")]
+struct Args {
+    /// CAN channel name, e.g. can0 or COM4
+    channel: String,
+
+    /// gap in milli seconds between bursts
+    #[arg(short = 'g', value_name = "ms", default_value_t = 200.0)]
+    gap_ms: f64,
+
+    /// randomize the gap between -g and <ms> milli seconds
+    #[arg(long = "max-gap", value_name = "ms")]
+    max_gap_ms: Option<f64>,
+
+    /// send <count> frames per gap
+    #[arg(short = 'c', value_name = "count", default_value_t = 1)]
+    burst: u32,
+
+    /// send at a rate loading the bus to <percent>, instead of -g
+    #[arg(long = "busload", value_name = "percent")]
+    busload: Option<f64>,
+
+    /// nominal bitrate used for --busload
+    #[arg(long = "bitrate", value_name = "bps", default_value_t = 500_000)]
+    bitrate: u32,
+
+    /// CAN FD data phase bitrate used for --busload (default: the nominal bitrate)
+    #[arg(long = "data-bitrate", value_name = "bps")]
+    data_bitrate: Option<u32>,
+
+    /// generate extended frame mode (EFF) frames
+    #[arg(short = 'e')]
+    extended: bool,
+
+    /// generate CAN FD frames
+    #[arg(short = 'f')]
+    fd: bool,
+
+    /// generate remote transmission request (RTR) frames
+    #[arg(short = 'R')]
+    rtr: bool,
+
+    /// mix standard, extended, CAN FD and RTR frames
+    #[arg(short = 'm')]
+    mix: bool,
+
+    /// id generation: 'r'andom, 'i'ncrement or a fixed hex id
+    #[arg(short = 'I', value_name = "mode", default_value = "r", value_parser = parse_id_mode)]
+    id_mode: Mode,
+
+    /// length generation: 'r'andom, 'i'ncrement or a fixed length
+    #[arg(short = 'L', value_name = "mode", default_value = "r", value_parser = parse_len_mode)]
+    len_mode: Mode,
+
+    /// data generation: 'r'andom, 'i'ncrement or fixed hex data (sets the length)
+    #[arg(short = 'D', value_name = "mode", default_value = "r", value_parser = parse_data_mode)]
+    data_mode: DataMode,
+
+    /// stop after <count> frames (default: run until Ctrl+C)
+    #[arg(short = 'n', value_name = "count", value_parser = clap::value_parser!(u64).range(1..))]
+    count: Option<u64>,
+
+    /// keep going after errors writing to the server's pipe
+    #[arg(short = 'i')]
+    ignore_errors: bool,
+
+    /// print the generated frames
+    #[arg(short = 'v')]
+    verbose: bool,
+}
+
+/// How an id or length is generated.
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    Random,
+    Increment,
+    Fixed(u32),
+}
+
+#[derive(Debug, Clone)]
+enum DataMode {
+    Random,
+    Increment,
+    Fixed(Vec<u8>),
+}
+
+/// Counters reported on exit.
+#[derive(Debug, Default)]
+struct Stats {
+    sent: u64,
+    errors: u64,
+    bus_time: Duration,
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+
+    if let Err(e) = validate(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+
+    let mut pipe = match FrameClient::open_write_only(&args.channel).await {
+        Ok(pipe) => pipe,
+        Err(e) => {
+            eprintln!(
+                "Unable to connect to {}: {}. Is a server running?",
+                args.channel, e
+            );
+            process::exit(1);
+        }
+    };
+
+    let mut stats = Stats::default();
+    let start = Instant::now();
+    let result = tokio::select! {
+        result = generate(&args, &mut pipe, &mut stats) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let load = if elapsed > 0.0 {
+        stats.bus_time.as_secs_f64() / elapsed * 100.0
+    } else {
+        0.0
+    };
+    eprintln!(
+        "{} frames sent in {:.3} s ({:.1} frames/s, ~{:.1}% load on a {} bit/s bus), {} pipe write errors",
+        stats.sent,
+        elapsed,
+        stats.sent as f64 / elapsed.max(f64::EPSILON),
+        load,
+        args.bitrate,
+        stats.errors
+    );
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn validate(args: &Args) -> Result<(), String> {
+    if let Mode::Fixed(id) = args.id_mode {
+        if !args.extended && !args.mix && id > 0x7FF {
+            return Err(format!("id {:X} needs extended frames (-e)", id));
+        }
+        if id > 0x1FFF_FFFF {
+            return Err(format!("id {:X} exceeds 29 bits", id));
+        }
+    }
+    let max_len = if args.fd || args.mix { 64 } else { 8 };
+    if let Mode::Fixed(len) = args.len_mode
+        && len as usize > max_len
+    {
+        return Err(format!("length {} exceeds {} bytes", len, max_len));
+    }
+    if let DataMode::Fixed(data) = &args.data_mode
+        && data.len() > max_len
+    {
+        return Err(format!(
+            "{} data bytes exceed {} bytes",
+            data.len(),
+            max_len
+        ));
+    }
+    if args.rtr && args.fd {
+        return Err("CAN FD has no remote frames".to_string());
+    }
+    if !args.gap_ms.is_finite() || args.gap_ms < 0.0 {
+        return Err(format!("invalid gap {}", args.gap_ms));
+    }
+    if let Some(max) = args.max_gap_ms
+        && (!max.is_finite() || max < args.gap_ms)
+    {
+        return Err(format!("max gap {} is below the gap {}", max, args.gap_ms));
+    }
+    if let Some(load) = args.busload
+        && !(load > 0.0 && load <= 100.0)
+    {
+        return Err(format!("bus load {} must be within (0, 100]", load));
+    }
+    if args.bitrate == 0 || args.data_bitrate == Some(0) {
+        return Err("bitrates must be positive".to_string());
+    }
+    if args.burst == 0 {
+        return Err("burst count must be positive".to_string());
+    }
+    Ok(())
+}
+
+async fn generate(args: &Args, pipe: &mut FrameClient, stats: &mut Stats) -> Result<(), String> {
+    let mut generator = Generator::new(args);
+    let start = Instant::now();
+    let mut next = start;
+
+    loop {
+        let frame = generator.next_frame()?;
+        let duration =
+            frame_bits(&frame, Stuffing::WorstCase).duration(args.bitrate, args.data_bitrate);
+        let line = args.verbose.then(|| format_frame(&frame));
+
+        match pipe.write_frame(&frame).await {
+            Ok(()) => {
+                stats.sent += 1;
+                stats.bus_time += duration;
+                if let Some(line) = line {
+                    println!("  {}  {}", args.channel, line);
+                }
+            }
+            Err(e) if args.ignore_errors => {
+                stats.errors += 1;
+                log_error(stats.errors, &e);
+            }
+            Err(e) => {
+                stats.errors += 1;
+                return Err(format!("Error writing to {}: {}", args.channel, e));
+            }
+        }
+
+        let attempts = stats.sent + stats.errors;
+        if args.count.is_some_and(|count| attempts >= count) {
+            return Ok(());
+        }
+        if let Some(load) = args.busload {
+            // Pace by the bus time used so far rather than per frame, so
+            // timer granularity does not accumulate.
+            next = start + stats.bus_time.mul_f64(100.0 / load);
+        } else if attempts.is_multiple_of(args.burst as u64) {
+            next += generator.gap();
+            // Do not try to catch up on gaps missed while the pipe was busy.
+            next = next.max(Instant::now());
+        } else {
+            continue;
+        }
+        sleep_until(next).await;
+    }
+}
+
+/// Report pipe write errors without flooding the console.
+fn log_error(count: u64, e: &std::io::Error) {
+    if count.is_power_of_two() {
+        eprintln!("Pipe write error #{}: {}", count, e);
+    }
+}
+
+/// Produces frames as configured by [`Args`].
+struct Generator<'a> {
+    args: &'a Args,
+    rng: u64,
+    id: u32,
+    len: usize,
+    data: u64,
+}
+
+impl<'a> Generator<'a> {
+    fn new(args: &'a Args) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self {
+            args,
+            rng: seed | 1,
+            id: 0,
+            len: 0,
+            data: 0,
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn gap(&mut self) -> Duration {
+        let ms = match self.args.max_gap_ms {
+            Some(max) => {
+                let unit = (self.next_random() >> 11) as f64 / (1u64 << 53) as f64;
+                self.args.gap_ms + (max - self.args.gap_ms) * unit
+            }
+            None => self.args.gap_ms,
+        };
+        Duration::from_secs_f64(ms / 1000.0)
+    }
+
+    fn next_frame(&mut self) -> Result<CanFrame, String> {
+        let args = self.args;
+        let (extended, fd, rtr) = if args.mix {
+            let bits = self.next_random();
+            let fd = bits & 2 != 0;
+            (bits & 1 != 0, fd, !fd && bits & 4 != 0)
+        } else {
+            (args.extended, args.fd, args.rtr)
+        };
+        // A fixed id too long for a standard frame keeps mixed frames extended.
+        let extended = extended || matches!(args.id_mode, Mode::Fixed(id) if id > 0x7FF);
+
+        let id_mask: u32 = if extended { 0x1FFF_FFFF } else { 0x7FF };
+        let id = match args.id_mode {
+            Mode::Random => self.next_random() as u32 & id_mask,
+            Mode::Increment => {
+                let id = self.id & id_mask;
+                self.id = self.id.wrapping_add(1);
+                id
+            }
+            Mode::Fixed(id) => id & id_mask,
+        };
+
+        let max_dlc = if fd { 15 } else { 8 };
+        let len = match (&args.data_mode, args.len_mode) {
+            (DataMode::Fixed(data), _) if !rtr => data.len(),
+            (_, Mode::Random) => dlc_to_len(self.next_random() as usize % (max_dlc + 1), fd),
+            (_, Mode::Increment) => {
+                let len = dlc_to_len(self.len % (max_dlc + 1), fd);
+                self.len += 1;
+                len
+            }
+            (_, Mode::Fixed(len)) => len as usize,
+        };
+        // A fixed length may come from a mixed in CAN FD setting.
+        let len = if fd {
+            CANFD_LENGTHS.into_iter().find(|&l| l >= len).unwrap_or(64)
+        } else {
+            len.min(8)
+        };
+
+        if rtr {
+            return build_frame(id, extended, Some(len), &[]);
+        }
+
+        let mut data = match &args.data_mode {
+            DataMode::Fixed(data) => data.clone(),
+            DataMode::Random => (0..len).map(|_| self.next_random() as u8).collect(),
+            DataMode::Increment => {
+                let mut data = vec![0; len];
+                let counter = self.data.to_le_bytes();
+                let n = len.min(counter.len());
+                data[..n].copy_from_slice(&counter[..n]);
+                self.data = self.data.wrapping_add(1);
+                data
+            }
+        };
+        // Pad CAN FD payloads up to a valid length.
+        data.resize(len, 0);
+        build_frame(id, extended, None, &data)
+    }
+}
+
+fn dlc_to_len(dlc: usize, fd: bool) -> usize {
+    if fd { CANFD_LENGTHS[dlc] } else { dlc.min(8) }
+}
+
+fn parse_id_mode(s: &str) -> Result<Mode, String> {
+    match s {
+        "r" => Ok(Mode::Random),
+        "i" => Ok(Mode::Increment),
+        _ => u32::from_str_radix(s, 16)
+            .map(Mode::Fixed)
+            .map_err(|_| format!("invalid id mode '{}'", s)),
+    }
+}
+
+fn parse_len_mode(s: &str) -> Result<Mode, String> {
+    match s {
+        "r" => Ok(Mode::Random),
+        "i" => Ok(Mode::Increment),
+        _ => s
+            .parse::<u32>()
+            .map(Mode::Fixed)
+            .map_err(|_| format!("invalid length mode '{}'", s)),
+    }
+}
+
+fn parse_data_mode(s: &str) -> Result<DataMode, String> {
+    match s {
+        "r" => return Ok(DataMode::Random),
+        "i" => return Ok(DataMode::Increment),
+        _ => {}
+    }
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("invalid data mode '{}'", s));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map(DataMode::Fixed)
+        .map_err(|_| format!("invalid data mode '{}'", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(options: &[&str]) -> Args {
+        let mut argv = vec!["cangen", "can0"];
+        argv.extend(options);
+        Args::try_parse_from(argv).unwrap()
+    }
+
+    fn args(options: &[&str]) -> Args {
+        let args = parse(options);
+        validate(&args).unwrap();
+        args
+    }
+
+    fn frames(args: &Args, n: usize) -> Vec<CanFrame> {
+        let mut generator = Generator::new(args);
+        (0..n).map(|_| generator.next_frame().unwrap()).collect()
+    }
+
+    #[test]
+    fn long_fixed_ids_need_extended_frames() {
+        assert!(validate(&parse(&["-I", "800"])).is_err());
+        assert!(validate(&parse(&["-I", "20000000", "-e"])).is_err());
+
+        for args in [args(&["-I", "800", "-e"]), args(&["-I", "800", "-m"])] {
+            for frame in frames(&args, 50) {
+                assert!(frame.is_extended());
+                assert_eq!(frame.id(), 0x800);
+            }
+        }
+
+        // Short ids still mix both lengths.
+        let mixed = frames(&args(&["-I", "123", "-m"]), 200);
+        assert!(mixed.iter().all(|frame| frame.id() == 0x123));
+        assert!(mixed.iter().any(|frame| frame.is_extended()));
+        assert!(mixed.iter().any(|frame| !frame.is_extended()));
+    }
+
+    #[test]
+    fn increments_ids_lengths_and_data() {
+        let frames = frames(&args(&["-I", "i", "-L", "i", "-D", "i"]), 12);
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(frame.id(), i as u32);
+            assert_eq!(frame.data().len(), i % 9);
+            if let Some(&first) = frame.data().first() {
+                assert_eq!(first, i as u8);
+            }
+        }
+    }
+
+    #[test]
+    fn fd_frames_have_valid_lengths() {
+        for frame in frames(&args(&["-f"]), 200) {
+            assert!(CANFD_LENGTHS.contains(&frame.data().len()));
+        }
+        // A fixed length rounds up to the next valid one.
+        for frame in frames(&args(&["-f", "-L", "10"]), 5) {
+            assert_eq!(frame.data().len(), 12);
+        }
+    }
+
+    #[test]
+    fn fixed_data_sets_the_length_and_remote_frames_have_none() {
+        for frame in frames(&args(&["-D", "DEADBEEF"]), 5) {
+            assert_eq!(frame.data(), [0xDE, 0xAD, 0xBE, 0xEF]);
+        }
+        for frame in frames(&args(&["-R", "-L", "4"]), 5) {
+            assert!(frame.is_rtr());
+            assert!(frame.data().is_empty());
+        }
+    }
+
+    #[test]
+    fn random_gaps_stay_within_range() {
+        let args = args(&["-g", "5", "--max-gap", "10"]);
+        let mut generator = Generator::new(&args);
+        for _ in 0..100 {
+            let gap = generator.gap();
+            assert!(gap >= Duration::from_millis(5) && gap <= Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use clap::Parser;
+use crosscan::can::CanFrame;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::time::{Duration, sleep};
+use win_can_utils::can_log::format_line;
+use win_can_utils::drivers::timestamp_us;
+use win_can_utils::filter::FilterSet;
+use win_can_utils::thread_manager_async::FrameClient;
+
+/// Stream CAN traffic as candump log lines to TCP clients, like can-utils'
+/// `canlogserver`.
+///
+/// Each client receives `(timestamp) ifname ID#DATA` lines. A client may send
+/// lines in the candump interface grammar (`<ifname>[,filter]*`, with `any`
+/// matching every channel) to restrict what it receives; until it does, it
+/// receives every channel unfiltered.
+#[derive(Debug, Parser)]
+#[command(name = "canlogserver")]
+struct Args {
+    /// TCP port to listen on
+    #[arg(short = 'p', long = "port", default_value_t = 28700)]
+    port: u16,
+
+    /// Address to listen on
+    #[arg(short = 'a', long = "address", default_value = "0.0.0.0")]
+    address: String,
+
+    /// CAN channels with optional filter sets applied for all clients: <ifname>[,filter]*
+    #[arg(required = true, value_name = "IF[,FILTER]*", num_args = 1..)]
+    interfaces: Vec<String>,
+}
+
+/// A frame received on one of the served channels.
+#[derive(Debug, Clone)]
+struct Received {
+    ifname: Arc<str>,
+    timestamp_us: u64,
+    frame: CanFrame,
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+
+    let mut channels = Vec::new();
+    for spec in &args.interfaces {
+        match FilterSet::parse_spec(spec) {
+            Ok(channel) => channels.push(channel),
+            Err(e) => {
+                eprintln!("error parsing interface spec '{}': {}", spec, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let listener = TcpListener::bind((args.address.as_str(), args.port)).await?;
+    println!("canlogserver listening on {}", listener.local_addr()?);
+
+    let (tx, _) = broadcast::channel(4096);
+    for (ifname, filters) in channels {
+        tokio::spawn(read_channel(ifname, filters, tx.clone()));
+    }
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        println!("Client {} connected", peer);
+        let rx = tx.subscribe();
+        tokio::spawn(async move {
+            if let Err(e) = serve_client(stream, rx).await {
+                eprintln!("Client {}: {}", peer, e);
+            }
+            println!("Client {} disconnected", peer);
+        });
+    }
+}
+
+/// Forward frames from one channel to all clients, reconnecting if its server restarts.
+async fn read_channel(ifname: String, filters: FilterSet, tx: broadcast::Sender<Received>) {
+    let name: Arc<str> = Arc::from(ifname.as_str());
+    loop {
+        let mut pipe = connect_pipe_retry(&ifname).await;
+        loop {
+            match pipe.read_frame().await {
+                Ok(frame) => {
+                    if !filters.matches(&frame) {
+                        continue;
+                    }
+                    // No clients connected is not an error.
+                    let _ = tx.send(Received {
+                        ifname: name.clone(),
+                        timestamp_us: timestamp_us(),
+                        frame,
+                    });
+                }
+                Err(e) => {
+                    eprintln!("Error reading from {}: {:?}", ifname, e);
+                    break;
+                }
+            }
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+async fn serve_client(
+    stream: TcpStream,
+    mut rx: broadcast::Receiver<Received>,
+) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    // Per channel filters requested by the client; None streams everything.
+    let mut selection: Option<Vec<(String, FilterSet)>> = None;
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+                let spec = line.trim();
+                if spec.is_empty() {
+                    continue;
+                }
+                match FilterSet::parse_spec(spec) {
+                    Ok((ifname, filters)) => {
+                        let selection = selection.get_or_insert_with(Vec::new);
+                        selection.retain(|(name, _)| *name != ifname);
+                        selection.push((ifname, filters));
+                    }
+                    Err(e) => {
+                        writer.write_all(format!("# {}\n", e).as_bytes()).await?;
+                    }
+                }
+            }
+            received = rx.recv() => match received {
+                Ok(received) => {
+                    if !selected(&selection, &received) {
+                        continue;
+                    }
+                    let mut line = format_line(received.timestamp_us, &received.ifname, &received.frame);
+                    line.push('\n');
+                    writer.write_all(line.as_bytes()).await?;
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    eprintln!("Client too slow, {} frames dropped", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+fn selected(selection: &Option<Vec<(String, FilterSet)>>, received: &Received) -> bool {
+    let Some(selection) = selection else {
+        return true;
+    };
+    selection.iter().any(|(ifname, filters)| {
+        (ifname == "any" || **ifname == *received.ifname) && filters.matches(&received.frame)
+    })
+}
+
+async fn connect_pipe_retry(channel: &str) -> FrameClient {
+    println!("Attempting to connect to {} server", channel);
+
+    loop {
+        match FrameClient::open_read_only(channel).await {
+            Ok(pipe) => {
+                println!("Connected to {} server", channel);
+                return pipe;
+            }
+            Err(_) => {
+                sleep(Duration::from_millis(500)).await;
+            }
+        };
+    }
+}
@@ -0,0 +1,348 @@
+use clap::Parser;
+use std::collections::HashMap;
+use std::process;
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::time::{Duration, Instant, sleep_until};
+use win_can_utils::can_log::{LogEntry, format_line, parse_line};
+use win_can_utils::thread_manager_async::FrameClient;
+
+/// Replay candump log files onto CAN channels, like can-utils' `canplayer`.
+///
+/// Frames are written to the channel named in the log unless interface
+/// assignments are given, in which case only the assigned interfaces are
+/// replayed.
+#[derive(Debug, Parser)]
+#[command(name = "canplayer")]
+struct Args {
+    /// log file to replay (default: stdin)
+    #[arg(short = 'I', value_name = "infile")]
+    infile: Option<String>,
+
+    /// play the log <num> times, or 'i' for infinite loops
+    #[arg(short = 'l', value_name = "num", default_value = "1", value_parser = parse_loops)]
+    loops: Loops,
+
+    /// ignore timestamps: send frames immediately, separated by the -g gap
+    #[arg(short = 't')]
+    ignore_timestamps: bool,
+
+    /// gap in milli seconds between frames when ignoring timestamps
+    #[arg(short = 'g', value_name = "ms", default_value_t = 1)]
+    gap_ms: u64,
+
+    /// skip gaps in the timestamps longer than <s> seconds
+    #[arg(short = 's', value_name = "s")]
+    skip_gaps: Option<u64>,
+
+    /// replay speed factor, e.g. 2 plays twice as fast
+    #[arg(long = "speed", value_name = "factor", default_value_t = 1.0, value_parser = parse_speed)]
+    speed: f64,
+
+    /// print the frames as they are sent
+    #[arg(short = 'v')]
+    verbose: bool,
+
+    /// interface assignments: <write-if>=<log-if>, e.g. can1=can0
+    #[arg(value_name = "WRITE-IF=LOG-IF", value_parser = parse_assignment)]
+    assignments: Vec<(String, String)>,
+}
+
+/// How often to play the log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Loops {
+    Count(u64),
+    Infinite,
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+
+    if args.infile.is_none() && args.loops != Loops::Count(1) {
+        eprintln!("looping needs a log file (-I), stdin can only be read once");
+        process::exit(1);
+    }
+
+    // Logged interface name to the channel its frames are written to.
+    let targets: HashMap<String, String> = args
+        .assignments
+        .iter()
+        .map(|(write_if, log_if)| (log_if.clone(), write_if.clone()))
+        .collect();
+    let mut player = Player {
+        targets,
+        pipes: HashMap::new(),
+        verbose: args.verbose,
+    };
+
+    let mut played = 0;
+    while match args.loops {
+        Loops::Count(loops) => played < loops,
+        Loops::Infinite => true,
+    } {
+        let reader: Box<dyn AsyncBufRead + Unpin> = match &args.infile {
+            Some(path) => Box::new(BufReader::new(File::open(path).await?)),
+            None => Box::new(BufReader::new(tokio::io::stdin())),
+        };
+        if let Err(e) = play(reader, &args, &mut player).await {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        played += 1;
+    }
+
+    Ok(())
+}
+
+/// Where frames go and the pipes opened so far.
+struct Player {
+    targets: HashMap<String, String>,
+    pipes: HashMap<String, FrameClient>,
+    verbose: bool,
+}
+
+impl Player {
+    async fn send(&mut self, entry: LogEntry) -> Result<(), String> {
+        let channel = if self.targets.is_empty() {
+            entry.ifname.clone()
+        } else {
+            match self.targets.get(&entry.ifname) {
+                Some(channel) => channel.clone(),
+                None => return Ok(()),
+            }
+        };
+
+        if !self.pipes.contains_key(&channel) {
+            let pipe = FrameClient::open_write_only(&channel).await.map_err(|e| {
+                format!(
+                    "Unable to connect to {}: {}. Is a server running?",
+                    channel, e
+                )
+            })?;
+            self.pipes.insert(channel.clone(), pipe);
+        }
+        let pipe = self.pipes.get_mut(&channel).unwrap();
+
+        let line = self
+            .verbose
+            .then(|| format_line(entry.timestamp_us, &channel, &entry.frame));
+        pipe.write_frame(&entry.frame)
+            .await
+            .map_err(|e| format!("Error writing to {}: {}", channel, e))?;
+        if let Some(line) = line {
+            println!("{}", line);
+        }
+        Ok(())
+    }
+}
+
+/// When to send a frame, on a playback clock started at the first frame.
+#[derive(Debug, PartialEq)]
+struct Step {
+    /// Start the clock again at this frame.
+    restart: bool,
+    /// Time from the clock's start until the frame is due.
+    offset: Duration,
+}
+
+/// Turns log timestamps into send times.
+struct Schedule {
+    ignore_timestamps: bool,
+    gap: Duration,
+    skip_gap_us: Option<u64>,
+    speed: f64,
+    /// Log time the clock was started at.
+    base_us: Option<u64>,
+    last_us: u64,
+}
+
+impl Schedule {
+    fn new(args: &Args) -> Self {
+        Self {
+            ignore_timestamps: args.ignore_timestamps,
+            gap: Duration::from_millis(args.gap_ms),
+            skip_gap_us: args.skip_gaps.map(|s| s * 1_000_000),
+            speed: args.speed,
+            base_us: None,
+            last_us: 0,
+        }
+    }
+
+    fn next(&mut self, ts: u64) -> Step {
+        if self.ignore_timestamps {
+            // Each frame follows the previous one by the gap.
+            let first = self.base_us.replace(0).is_none();
+            return Step {
+                restart: true,
+                offset: if first { Duration::ZERO } else { self.gap },
+            };
+        }
+
+        let skipped = self
+            .skip_gap_us
+            .is_some_and(|gap| ts.saturating_sub(self.last_us) > gap);
+        // Restart on the first frame, a skipped gap or a timestamp going backwards.
+        let restart = self.base_us.is_none() || skipped || ts < self.last_us;
+        if restart {
+            self.base_us = Some(ts);
+        }
+        let base_us = self.base_us.unwrap_or(ts);
+        self.last_us = ts;
+        Step {
+            restart,
+            offset: Duration::from_micros(ts - base_us).div_f64(self.speed),
+        }
+    }
+}
+
+/// Replay one pass over the log.
+async fn play(
+    reader: Box<dyn AsyncBufRead + Unpin>,
+    args: &Args,
+    player: &mut Player,
+) -> Result<(), String> {
+    let mut schedule = Schedule::new(args);
+    let mut start = Instant::now();
+
+    let mut lines = reader.lines();
+    let mut line_no = 0;
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .map_err(|e| format!("Error reading log: {}", e))?
+    {
+        line_no += 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = parse_line(line).map_err(|e| format!("line {}: {}", line_no, e))?;
+
+        let step = schedule.next(entry.timestamp_us);
+        if step.restart {
+            start = Instant::now();
+        }
+        sleep_until(start + step.offset).await;
+
+        player.send(entry).await?;
+    }
+    Ok(())
+}
+
+fn parse_loops(s: &str) -> Result<Loops, String> {
+    if s == "i" {
+        return Ok(Loops::Infinite);
+    }
+    match s.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(Loops::Count(n)),
+        _ => Err(format!("invalid loop count '{}'", s)),
+    }
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|speed| speed.is_finite() && *speed > 0.0)
+        .ok_or_else(|| format!("invalid speed factor '{}'", s))
+}
+
+fn parse_assignment(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((write_if, log_if)) if !write_if.is_empty() && !log_if.is_empty() => {
+            Ok((write_if.to_string(), log_if.to_string()))
+        }
+        _ => Err(format!("expected <write-if>=<log-if>, got '{}'", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Offsets and restarts scheduled for log timestamps in milliseconds.
+    fn schedule(options: &[&str], times_ms: &[u64]) -> Vec<(bool, u64)> {
+        let mut argv = vec!["canplayer"];
+        argv.extend(options);
+        let mut schedule = Schedule::new(&Args::try_parse_from(argv).unwrap());
+        times_ms
+            .iter()
+            .map(|ms| {
+                let step = schedule.next(ms * 1000);
+                (step.restart, step.offset.as_millis() as u64)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn follows_the_log_timestamps() {
+        assert_eq!(
+            schedule(&[], &[1000, 1000, 1250, 4000]),
+            [(true, 0), (false, 0), (false, 250), (false, 3000)]
+        );
+    }
+
+    #[test]
+    fn speed_factor_scales_the_offsets() {
+        assert_eq!(
+            schedule(&["--speed", "2"], &[0, 1000, 3000]),
+            [(true, 0), (false, 500), (false, 1500)]
+        );
+        assert_eq!(
+            schedule(&["--speed", "0.5"], &[0, 1000]),
+            [(true, 0), (false, 2000)]
+        );
+    }
+
+    #[test]
+    fn long_gaps_are_skipped() {
+        assert_eq!(
+            schedule(&["-s", "1"], &[0, 500, 1500, 10_000, 10_200]),
+            [
+                (true, 0),
+                (false, 500),
+                (false, 1500),
+                (true, 0),
+                (false, 200)
+            ]
+        );
+        // Without -s the gap is waited out.
+        assert_eq!(schedule(&[], &[0, 10_000]), [(true, 0), (false, 10_000)]);
+    }
+
+    #[test]
+    fn timestamps_going_backwards_restart_the_schedule() {
+        assert_eq!(
+            schedule(&[], &[5000, 6000, 2000, 3000]),
+            [(true, 0), (false, 1000), (true, 0), (false, 1000)]
+        );
+    }
+
+    #[test]
+    fn ignoring_timestamps_spaces_frames_by_the_gap() {
+        assert_eq!(
+            schedule(&["-t", "-g", "20"], &[5000, 0, 9000]),
+            [(true, 0), (true, 20), (true, 20)]
+        );
+    }
+
+    #[test]
+    fn parses_loop_counts() {
+        assert_eq!(parse_loops("i"), Ok(Loops::Infinite));
+        assert_eq!(parse_loops("3"), Ok(Loops::Count(3)));
+        for s in ["0", "-1", "x", ""] {
+            assert!(parse_loops(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parses_interface_assignments() {
+        assert_eq!(
+            parse_assignment("can1=can0"),
+            Ok(("can1".to_string(), "can0".to_string()))
+        );
+        for s in ["can1", "=can0", "can1=", "="] {
+            assert!(parse_assignment(s).is_err(), "{}", s);
+        }
+    }
+}
@@ -1,50 +1,217 @@
 use clap::Parser;
-use crosscan::{CanInterface, can::CanFrame, win_can::WindowsCan};
+use crosscan::can::CanFrame;
+use std::collections::HashSet;
 use std::io;
 use std::process;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines, Stdin};
 use tokio::time::{Duration, sleep};
+use win_can_utils::bcm::{BcmClient, BcmRequest, MIN_CYCLIC_INTERVAL};
+use win_can_utils::can_log::parse_frame;
+use win_can_utils::thread_manager_async::FrameClient;
 
+/// Send CAN frames in the can-utils `cansend` syntax:
+///
+///   <can_id>#{data}            classic frame, e.g. 123#11.22.33 or 12345678#DEADBEEF
+///   <can_id>#{data}_{dlc}      classic frame of length 8 with a raw DLC of 9 to F
+///   <can_id>#R{len}            remote frame, e.g. 123#R or 123#R4
+///   <can_id>##<flags>{data}    CAN FD frame of 9 to 64 bytes, e.g. 123##100112233445566778899
+///
+/// <can_id> has 3 hex digits for standard ids, or 8 for extended ids and
+/// error frames.
 #[derive(Parser)]
+#[command(verbatim_doc_comment)]
 struct Args {
     /// CAN channel name, e.g. COM1 or COM4
     channel: String,
 
-    /// CAN frame to send, format: ID#DATA, e.g. 123#11223344 or 1ABCDEFC#11AA22
-    frame: String,
+    /// CAN frames to send; read one per line from stdin if none or '-' is given
+    #[arg(value_name = "FRAME")]
+    frames: Vec<String>,
+
+    /// send the frames cyclically, scheduled by the server, e.g. 100ms, 1s or 2.5ms;
+    /// frames read from stdin then replace the data of running ones
+    #[arg(long = "every", value_name = "interval", value_parser = parse_interval)]
+    every: Option<Duration>,
+
+    /// with --every, send each frame <count> times; the server finishes them after cansend exits
+    #[arg(long = "count", value_name = "count", requires = "every", value_parser = clap::value_parser!(u64).range(1..))]
+    count: Option<u64>,
 }
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
     let args = Args::parse();
 
+    // Parse all frames up front so a typo does not leave a partial sequence sent
+    let from_stdin = args.frames.is_empty() || args.frames == ["-"];
+    let frames = if from_stdin {
+        Vec::new()
+    } else {
+        match args
+            .frames
+            .iter()
+            .map(|f| parse_frame(f))
+            .collect::<Result<Vec<CanFrame>, _>>()
+        {
+            Ok(frames) => frames,
+            Err(e) => {
+                eprintln!("Failed to parse CAN frame: {}", e);
+                process::exit(1);
+            }
+        }
+    };
+
+    if let Some(interval) = args.every {
+        return send_cyclic(&args, frames, from_stdin, interval).await;
+    }
+
     // Attempt to open the named pipe (with retries)
     let max_attempts = 5;
     let mut pipe = match connect_pipe_retry(&args.channel, max_attempts).await {
         Some(p) => p,
         None => {
-            eprintln!("Failed to parse CAN frame");
+            eprintln!("Failed to connect to {}", args.channel);
             process::exit(1);
         }
     };
 
-    // Attempt to parse the CAN frame from the arg provided
-    let frame = match parse_cansend_frame(&args.frame) {
-        Some(f) => f,
-        None => {
-            eprintln!("Failed to parse CAN frame");
+    if !from_stdin {
+        for frame in frames {
+            pipe.write_frame(&frame).await?;
+        }
+        return Ok(());
+    }
+
+    let mut stdin = StdinFrames::new();
+    while let Some(frame) = stdin.next().await? {
+        pipe.write_frame(&frame).await?;
+    }
+    Ok(())
+}
+
+/// Have the server's broadcast manager send the frames every `interval`.
+/// Endless jobs end when this process exits and its connection closes.
+async fn send_cyclic(
+    args: &Args,
+    frames: Vec<CanFrame>,
+    from_stdin: bool,
+    interval: Duration,
+) -> io::Result<()> {
+    let mut client = match BcmClient::connect(&args.channel).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!(
+                "Failed to connect to the {} server's broadcast manager: {}",
+                args.channel, e
+            );
             process::exit(1);
         }
     };
 
-    pipe.write_frame(frame).await
+    let interval_us = interval.as_micros() as u64;
+    let mut running = HashSet::new();
+    for frame in frames {
+        running.insert((frame.id(), frame.is_extended()));
+        client
+            .request(&BcmRequest::TxSetup {
+                frame,
+                interval_us,
+                count: args.count,
+            })
+            .await?;
+    }
+
+    if from_stdin {
+        let mut stdin = StdinFrames::new();
+        while let Some(frame) = stdin.next().await? {
+            let request = if running.insert((frame.id(), frame.is_extended())) {
+                BcmRequest::TxSetup {
+                    frame,
+                    interval_us,
+                    count: args.count,
+                }
+            } else {
+                BcmRequest::TxUpdate { frame }
+            };
+            client.request(&request).await?;
+        }
+    }
+
+    // The server finishes counted jobs on its own; endless ones run until we leave.
+    if args.count.is_none() {
+        println!("Sending every {:?}, press Ctrl+C to stop", interval);
+        tokio::signal::ctrl_c().await?;
+    }
+    Ok(())
+}
+
+/// Frames read from stdin, one per line; exits on a malformed line.
+struct StdinFrames {
+    lines: Lines<BufReader<Stdin>>,
+    line_no: usize,
 }
 
-async fn connect_pipe_retry(channel: &str, max_attempts: i32) -> Option<WindowsCan> {
+impl StdinFrames {
+    fn new() -> Self {
+        Self {
+            lines: BufReader::new(tokio::io::stdin()).lines(),
+            line_no: 0,
+        }
+    }
+
+    async fn next(&mut self) -> io::Result<Option<CanFrame>> {
+        while let Some(line) = self.lines.next_line().await? {
+            self.line_no += 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match parse_frame(line) {
+                Ok(frame) => return Ok(Some(frame)),
+                Err(e) => {
+                    eprintln!("Failed to parse CAN frame on line {}: {}", self.line_no, e);
+                    process::exit(1);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Parse an interval such as `100ms`, `1s`, `1500us` or `2.5ms`; bare numbers are milliseconds.
+/// The server refuses periods below [`MIN_CYCLIC_INTERVAL`].
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let (number, scale) = if let Some(n) = s.strip_suffix("us") {
+        (n, 1e-6)
+    } else if let Some(n) = s.strip_suffix("ms") {
+        (n, 1e-3)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1.0)
+    } else {
+        (s, 1e-3)
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .map(|n| n * scale)
+        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| format!("invalid interval '{}'", s))
+        .and_then(|interval| {
+            if interval < MIN_CYCLIC_INTERVAL {
+                Err(format!("interval '{}' is below the minimum of 1ms", s))
+            } else {
+                Ok(interval)
+            }
+        })
+}
+
+async fn connect_pipe_retry(channel: &str, max_attempts: i32) -> Option<FrameClient> {
     println!("Attempting to connect to {} server", channel);
 
     let mut attempts = 0;
     loop {
-        match WindowsCan::open_write_only(channel) {
+        match FrameClient::open_write_only(channel).await {
             Ok(pipe) => {
                 println!("Connected to {} server", channel);
                 return Some(pipe);
@@ -62,43 +229,3 @@ async fn connect_pipe_retry(channel: &str, max_attempts: i32) -> Option<WindowsC
         };
     }
 }
-
-fn parse_cansend_frame(input: &str) -> Option<CanFrame> {
-    let parts: Vec<&str> = input.split('#').collect();
-    if parts.len() != 2 {
-        eprintln!("Invalid frame format, expected ID#DATA");
-        return None;
-    }
-
-    let id = u32::from_str_radix(parts[0], 16).ok()?;
-    let extended = id > 0x7FF;
-
-    let data_str = parts[1];
-    if data_str.len() % 2 != 0 {
-        eprintln!("Data length must be even hex digits");
-        return None;
-    }
-
-    let data: Option<Vec<u8>> = (0..data_str.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&data_str[i..i + 2], 16).ok())
-        .collect();
-
-    if extended {
-        match CanFrame::new_eff(id, data?.as_slice()) {
-            Ok(frame) => Some(frame),
-            Err(e) => {
-                eprintln!("Could create CAN frame: {}", e);
-                None
-            }
-        }
-    } else {
-        match CanFrame::new(id, data?.as_slice()) {
-            Ok(frame) => Some(frame),
-            Err(e) => {
-                eprintln!("Could create CAN frame: {}", e);
-                None
-            }
-        }
-    }
-}
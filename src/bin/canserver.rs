@@ -1,15 +1,33 @@
-use bincode;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use crosscan::can::CanFrame;
 use serialport::available_ports;
+use std::net::ToSocketAddrs;
 use std::path::Path;
 use std::process::exit;
 use std::sync::Arc;
+use tokio::net::TcpListener;
 use tokio::signal;
 use tokio::sync::Mutex;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio::time::Duration;
-use win_can_utils::{CanDriver, GsUsbDriver, PcanDriver, SlcanDriver, thread_manager_async};
+use tokio_serial::FlowControl;
+use win_can_utils::drivers::bitrate_scan::STANDARD_BITRATES;
+use win_can_utils::drivers::cannelloni::{CannelloniDriver, CannelloniOptions};
+use win_can_utils::drivers::elm327::{ELM327_BAUD_RATE, Elm327Driver};
+use win_can_utils::drivers::pcan::set_pcan_library_path;
+use win_can_utils::drivers::scan_bitrate;
+use win_can_utils::drivers::slcan::{SLCAN_PROBE_BAUD_RATES, SlcanSerialOptions};
+#[cfg(target_os = "linux")]
+use win_can_utils::drivers::socketcan::SocketCanDriver;
+use win_can_utils::drivers::socketcand::{SOCKETCAND_PORT, SocketcandDriver};
+use win_can_utils::drivers::vcan::{VirtualDriver, VirtualOptions};
+#[cfg(target_os = "linux")]
+use win_can_utils::filter::FilterSet;
+use win_can_utils::{
+    CanDriver, GsUsbDriver, PcanDriver, SlcanDriver, bcm, slcan_bridge, socketcand_server,
+    thread_manager_async,
+};
 
 /// Determine the next available IPC channel name by probing for an unused pipe.
 ///
@@ -20,11 +38,9 @@ fn next_auto_channel(base: &str) -> String {
     let mut idx = 0;
     loop {
         let candidate = format!("{}{}", base, idx);
-        let path = &format!(r"\\.\pipe\can_{}_in", candidate);
+        let path = thread_manager_async::pipe_path(&candidate, "in");
 
-        let pipe_path = Path::new(path);
-
-        if !pipe_path.exists() {
+        if !Path::new(&path).exists() {
             return candidate;
         }
 
@@ -34,17 +50,142 @@ fn next_auto_channel(base: &str) -> String {
 
 #[derive(Parser, Debug)]
 struct Cli {
-    /// Supported drivers: gsusb, pcan, slcan
+    /// Supported drivers: cannelloni, elm327, gsusb, pcan, slcan, socketcan (Linux), socketcand, virtual
     driver: String,
     /// Channel: use auto for auto-detect
     #[arg(short = 'c', long = "channel", default_value = "auto")]
     channel: String,
+    /// Bitrate
     #[arg(short = 'b', long = "bitrate")]
     bitrate: Option<u32>,
+    /// Detect the bitrate by listening at each standard bitrate in listen-only mode (gsusb, pcan, slcan)
+    #[arg(long = "auto-bitrate", conflicts_with = "bitrate")]
+    auto_bitrate: bool,
+    /// Time to listen at each bitrate with --auto-bitrate, in milliseconds
+    #[arg(long = "scan-ms", default_value_t = 500)]
+    scan_ms: u64,
+    /// CAN FD data phase bitrate (slcan, pcan)
+    #[arg(short = 'd', long = "data-bitrate")]
+    data_bitrate: Option<u32>,
+    /// PCAN-Basic FD bitrate string, e.g. "f_clock_mhz=80, nom_brp=1, ..." (pcan only)
+    #[arg(long = "fd-bitrate", conflicts_with_all = ["bitrate", "data_bitrate"])]
+    fd_bitrate: Option<String>,
+    /// Serial baud rate (slcan, elm327)
+    #[arg(long = "baud")]
+    baud: Option<u32>,
+    /// Probe common baud rates with the SLCAN version command (slcan only)
+    #[arg(long = "auto-baud", conflicts_with = "baud")]
+    auto_baud: bool,
+    /// Serial flow control (slcan only)
+    #[arg(long = "flow-control", value_enum, default_value = "none")]
+    flow_control: FlowControlArg,
+    /// Force the DTR line high (true) or low (false) after opening (slcan only)
+    #[arg(long = "dtr")]
+    dtr: Option<bool>,
+    /// Force the RTS line high (true) or low (false) after opening (slcan only)
+    #[arg(long = "rts")]
+    rts: Option<bool>,
+    /// Serial read buffer size in bytes (slcan only)
+    #[arg(long = "rx-buffer", default_value_t = 4096)]
+    read_buffer_size: usize,
+    /// Path to PCANBasic.dll / libpcanbasic.so; defaults to $PCANBASIC_PATH or the system library (pcan only)
+    #[arg(long = "pcan-lib")]
+    pcan_lib: Option<std::path::PathBuf>,
+    /// Exchange frames with virtual buses of the same name in other processes (virtual only)
+    #[arg(long = "shared")]
+    shared: bool,
+    /// Delay frames by their transmission time at the configured bitrate (virtual only)
+    #[arg(long = "simulate-timing")]
+    simulate_timing: bool,
+    /// Fail transmissions that no other node acknowledges (virtual only)
+    #[arg(long = "require-ack")]
+    require_ack: bool,
+    /// Probability of a bus error frame before each transmission, 0.0 to 1.0 (virtual only)
+    #[arg(long = "error-rate", default_value_t = 0.0)]
+    error_rate: f64,
+    /// Remote address: cannelloni peer (learned from incoming packets if omitted) or socketcand host[:port]
+    #[arg(long = "remote")]
+    remote: Option<String>,
+    /// Local UDP address to listen on (cannelloni only)
+    #[arg(long = "local", default_value = "0.0.0.0:20000")]
+    local: String,
+    /// Time frames may be held to batch them into one packet, in microseconds (cannelloni only)
+    #[arg(long = "batch-us", default_value_t = 1000)]
+    batch_us: u64,
+    /// Kernel receive filters, e.g. 123:7FF,200~700,#FFFFFFFF (socketcan only)
+    #[arg(long = "filter")]
+    filter: Option<String>,
+    /// Interface on the socketcand host (socketcand only)
+    #[arg(long = "remote-bus", default_value = "can0")]
+    remote_bus: String,
+    /// Also serve the channel over the socketcand protocol, e.g. 0.0.0.0:29536
+    #[arg(long = "socketcand")]
+    socketcand: Option<String>,
+    /// Also emulate an SLCAN adapter on a TCP port, e.g. 127.0.0.1:3333
+    #[arg(long = "slcan-tcp")]
+    slcan_tcp: Option<String>,
+    /// Also emulate an SLCAN adapter on a pseudo-terminal (Linux/macOS)
+    #[arg(long = "slcan-pty")]
+    slcan_pty: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FlowControlArg {
+    None,
+    Software,
+    Hardware,
+}
+
+impl From<FlowControlArg> for FlowControl {
+    fn from(arg: FlowControlArg) -> Self {
+        match arg {
+            FlowControlArg::None => FlowControl::None,
+            FlowControlArg::Software => FlowControl::Software,
+            FlowControlArg::Hardware => FlowControl::Hardware,
+        }
+    }
+}
+
+/// The bitrate given with -b, or the one detected when --auto-bitrate is set.
+async fn pick_bitrate(cli: &Cli, driver: &mut dyn CanDriver) -> std::io::Result<u32> {
+    match cli.bitrate {
+        Some(b) => Ok(b),
+        None if cli.auto_bitrate => detect_bitrate(cli, driver).await,
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "No bitrate provided. Use -b <bitrate>, or --auto-bitrate to detect it on an active bus.",
+        )),
+    }
+}
+
+/// Detect the bus bitrate by listening at each standard bitrate in turn.
+async fn detect_bitrate(cli: &Cli, driver: &mut dyn CanDriver) -> std::io::Result<u32> {
+    println!("Scanning for the bitrate in listen-only mode...");
+
+    match scan_bitrate(
+        driver,
+        &STANDARD_BITRATES,
+        Duration::from_millis(cli.scan_ms),
+    )
+    .await?
+    {
+        Some(b) => {
+            println!("Detected bitrate: {}", b);
+            Ok(b)
+        }
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "No traffic received at any standard bitrate. Specify one with -b <bitrate>.",
+        )),
+    }
 }
 
 /// Initialize PCAN driver from CLI args.
 async fn init_pcan(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
+    if let Some(path) = &cli.pcan_lib {
+        set_pcan_library_path(path)?;
+    }
+
     // Try to open the PCAN channel (e.g., "USBBUS1")
     let mut pcan_driver = if cli.channel.to_ascii_uppercase() == "AUTO" {
         // Try common PCAN channels in order
@@ -52,8 +193,26 @@ async fn init_pcan(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
             "USBBUS1", "USBBUS2", "USBBUS3", "USBBUS4", "PCIBUS1", "PCIBUS2", "LANBUS1", "LANBUS2",
         ];
 
+        // Prefer the driver's list of attached, unoccupied channels when available.
+        let attached: Vec<String> = match PcanDriver::attached_channels() {
+            Ok(channels) => channels
+                .into_iter()
+                .filter(|c| c.available && !c.occupied)
+                .map(|c| c.name)
+                .collect(),
+            Err(e) => {
+                log::debug!("PCAN_ATTACHED_CHANNELS unavailable: {}", e);
+                Vec::new()
+            }
+        };
+        let candidates: Vec<&str> = if attached.is_empty() {
+            common_channels.to_vec()
+        } else {
+            attached.iter().map(String::as_str).collect()
+        };
+
         let mut found: Option<PcanDriver> = None;
-        for ch in &common_channels {
+        for ch in &candidates {
             if let Ok(driver) = PcanDriver::open(ch).await {
                 // Try to actually initialize the hardware (set bitrate later)
                 found = Some(driver);
@@ -90,27 +249,59 @@ async fn init_pcan(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
     // Close channel if left open (same pattern as SLCAN)
     let _ = pcan_driver.close_channel().await;
 
-    // Pick bitrate from CLI or hardware
-    let bitrate = match cli.bitrate {
-        Some(b) => b,
-        None => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "No bitrate provided and failed to detect automatically. Use -b <bitrate>.",
-            ));
-        }
-    };
-
     println!("PCAN Connected on {}", &cli.channel);
+    if let (Ok(name), Ok(firmware)) = (pcan_driver.hardware_name(), pcan_driver.firmware_version())
+    {
+        println!("PCAN hardware: {} (firmware {})", name, firmware);
+    }
 
-    pcan_driver.set_bitrate(bitrate).await?;
+    if let Some(bitrate_fd) = &cli.fd_bitrate {
+        pcan_driver.set_fd_bitrate_string(bitrate_fd)?;
+    } else {
+        let bitrate = pick_bitrate(cli, &mut pcan_driver).await?;
+
+        pcan_driver.set_bitrate(bitrate).await?;
+        if let Some(data_bitrate) = cli.data_bitrate {
+            pcan_driver.set_data_bitrate(data_bitrate)?;
+        }
+    }
     pcan_driver.enable_timestamp().await?;
     pcan_driver.open_channel().await?;
 
     Ok(Box::new(pcan_driver))
 }
 
+/// Build the serial port settings for SLCAN adapters from CLI args.
+fn slcan_serial_options(cli: &Cli) -> SlcanSerialOptions {
+    let defaults = SlcanSerialOptions::default();
+    SlcanSerialOptions {
+        baud_rate: cli.baud.unwrap_or(defaults.baud_rate),
+        flow_control: cli.flow_control.into(),
+        dtr: cli.dtr,
+        rts: cli.rts,
+        read_buffer_size: cli.read_buffer_size,
+    }
+}
+
+/// Open an SLCAN serial port, probing the baud rate when `--auto-baud` is given.
+async fn open_slcan_port(
+    cli: &Cli,
+    options: &SlcanSerialOptions,
+    port_name: &str,
+) -> std::io::Result<SlcanDriver> {
+    if cli.auto_baud {
+        let (driver, baud_rate) =
+            SlcanDriver::open_auto_baud(port_name, options, &SLCAN_PROBE_BAUD_RATES).await?;
+        println!("Detected SLCAN baud rate {} on {}", baud_rate, port_name);
+        Ok(driver)
+    } else {
+        SlcanDriver::open_with_options(port_name, options).await
+    }
+}
+
 async fn init_slcan(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
+    let serial_options = slcan_serial_options(cli);
+
     let mut slcan_driver = if cli.channel.to_ascii_lowercase() == "auto" {
         // Enumerate all serial ports on the system
         let ports = available_ports().map_err(|e| {
@@ -132,7 +323,7 @@ async fn init_slcan(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
             let port_name = p.port_name;
             println!("Trying SLCAN auto-detect on {}", port_name);
 
-            if let Ok(mut driver) = SlcanDriver::open(&port_name).await {
+            if let Ok(mut driver) = open_slcan_port(cli, &serial_options, &port_name).await {
                 if driver.get_version().await.is_err() {
                     continue;
                 }
@@ -153,7 +344,7 @@ async fn init_slcan(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
         }
     } else {
         // User provided a channel manually
-        match SlcanDriver::open(&cli.channel).await {
+        match open_slcan_port(cli, &serial_options, &cli.channel).await {
             Ok(d) => d,
             Err(_) => {
                 return Err(std::io::Error::new(
@@ -182,6 +373,7 @@ async fn init_slcan(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
 
     let bitrate = match cli.bitrate {
         Some(b) => b,
+        None if cli.auto_bitrate => detect_bitrate(cli, &mut slcan_driver).await?,
         None => {
             let is_cyder_fw = firmware_version.starts_with("CYDER-CANABLE");
             if is_cyder_fw {
@@ -200,7 +392,7 @@ async fn init_slcan(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
             } else {
                 return Err(std::io::Error::new(
                     std::io::ErrorKind::Other,
-                    "No bitrate provided. Use the bitrate flag -b or upgrade to Cyder-Canable firmware for auto-bitrate detection.",
+                    "No bitrate provided. Use -b <bitrate>, --auto-bitrate to detect it on an active bus, or upgrade to Cyder-Canable firmware for auto-bitrate detection.",
                 ));
             }
         }
@@ -209,6 +401,9 @@ async fn init_slcan(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
     println!("SLCan Connected. FW Version: {}", firmware_version);
 
     slcan_driver.set_bitrate(bitrate).await?;
+    if let Some(data_bitrate) = cli.data_bitrate {
+        slcan_driver.set_data_bitrate(data_bitrate).await?;
+    }
     slcan_driver.enable_timestamp().await?;
     slcan_driver.open_channel().await?;
 
@@ -220,12 +415,7 @@ async fn init_gsusb(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
 
     driver.close_channel().await?;
 
-    let bitrate = cli.bitrate.ok_or_else(|| {
-        std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "No bitrate provided. Specify one with -b <bitrate> for gs_usb devices.",
-        )
-    })?;
+    let bitrate = pick_bitrate(cli, &mut driver).await?;
 
     println!("gs_usb connected to {}", driver.device_label());
 
@@ -236,16 +426,173 @@ async fn init_gsusb(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
     Ok(Box::new(driver))
 }
 
+/// Initialize a virtual bus from CLI args.
+async fn init_virtual(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
+    let bus = if cli.channel.to_ascii_lowercase() == "auto" {
+        "vcan0"
+    } else {
+        cli.channel.as_str()
+    };
+    let options = VirtualOptions {
+        shared: cli.shared,
+        simulate_timing: cli.simulate_timing,
+        require_ack: cli.require_ack,
+        error_rate: cli.error_rate,
+        // Echo frames so pipe clients see each other's traffic, as on Linux vcan.
+        loopback: true,
+    };
+    let mut driver = VirtualDriver::open_with_options(bus, options).await?;
+
+    println!("Virtual bus {}", driver.bus_name());
+
+    // There is no traffic to detect a bitrate from; it only matters for timing.
+    driver.set_bitrate(cli.bitrate.unwrap_or(500_000)).await?;
+    if let Some(data_bitrate) = cli.data_bitrate {
+        driver.set_data_bitrate(data_bitrate)?;
+    }
+    driver.enable_timestamp().await?;
+    driver.open_channel().await?;
+
+    Ok(Box::new(driver))
+}
+
+/// Initialize a Linux SocketCAN interface from CLI args.
+#[cfg(target_os = "linux")]
+async fn init_socketcan(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
+    let interface = if cli.channel.to_ascii_lowercase() == "auto" {
+        "can0"
+    } else {
+        cli.channel.as_str()
+    };
+    let mut driver = SocketCanDriver::open(interface).await?;
+
+    println!("SocketCAN connected to {}", driver.interface());
+
+    // The interface bitrate is set with `ip link`; only record it for clients.
+    if let Some(bitrate) = cli.bitrate {
+        driver.set_bitrate(bitrate).await?;
+    }
+    if let Some(data_bitrate) = cli.data_bitrate {
+        driver.set_data_bitrate(data_bitrate)?;
+    }
+    if let Some(filter) = &cli.filter {
+        let set = FilterSet::parse(filter.split(','))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        driver.set_filter_set(&set)?;
+    }
+    driver.enable_timestamp().await?;
+    driver.open_channel().await?;
+
+    Ok(Box::new(driver))
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn init_socketcan(_cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "SocketCAN is only available on Linux",
+    ))
+}
+
+/// Initialize a cannelloni UDP tunnel from CLI args.
+async fn init_cannelloni(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
+    let resolve = |addr: &str| -> std::io::Result<std::net::SocketAddr> {
+        addr.to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Could not resolve address: {}", addr),
+            )
+        })
+    };
+    let options = CannelloniOptions {
+        local: resolve(&cli.local)?,
+        remote: cli.remote.as_deref().map(resolve).transpose()?,
+        batch_timeout: Duration::from_micros(cli.batch_us),
+    };
+    let mut driver = CannelloniDriver::open(options).await?;
+
+    // The remote bus is configured at the far end; the bitrate is informational.
+    if let Some(bitrate) = cli.bitrate {
+        driver.set_bitrate(bitrate).await?;
+    }
+    if let Some(data_bitrate) = cli.data_bitrate {
+        driver.set_data_bitrate(data_bitrate)?;
+    }
+    driver.enable_timestamp().await?;
+    driver.open_channel().await?;
+
+    match driver.peer() {
+        Some(peer) => println!("cannelloni on {} to {}", driver.local_addr()?, peer),
+        None => println!("cannelloni on {}, waiting for a peer", driver.local_addr()?),
+    }
+
+    Ok(Box::new(driver))
+}
+
+/// Initialize an ELM327 / STN11xx OBD adapter from CLI args.
+async fn init_elm327(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
+    if cli.channel.to_ascii_lowercase() == "auto" {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "elm327 needs the serial port given with -c, e.g. -c COM5 or -c /dev/ttyUSB0",
+        ));
+    }
+    let mut driver = Elm327Driver::open(&cli.channel, cli.baud.unwrap_or(ELM327_BAUD_RATE)).await?;
+
+    // Bitrate scanning would reset the adapter at every step; default to OBD-II's 500 kbit/s.
+    driver.set_bitrate(cli.bitrate.unwrap_or(500_000)).await?;
+    driver.enable_timestamp().await?;
+    driver.open_channel().await?;
+    println!(
+        "{} on {}",
+        driver.version().unwrap_or("ELM327"),
+        cli.channel
+    );
+
+    Ok(Box::new(driver))
+}
+
+/// Connect to a bus served by socketcand from CLI args.
+async fn init_socketcand(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
+    let Some(remote) = cli.remote.as_deref() else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "socketcand needs --remote <host[:port]>",
+        ));
+    };
+    let address = if remote.contains(':') {
+        remote.to_string()
+    } else {
+        format!("{}:{}", remote, SOCKETCAND_PORT)
+    };
+    let mut driver = SocketcandDriver::new(&address, &cli.remote_bus);
+
+    // The interface is configured on the socketcand host; the bitrate is informational.
+    if let Some(bitrate) = cli.bitrate {
+        driver.set_bitrate(bitrate).await?;
+    }
+    driver.enable_timestamp().await?;
+    driver.open_channel().await?;
+    println!("socketcand {} on {}", driver.bus(), driver.address());
+
+    Ok(Box::new(driver))
+}
+
 /// Resolve the requested driver implementation from the CLI arguments.
 async fn initialize_driver(cli: &Cli) -> std::io::Result<Box<dyn CanDriver>> {
     match cli.driver.to_lowercase().as_str() {
         "slcan" => init_slcan(cli).await,
         "pcan" => init_pcan(cli).await,
         "gsusb" | "gs_usb" => init_gsusb(cli).await,
+        "virtual" | "vcan" => init_virtual(cli).await,
+        "socketcan" => init_socketcan(cli).await,
+        "cannelloni" => init_cannelloni(cli).await,
+        "socketcand" => init_socketcand(cli).await,
+        "elm327" => init_elm327(cli).await,
         other => Err(std::io::Error::new(
             std::io::ErrorKind::Other,
             format!(
-                "Did not recognize driver specified: {}\nSupported drivers are: slcan, pcan, gsusb",
+                "Did not recognize driver specified: {}\nSupported drivers are: slcan, pcan, gsusb, socketcan, virtual, cannelloni, socketcand, elm327",
                 other
             ),
         )),
@@ -274,10 +621,13 @@ fn spawn_ipc_tasks(
     ));
 
     tokio::spawn(async move {
+        let d = driver.lock().await;
         let config = thread_manager_async::CanServerConfig {
-            bitrate: driver.lock().await.get_bitrate().await,
+            bitrate: d.get_bitrate().await,
+            data_bitrate: d.get_data_bitrate().await,
             version: env!("CARGO_PKG_VERSION").to_string(),
         };
+        drop(d);
         if let Err(e) = thread_manager_async::start_ipc_config_handler(channel_name, config).await {
             eprintln!("Encounted error when sending Config {:?}", e);
         }
@@ -291,10 +641,10 @@ async fn forward_pipe_to_can(
     mut rx_in_pipe: mpsc::Receiver<Vec<u8>>,
     driver: Arc<Mutex<Box<dyn CanDriver>>>,
 ) {
-    while let Some(line) = rx_in_pipe.recv().await {
-        if let Ok((frame, _)) =
-            bincode::serde::decode_from_slice::<CanFrame, _>(&line, bincode::config::standard())
-        {
+    let mut pending = Vec::new();
+    while let Some(chunk) = rx_in_pipe.recv().await {
+        pending.extend(chunk);
+        for frame in thread_manager_async::decode_frames(&mut pending) {
             let mut d = driver.lock().await;
             if let Err(e) = d.send_frame(&frame).await {
                 eprintln!("Failed to send CAN frame: {:?}", e);
@@ -303,25 +653,25 @@ async fn forward_pipe_to_can(
     }
 }
 
-/// Continuously poll the CAN driver and push any frames to the IPC writer.
+/// Continuously poll the CAN driver and push any frames to the IPC writer
+/// and to the broadcast manager and TCP/pty bridges subscribed to `tx_frames`.
 async fn forward_can_to_pipe(
     driver: Arc<Mutex<Box<dyn CanDriver>>>,
     tx_out_pipe: mpsc::Sender<Vec<u8>>,
+    tx_frames: broadcast::Sender<CanFrame>,
 ) {
     loop {
         match driver.lock().await.read_frames().await {
             Ok(frames) => {
                 for frame in frames {
-                    if let Ok(mut data) =
-                        bincode::serde::encode_to_vec(frame, bincode::config::standard())
-                    {
-                        if data.len() > (u8::MAX as usize) {
-                            eprintln!("Serialized CanFrame too large: {}", data.len());
-                            continue;
+                    if tx_frames.receiver_count() > 0 {
+                        let _ = tx_frames.send(frame.clone());
+                    }
+                    match thread_manager_async::encode_frame_message(&frame) {
+                        Ok(msg) => {
+                            let _ = tx_out_pipe.try_send(msg);
                         }
-                        let mut msg = vec![data.len() as u8];
-                        msg.append(&mut data);
-                        let _ = tx_out_pipe.try_send(msg);
+                        Err(e) => eprintln!("{}", e),
                     }
                 }
             }
@@ -333,16 +683,45 @@ async fn forward_can_to_pipe(
     }
 }
 
+/// Emulate an SLCAN adapter on a new pseudo-terminal.
+#[cfg(unix)]
+fn spawn_slcan_pty(
+    driver: Arc<Mutex<Box<dyn CanDriver>>>,
+    tx_frames: broadcast::Sender<CanFrame>,
+) -> std::io::Result<()> {
+    let pty = slcan_bridge::Pty::open()?;
+    println!("SLCAN bridge on {}", pty.path());
+    tokio::spawn(async move {
+        if let Err(e) = pty.serve(driver, tx_frames).await {
+            eprintln!("SLCAN bridge stopped: {:?}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn spawn_slcan_pty(
+    _driver: Arc<Mutex<Box<dyn CanDriver>>>,
+    _tx_frames: broadcast::Sender<CanFrame>,
+) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "--slcan-pty is not available on Windows; use --slcan-tcp with a virtual COM port redirector",
+    ))
+}
+
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init();
     let cli = Cli::parse();
 
-    let channel_name = if cli.channel.to_ascii_lowercase() == "auto" {
-        next_auto_channel("can")
-    } else {
-        cli.channel.clone()
-    };
+    // Serial device paths such as /dev/ttyUSB0 cannot name a pipe.
+    let channel_name =
+        if cli.channel.to_ascii_lowercase() == "auto" || cli.channel.contains(['/', '\\']) {
+            next_auto_channel("can")
+        } else {
+            cli.channel.clone()
+        };
 
     // Initialize the requested CAN driver implementation.
     let driver = match initialize_driver(&cli).await {
@@ -357,11 +736,55 @@ async fn main() -> std::io::Result<()> {
 
     println!("\nCreated CAN server: {}", channel_name);
 
+    // Frames received from the bus, for the broadcast manager and bridges.
+    let (tx_frames, _) = broadcast::channel(1024);
+
+    // Cyclic transmission and content filtered reception for clients such
+    // as `cansend --every` and `candump --changes-only`.
+    tokio::spawn({
+        let channel_name = channel_name.clone();
+        let driver = driver.clone();
+        let tx_frames = tx_frames.clone();
+        async move {
+            if let Err(e) = bcm::serve(channel_name, driver, tx_frames).await {
+                eprintln!("Broadcast manager stopped: {:?}", e);
+            }
+        }
+    });
+
     // Task to bridge IPC traffic into the CAN driver.
     let mut task_in = tokio::spawn(forward_pipe_to_can(rx_in_pipe, driver.clone()));
 
+    // Optional bridges sharing the channel over TCP or a pseudo-terminal.
+    if let Some(address) = &cli.socketcand {
+        let listener = TcpListener::bind(address).await?;
+        println!(
+            "socketcand server for {} on {}",
+            channel_name,
+            listener.local_addr()?
+        );
+        tokio::spawn(socketcand_server::serve(
+            listener,
+            channel_name.clone(),
+            driver.clone(),
+            tx_frames.clone(),
+        ));
+    }
+    if let Some(address) = &cli.slcan_tcp {
+        let listener = TcpListener::bind(address).await?;
+        println!("SLCAN bridge on {}", listener.local_addr()?);
+        tokio::spawn(slcan_bridge::serve_tcp(
+            listener,
+            driver.clone(),
+            tx_frames.clone(),
+        ));
+    }
+    if cli.slcan_pty {
+        spawn_slcan_pty(driver.clone(), tx_frames.clone())?;
+    }
+
     // Task to bridge CAN traffic out to the IPC pipe.
-    let mut task_out = tokio::spawn(forward_can_to_pipe(driver.clone(), tx_out_pipe));
+    let mut task_out = tokio::spawn(forward_can_to_pipe(driver.clone(), tx_out_pipe, tx_frames));
 
     // Wait for ctrl+c OR a task finishing
     tokio::select! {
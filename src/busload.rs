@@ -0,0 +1,405 @@
+/// Bus load measurement from the exact length of each frame on the wire,
+/// counted from SOF through the interframe space as in ISO 11898-1.
+///
+/// Classic frames take `bitrate` throughout. Frames with more than 8 data
+/// bytes are CAN FD frames and, as `CanFrame` carries no BRS flag, are
+/// assumed to switch to the data bitrate from the BRS bit up to the CRC
+/// delimiter whenever one is known.
+use crosscan::can::CanFrame;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::bcm::FrameKey;
+
+/// Generator polynomial of the classic CAN CRC-15.
+const CRC15_POLY: u16 = 0x4599;
+
+/// CRC delimiter, ACK slot, ACK delimiter, end of frame and interframe space.
+const FOOTER_BITS: u32 = 1 + 1 + 1 + 7 + 3;
+
+/// How dynamic stuff bits are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Stuffing {
+    /// Leave them out.
+    Ignore,
+    /// Assume the most a frame of this size can need: one per four bits.
+    #[default]
+    WorstCase,
+    /// Count those the frame's actual bit pattern needs.
+    Exact,
+}
+
+/// Length of a frame in bits at the nominal (arbitration) and data bitrates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameBits {
+    pub nominal: u32,
+    pub data: u32,
+}
+
+impl FrameBits {
+    pub fn total(&self) -> u32 {
+        self.nominal + self.data
+    }
+
+    /// Time the frame occupies the bus. Data phase bits go at the nominal
+    /// rate without a data bitrate.
+    pub fn duration(&self, bitrate: u32, data_bitrate: Option<u32>) -> Duration {
+        let data_bitrate = data_bitrate.unwrap_or(bitrate);
+        let nanos = self.nominal as u64 * 1_000_000_000 / bitrate as u64
+            + self.data as u64 * 1_000_000_000 / data_bitrate as u64;
+        Duration::from_nanos(nanos)
+    }
+}
+
+/// Number of bits `frame` takes on the bus. Error frames reported by the
+/// driver are not bus traffic and count as zero.
+pub fn frame_bits(frame: &CanFrame, stuffing: Stuffing) -> FrameBits {
+    if frame.is_error() {
+        return FrameBits::default();
+    }
+    if frame.data().len() > 8 {
+        fd_frame_bits(frame, stuffing)
+    } else {
+        classic_frame_bits(frame, stuffing)
+    }
+}
+
+/// Bits of the arbitration field: SOF, identifier and, for extended ids,
+/// SRR and IDE, all before the RTR/RRS bit.
+fn push_arbitration(bits: &mut Vec<bool>, frame: &CanFrame) {
+    bits.push(false);
+    if frame.is_extended() {
+        push_value(bits, frame.id() >> 18, 11);
+        bits.extend([true, true]);
+        push_value(bits, frame.id(), 18);
+    } else {
+        push_value(bits, frame.id(), 11);
+    }
+}
+
+fn classic_frame_bits(frame: &CanFrame, stuffing: Stuffing) -> FrameBits {
+    let data = frame.data();
+    let dlc = if frame.is_rtr() {
+        frame.dlc().min(8)
+    } else {
+        data.len()
+    };
+
+    let mut bits = Vec::with_capacity(54 + 64 + 15);
+    push_arbitration(&mut bits, frame);
+    bits.push(frame.is_rtr());
+    // IDE and r0 for standard ids, r1 and r0 for extended ones.
+    bits.extend([false, false]);
+    push_value(&mut bits, dlc as u32, 4);
+    for &byte in data {
+        push_value(&mut bits, byte as u32, 8);
+    }
+    let crc = crc15(&bits);
+    push_value(&mut bits, crc as u32, 15);
+
+    // Everything from SOF through the CRC sequence is dynamically stuffed.
+    let stuffed = bits.len() as u32;
+    let stuff_bits = match stuffing {
+        Stuffing::Ignore => 0,
+        Stuffing::WorstCase => (stuffed - 1) / 4,
+        Stuffing::Exact => stuff_positions(&bits).len() as u32,
+    };
+    FrameBits {
+        nominal: stuffed + stuff_bits + FOOTER_BITS,
+        data: 0,
+    }
+}
+
+fn fd_frame_bits(frame: &CanFrame, stuffing: Stuffing) -> FrameBits {
+    let data = frame.data();
+
+    let mut bits = Vec::with_capacity(41 + 64 * 8);
+    push_arbitration(&mut bits, frame);
+    if !frame.is_extended() {
+        // RRS, then IDE.
+        bits.push(false);
+    }
+    // RRS (IDE for standard ids), FDF, res and BRS.
+    bits.extend([false, true, false, true]);
+    // The data phase starts after BRS.
+    let arbitration = bits.len() as u32;
+    // ESI and DLC.
+    bits.push(false);
+    push_value(&mut bits, fd_dlc(data.len()), 4);
+    for &byte in data {
+        push_value(&mut bits, byte as u32, 8);
+    }
+
+    // Only SOF through the data field is dynamically stuffed; stuff bits
+    // after BRS go at the data bitrate.
+    let stuffed = bits.len() as u32;
+    let (nominal_stuff, data_stuff) = match stuffing {
+        Stuffing::Ignore => (0, 0),
+        Stuffing::WorstCase => {
+            let nominal = (arbitration - 1) / 4;
+            (nominal, (stuffed - 1) / 4 - nominal)
+        }
+        Stuffing::Exact => {
+            let positions = stuff_positions(&bits);
+            let nominal = positions.iter().filter(|&&i| i < arbitration - 1).count() as u32;
+            (nominal, positions.len() as u32 - nominal)
+        }
+    };
+
+    // Stuff count (3 bit gray code and parity), CRC-17 or CRC-21 and a fixed
+    // stuff bit before each four bits of them, then the CRC delimiter.
+    let crc_len: u32 = if data.len() > 16 { 21 } else { 17 };
+    let crc_field = 4 + crc_len + (4 + crc_len).div_ceil(4) + 1;
+    // ACK, EOF and interframe space are back at the nominal rate.
+    let footer = FOOTER_BITS - 1;
+
+    FrameBits {
+        nominal: arbitration + nominal_stuff + footer,
+        data: stuffed - arbitration + data_stuff + crc_field,
+    }
+}
+
+/// DLC code of a CAN FD payload length.
+fn fd_dlc(len: usize) -> u32 {
+    match len {
+        0..=8 => len as u32,
+        9..=12 => 9,
+        13..=16 => 10,
+        17..=20 => 11,
+        21..=24 => 12,
+        25..=32 => 13,
+        33..=48 => 14,
+        _ => 15,
+    }
+}
+
+/// Append the `len` low bits of `value`, most significant first.
+fn push_value(bits: &mut Vec<bool>, value: u32, len: u32) {
+    bits.extend((0..len).rev().map(|i| value >> i & 1 != 0));
+}
+
+fn crc15(bits: &[bool]) -> u16 {
+    let mut crc: u16 = 0;
+    for &bit in bits {
+        let feedback = bit ^ (crc & 0x4000 != 0);
+        crc = (crc << 1) & 0x7FFF;
+        if feedback {
+            crc ^= CRC15_POLY;
+        }
+    }
+    crc
+}
+
+/// Indices of the bits after which a stuff bit is inserted: after five equal
+/// bits, counting earlier stuff bits, which are the opposite of the run.
+fn stuff_positions(bits: &[bool]) -> Vec<u32> {
+    let mut positions = Vec::new();
+    let mut last = None;
+    let mut run = 0;
+    for (i, &bit) in bits.iter().enumerate() {
+        if last == Some(bit) {
+            run += 1;
+        } else {
+            last = Some(bit);
+            run = 1;
+        }
+        if run == 5 {
+            positions.push(i as u32);
+            last = Some(!bit);
+            run = 1;
+        }
+    }
+    positions
+}
+
+/// Bus usage of one identifier.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IdUsage {
+    pub frames: u64,
+    pub bits: u64,
+    pub busy: Duration,
+}
+
+/// Bus usage over one measurement interval.
+#[derive(Debug, Clone, Default)]
+pub struct LoadInterval {
+    pub frames: u64,
+    pub bits: u64,
+    pub busy: Duration,
+    pub per_id: HashMap<FrameKey, IdUsage>,
+}
+
+impl LoadInterval {
+    /// Bus load in percent of `interval`.
+    pub fn load(&self, interval: Duration) -> f64 {
+        percent(self.busy, interval)
+    }
+}
+
+/// `busy` in percent of `interval`.
+pub fn percent(busy: Duration, interval: Duration) -> f64 {
+    if interval.is_zero() {
+        return 0.0;
+    }
+    busy.as_secs_f64() * 100.0 / interval.as_secs_f64()
+}
+
+/// Accumulates the frames seen on one bus until the interval is taken.
+#[derive(Debug, Clone)]
+pub struct BusLoadMeter {
+    bitrate: u32,
+    data_bitrate: Option<u32>,
+    stuffing: Stuffing,
+    current: LoadInterval,
+}
+
+impl BusLoadMeter {
+    /// `bitrate` must not be zero.
+    pub fn new(bitrate: u32, data_bitrate: Option<u32>, stuffing: Stuffing) -> Self {
+        Self {
+            bitrate,
+            data_bitrate,
+            stuffing,
+            current: LoadInterval::default(),
+        }
+    }
+
+    pub fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+
+    pub fn data_bitrate(&self) -> Option<u32> {
+        self.data_bitrate
+    }
+
+    pub fn add(&mut self, frame: &CanFrame) {
+        let bits = frame_bits(frame, self.stuffing);
+        if bits.total() == 0 {
+            return;
+        }
+        let busy = bits.duration(self.bitrate, self.data_bitrate);
+
+        self.current.frames += 1;
+        self.current.bits += bits.total() as u64;
+        self.current.busy += busy;
+        let usage = self
+            .current
+            .per_id
+            .entry((frame.id(), frame.is_extended()))
+            .or_default();
+        usage.frames += 1;
+        usage.bits += bits.total() as u64;
+        usage.busy += busy;
+    }
+
+    /// End the current interval and start the next one.
+    pub fn take(&mut self) -> LoadInterval {
+        std::mem::take(&mut self.current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(frame: &CanFrame, stuffing: Stuffing) -> FrameBits {
+        frame_bits(frame, stuffing)
+    }
+
+    #[test]
+    fn worst_case_classic_lengths() {
+        let full = CanFrame::new(0x7FF, &[0xFF; 8]).unwrap();
+        assert_eq!(bits(&full, Stuffing::WorstCase).nominal, 135);
+        assert_eq!(bits(&full, Stuffing::Ignore).nominal, 111);
+
+        let empty = CanFrame::new(0x123, &[]).unwrap();
+        assert_eq!(bits(&empty, Stuffing::WorstCase).nominal, 55);
+        assert_eq!(bits(&empty, Stuffing::Ignore).nominal, 47);
+
+        let extended = CanFrame::new_eff(0x1ABCDEF, &[0; 8]).unwrap();
+        assert_eq!(bits(&extended, Stuffing::WorstCase).nominal, 160);
+        assert_eq!(bits(&extended, Stuffing::Ignore).nominal, 131);
+
+        // A remote frame is as long as an empty data frame, whatever its DLC.
+        let remote = CanFrame::new_remote(0x123, 8, false).unwrap();
+        assert_eq!(bits(&remote, Stuffing::Ignore).nominal, 47);
+    }
+
+    #[test]
+    fn exact_stuffing_follows_the_bit_pattern() {
+        // Long runs of dominant bits: 16 stuff bits, the CRC included.
+        let zeros = CanFrame::new(0x000, &[0; 8]).unwrap();
+        assert_eq!(bits(&zeros, Stuffing::Exact).nominal, 127);
+
+        // Alternating data needs a single stuff bit, in the CRC.
+        let alternating = CanFrame::new(0x123, &[0xAA; 8]).unwrap();
+        assert_eq!(bits(&alternating, Stuffing::Exact).nominal, 112);
+    }
+
+    #[test]
+    fn counts_stuff_bits_in_their_own_runs() {
+        // Five equal bits, then the stuff bit starts a new run with the next four.
+        let bits = [false, false, false, false, false, true, true, true, true];
+        assert_eq!(stuff_positions(&bits), [4, 8]);
+        assert!(stuff_positions(&[true, false, true, false]).is_empty());
+    }
+
+    #[test]
+    fn fd_frames_split_between_the_bitrates() {
+        let frame = CanFrame::new(0x123, &[0x55; 64]).unwrap();
+        // SOF to BRS and ACK to the interframe space at the nominal rate;
+        // ESI, DLC, 512 data bits and the 33 bit CRC field at the data rate.
+        assert_eq!(
+            bits(&frame, Stuffing::Ignore),
+            FrameBits {
+                nominal: 29,
+                data: 550
+            }
+        );
+
+        let extended = CanFrame::new_eff(0x123, &[0x55; 12]).unwrap();
+        let split = bits(&extended, Stuffing::Ignore);
+        assert_eq!(split.nominal, 36 + 12);
+        // A 17 bit CRC up to 16 data bytes.
+        assert_eq!(split.data, 5 + 96 + 4 + 17 + 6 + 1);
+    }
+
+    #[test]
+    fn durations_use_the_data_bitrate_when_known() {
+        let classic = FrameBits {
+            nominal: 135,
+            data: 0,
+        };
+        assert_eq!(
+            classic.duration(500_000, Some(2_000_000)),
+            Duration::from_micros(270)
+        );
+
+        let fd = FrameBits {
+            nominal: 29,
+            data: 550,
+        };
+        assert_eq!(
+            fd.duration(500_000, Some(2_000_000)),
+            Duration::from_micros(333)
+        );
+        assert_eq!(fd.duration(500_000, None), Duration::from_micros(1158));
+    }
+
+    #[test]
+    fn error_frames_take_no_bus_time() {
+        let error = CanFrame::new_error(0x04).unwrap();
+        assert_eq!(bits(&error, Stuffing::WorstCase).total(), 0);
+
+        let mut meter = BusLoadMeter::new(500_000, None, Stuffing::WorstCase);
+        meter.add(&error);
+        meter.add(&CanFrame::new(0x7FF, &[0xFF; 8]).unwrap());
+        meter.add(&CanFrame::new(0x7FF, &[0xFF; 8]).unwrap());
+        let interval = meter.take();
+        assert_eq!((interval.frames, interval.bits), (2, 270));
+        assert_eq!(interval.busy, Duration::from_micros(540));
+        assert!((interval.load(Duration::from_millis(1)) - 54.0).abs() < 1e-9);
+        assert_eq!(interval.per_id[&(0x7FF, false)].frames, 2);
+        assert_eq!(meter.take().frames, 0);
+    }
+}
@@ -0,0 +1,385 @@
+/// The candump log format used by can-utils' `candump -l`, `canlogserver`
+/// and `canplayer`: `(<sec>.<usec>) <ifname> <frame>`, where the frame is
+/// `<id>#<data>` for classic CAN, `<id>#R[<len>]` for remote frames and
+/// `<id>##<flags><data>` for CAN FD.
+use crosscan::can::CanFrame;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::drivers::can_id::{CAN_EFF_MASK, CAN_ERR_FLAG, CAN_ERR_MASK, CAN_SFF_MASK};
+
+/// Largest CAN FD payload.
+const CANFD_MAX_LEN: usize = 64;
+
+/// One line of a candump log.
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub timestamp_us: u64,
+    pub ifname: String,
+    pub frame: CanFrame,
+}
+
+/// Format a frame the way `cansend` accepts it, e.g. `123#DEADBEEF`.
+/// Error frames carry the `CAN_ERR_FLAG` in their 8 digit id.
+pub fn format_frame(frame: &CanFrame) -> String {
+    let mut out = if frame.is_error() {
+        format!("{:08X}#", CAN_ERR_FLAG | frame.id())
+    } else if frame.is_extended() {
+        format!("{:08X}#", frame.id())
+    } else {
+        format!("{:03X}#", frame.id())
+    };
+
+    if frame.is_rtr() {
+        out.push('R');
+        let len = frame.dlc();
+        if len > 0 && len <= 8 {
+            out.push_str(&format!("{:X}", len));
+        }
+        return out;
+    }
+
+    let data = frame.data();
+    if data.len() > 8 {
+        // CAN FD: no flags are known, so BRS/ESI are reported clear.
+        out.push_str("#0");
+    }
+    for byte in data {
+        out.push_str(&format!("{:02X}", byte));
+    }
+    out
+}
+
+/// Format a log line: `(1436509052.249713) can0 123#DEADBEEF`.
+pub fn format_line(timestamp_us: u64, ifname: &str, frame: &CanFrame) -> String {
+    format!(
+        "({}.{:06}) {} {}",
+        timestamp_us / 1_000_000,
+        timestamp_us % 1_000_000,
+        ifname,
+        format_frame(frame)
+    )
+}
+
+/// Parse a log line written by [`format_line`] or can-utils' `candump -l`.
+/// Anything after the frame, such as the direction flag newer versions of
+/// `candump` append, is ignored.
+pub fn parse_line(line: &str) -> Result<LogEntry, String> {
+    let mut fields = line.split_whitespace();
+    let (Some(timestamp), Some(ifname), Some(frame)) =
+        (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(format!(
+            "expected '(timestamp) ifname frame', got '{}'",
+            line
+        ));
+    };
+    Ok(LogEntry {
+        timestamp_us: parse_timestamp(timestamp)?,
+        ifname: ifname.to_string(),
+        frame: parse_frame(frame)?,
+    })
+}
+
+/// Parse a frame in the `cansend` syntax: `<id>#<data>`, `<id>#R[<len>]` or
+/// `<id>##<flags><data>`. Ids have 3 digits, or 8 for extended and error
+/// frames, and data bytes may be separated by dots.
+///
+/// Classic frames of length 8 may end in `_<dlc>` giving a raw DLC of 9 to
+/// F. It is validated but dropped, as `CanFrame` carries no raw DLC. Nor
+/// does it mark CAN FD other than by length, so FD frames of up to 8 bytes
+/// are refused rather than sent as classic frames.
+pub fn parse_frame(s: &str) -> Result<CanFrame, String> {
+    let invalid = |why: &str| format!("invalid frame '{}': {}", s, why);
+
+    let (id, rest) = s.split_once('#').ok_or_else(|| invalid("missing '#'"))?;
+    let can_id = u32::from_str_radix(id, 16).map_err(|_| invalid("bad id"))?;
+    let extended = match id.len() {
+        3 if can_id > CAN_SFF_MASK => return Err(invalid("standard id out of range")),
+        3 => false,
+        8 => true,
+        _ => return Err(invalid("id must have 3 or 8 hex digits")),
+    };
+
+    if can_id & CAN_ERR_FLAG != 0 {
+        return CanFrame::new_error(can_id & CAN_ERR_MASK).map_err(|e| invalid(&e.to_string()));
+    }
+    if extended && can_id > CAN_EFF_MASK {
+        return Err(invalid("id out of range"));
+    }
+
+    let (data, max_len) = if let Some(rest) = rest.strip_prefix('#') {
+        // CAN FD: a flags digit (BRS/ESI, not carried by CanFrame) precedes the data.
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some(flags) if flags.is_ascii_hexdigit() => {}
+            _ => return Err(invalid("missing CAN FD flags")),
+        }
+        let data = parse_data(chars.as_str()).ok_or_else(|| invalid("bad data"))?;
+        if data.len() <= 8 {
+            return Err(invalid("CAN FD frames of up to 8 bytes are not supported"));
+        }
+        (data, CANFD_MAX_LEN)
+    } else if let Some(len) = rest.strip_prefix(['R', 'r']) {
+        let len = strip_len8_dlc(len, |len| len == "8").map_err(&invalid)?;
+        let len = match len {
+            "" => 0,
+            len => len
+                .parse::<usize>()
+                .ok()
+                .filter(|&len| len <= 8)
+                .ok_or_else(|| invalid("bad remote length"))?,
+        };
+        return build_frame(can_id, extended, Some(len), &[]).map_err(|e| invalid(&e));
+    } else {
+        let rest = strip_len8_dlc(rest, |data| parse_data(data).is_some_and(|d| d.len() == 8))
+            .map_err(&invalid)?;
+        (parse_data(rest).ok_or_else(|| invalid("bad data"))?, 8)
+    };
+    if data.len() > max_len {
+        return Err(invalid("too much data"));
+    }
+
+    build_frame(can_id, extended, None, &data).map_err(|e| invalid(&e))
+}
+
+/// Build a data frame, or a remote frame requesting `remote_len` bytes.
+/// More than 8 data bytes make a CAN FD frame.
+pub fn build_frame(
+    id: u32,
+    extended: bool,
+    remote_len: Option<usize>,
+    data: &[u8],
+) -> Result<CanFrame, String> {
+    let frame = match remote_len {
+        Some(len) => CanFrame::new_remote(id, len, extended),
+        None if extended => CanFrame::new_eff(id, data),
+        None => CanFrame::new(id, data),
+    };
+    frame.map_err(|e| e.to_string())
+}
+
+/// Strip a `_<dlc>` suffix, which needs a DLC of 9 to F and a length of 8
+/// as judged by `is_len8`.
+fn strip_len8_dlc(s: &str, is_len8: impl Fn(&str) -> bool) -> Result<&str, &'static str> {
+    let Some((head, dlc)) = s.rsplit_once('_') else {
+        return Ok(s);
+    };
+    match u8::from_str_radix(dlc, 16) {
+        Ok(9..=15) if dlc.len() == 1 => {}
+        _ => return Err("raw DLC must be 9 to F"),
+    }
+    if !is_len8(head) {
+        return Err("raw DLC needs a length of 8");
+    }
+    Ok(head)
+}
+
+/// Parse `(<sec>.<usec>)` into microseconds.
+fn parse_timestamp(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid timestamp '{}'", s);
+    let inner = s
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(invalid)?;
+    let (sec, frac) = inner.split_once('.').unwrap_or((inner, ""));
+    if frac.len() > 6 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let sec: u64 = sec.parse().map_err(|_| invalid())?;
+    // Scale shorter fractions, e.g. `.25` is 250000 us.
+    let usec = format!("{:0<6}", frac)
+        .parse::<u64>()
+        .map_err(|_| invalid())?;
+    Ok(sec * 1_000_000 + usec)
+}
+
+/// Parse hex data bytes, optionally separated by dots: `DEADBEEF`, `DE.AD.BE.EF`.
+pub fn parse_data(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|&b| b != b'.').collect();
+    if !digits.len().is_multiple_of(2) || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Default log file name for `candump -l`, in local time:
+/// `candump-2015-07-10_081052.log`.
+pub fn log_file_name(now: SystemTime) -> String {
+    let secs = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    match local_time(secs as libc::time_t) {
+        Some(tm) => format!(
+            "candump-{:04}-{:02}-{:02}_{:02}{:02}{:02}.log",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec
+        ),
+        // Without a usable local time the epoch seconds still sort correctly.
+        None => format!("candump-{}.log", secs),
+    }
+}
+
+#[cfg(unix)]
+fn local_time(secs: libc::time_t) -> Option<libc::tm> {
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    let result = unsafe { libc::localtime_r(&secs, &mut tm) };
+    (!result.is_null()).then_some(tm)
+}
+
+#[cfg(windows)]
+fn local_time(secs: libc::time_t) -> Option<libc::tm> {
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    let result = unsafe { libc::localtime_s(&mut tm, &secs) };
+    (result == 0).then_some(tm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(frame: &CanFrame) -> LogEntry {
+        let line = format_line(1_436_509_052_249_713, "can0", frame);
+        parse_line(&line).unwrap_or_else(|e| panic!("{}: {}", line, e))
+    }
+
+    #[test]
+    fn log_lines_round_trip() {
+        let frames = [
+            CanFrame::new(0x123, &[0xDE, 0xAD, 0xBE, 0xEF]).unwrap(),
+            CanFrame::new(0x7FF, &[]).unwrap(),
+            CanFrame::new_eff(0x1ABCDEF, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap(),
+            CanFrame::new_remote(0x321, 3, false).unwrap(),
+            CanFrame::new_remote(0x1234567, 0, true).unwrap(),
+            CanFrame::new(0x456, &[0x55; 64]).unwrap(),
+            CanFrame::new_error(0x04).unwrap(),
+        ];
+        for frame in &frames {
+            let entry = round_trip(frame);
+            assert_eq!(entry.timestamp_us, 1_436_509_052_249_713);
+            assert_eq!(entry.ifname, "can0");
+            assert_eq!(format_frame(&entry.frame), format_frame(frame));
+            assert_eq!(
+                (entry.frame.is_extended(), entry.frame.is_rtr()),
+                (frame.is_extended(), frame.is_rtr())
+            );
+            assert_eq!(entry.frame.is_error(), frame.is_error());
+        }
+    }
+
+    #[test]
+    fn formats_log_lines_like_candump() {
+        let frame = CanFrame::new(0x123, &[0xDE, 0xAD, 0xBE, 0xEF]).unwrap();
+        assert_eq!(
+            format_line(1_436_509_052_000_042, "vcan0", &frame),
+            "(1436509052.000042) vcan0 123#DEADBEEF"
+        );
+        let fd = CanFrame::new_eff(0x1, &[0xAA; 12]).unwrap();
+        assert_eq!(format_frame(&fd), "00000001##0AAAAAAAAAAAAAAAAAAAAAAAA");
+        let error = CanFrame::new_error(0x40).unwrap();
+        assert_eq!(format_frame(&error), "20000040#");
+    }
+
+    #[test]
+    fn parses_lines_from_other_writers() {
+        // Newer candump versions append the direction; short fractions scale.
+        let entry = parse_line("(1.25) can1 123#11 R").unwrap();
+        assert_eq!(entry.timestamp_us, 1_250_000);
+        assert_eq!(entry.ifname, "can1");
+        assert_eq!(entry.frame.data(), &[0x11]);
+
+        for line in ["", "(1.0) can0", "1.0 can0 123#", "(1.0000001) can0 123#"] {
+            assert!(parse_line(line).is_err(), "{}", line);
+        }
+    }
+
+    fn frame(s: &str) -> CanFrame {
+        parse_frame(s).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    #[test]
+    fn parses_remote_frames() {
+        let remote = frame("123#R");
+        assert!(remote.is_rtr() && !remote.is_extended());
+        assert_eq!((remote.id(), remote.dlc()), (0x123, 0));
+        assert_eq!(frame("123#R4").dlc(), 4);
+        assert_eq!(frame("123#r8_9").dlc(), 8);
+        let extended = frame("1ABCDEF0#R2");
+        assert!(extended.is_rtr() && extended.is_extended());
+    }
+
+    #[test]
+    fn parses_data_frames() {
+        assert_eq!(frame("123#11.22.33").data(), &[0x11, 0x22, 0x33]);
+        assert_eq!(frame("7FF#").data(), &[] as &[u8]);
+
+        let extended = frame("12345678#");
+        assert!(extended.is_extended() && extended.data().is_empty());
+        assert_eq!(extended.id(), 0x1234_5678);
+        // Eight digits make an extended id even when the value is small.
+        assert!(frame("00000123#00").is_extended());
+
+        // A raw DLC is validated on 8 byte frames and then dropped.
+        assert_eq!(frame("123#1122334455667788_F").data().len(), 8);
+        assert_eq!(frame("123#11.22.33.44.55.66.77.88_9").data().len(), 8);
+    }
+
+    #[test]
+    fn parses_fd_frames() {
+        let short = frame(&format!("123##1{}", "AB".repeat(12)));
+        assert_eq!(short.data(), &[0xAB; 12]);
+        let long = frame(&format!("1ABCDEF0##3{}", "5A".repeat(64)));
+        assert!(long.is_extended());
+        assert_eq!(long.data(), &[0x5A; 64]);
+    }
+
+    #[test]
+    fn parses_error_frames() {
+        let error = frame("20000080#0000000000000000");
+        assert!(error.is_error());
+        assert_eq!(error.id(), 0x80);
+        assert!(frame("20000004#").is_error());
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let long = "11".repeat(9);
+        let too_long_fd = format!("123##0{}", "11".repeat(65));
+        for s in [
+            // Raw DLCs must be 9 to F and need exactly 8 bytes.
+            "123#1122334455667788_8",
+            "123#1122334455667788_G",
+            "123#1122334455667788_10",
+            "123#11_9",
+            "123#_9",
+            "123#R4_9",
+            // Too much data for the frame type.
+            &format!("123#{}", long),
+            &too_long_fd,
+            "123#R9",
+            // Bad ids and missing parts.
+            "800#",
+            "1234#",
+            "40000000#",
+            "12G#",
+            "123",
+            "123##G11",
+            "123##",
+            // FD frames must be longer than a classic one.
+            "123##1AABB",
+            "123##0",
+            &format!("123##0{}", "11".repeat(8)),
+            "123#1",
+            "123#11.2",
+        ] {
+            assert!(parse_frame(s).is_err(), "{}", s);
+        }
+    }
+}
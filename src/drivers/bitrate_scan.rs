@@ -0,0 +1,289 @@
+/// Bitrate detection by listening passively at each candidate bitrate.
+use std::io;
+use std::time::Duration;
+use tokio::time::{Instant, timeout};
+
+use crate::drivers::CanDriver;
+
+/// Nominal bitrates of the SLCAN `S0`..`S8` table, supported by most adapters.
+pub const STANDARD_BITRATES: [u32; 9] = [
+    10_000, 20_000, 50_000, 100_000, 125_000, 250_000, 500_000, 800_000, 1_000_000,
+];
+
+/// Number of clean frames that confirms a bitrate without waiting out the dwell time.
+const CONFIRM_FRAMES: usize = 10;
+
+/// Traffic observed while listening at one candidate bitrate.
+#[derive(Debug, Clone, Copy)]
+pub struct BitrateProbe {
+    pub bitrate: u32,
+    pub frames: usize,
+    pub error_frames: usize,
+}
+
+impl BitrateProbe {
+    /// Valid frames were received and error frames are at most a tenth of them.
+    pub fn is_clean(&self) -> bool {
+        self.frames > 0 && self.error_frames * 10 <= self.frames
+    }
+}
+
+/// Scan `candidates` in listen-only mode and return the bitrate that received clean traffic.
+///
+/// Each candidate is listened to for up to `dwell`. A candidate that reaches
+/// [`CONFIRM_FRAMES`] clean frames is returned immediately, otherwise the clean
+/// candidate with the most frames wins. The channel is left closed.
+pub async fn scan_bitrate(
+    driver: &mut dyn CanDriver,
+    candidates: &[u32],
+    dwell: Duration,
+) -> io::Result<Option<u32>> {
+    let mut best: Option<BitrateProbe> = None;
+
+    for &bitrate in candidates {
+        let probe = probe_bitrate(driver, bitrate, dwell).await;
+        let _ = driver.close_channel().await;
+        let probe = probe?;
+
+        log::info!(
+            "bitrate scan: {} bps → {} frames, {} error frames",
+            probe.bitrate,
+            probe.frames,
+            probe.error_frames
+        );
+
+        if !probe.is_clean() {
+            continue;
+        }
+        if probe.frames >= CONFIRM_FRAMES {
+            return Ok(Some(bitrate));
+        }
+        if best.is_none_or(|b| probe.frames > b.frames) {
+            best = Some(probe);
+        }
+    }
+
+    Ok(best.map(|p| p.bitrate))
+}
+
+/// Listen at a single bitrate and count the frames that arrive within `dwell`.
+pub async fn probe_bitrate(
+    driver: &mut dyn CanDriver,
+    bitrate: u32,
+    dwell: Duration,
+) -> io::Result<BitrateProbe> {
+    let mut probe = BitrateProbe {
+        bitrate,
+        frames: 0,
+        error_frames: 0,
+    };
+
+    let _ = driver.close_channel().await;
+    match driver.set_bitrate(bitrate).await {
+        Ok(()) => {}
+        // The adapter cannot generate this bitrate; treat it as silent.
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput => return Ok(probe),
+        Err(e) => return Err(e),
+    }
+    driver.open_listen_only().await?;
+
+    let deadline = Instant::now() + dwell;
+    while probe.frames < CONFIRM_FRAMES {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        match timeout(remaining, driver.read_frames()).await {
+            Ok(Ok(frames)) => {
+                if frames.is_empty() {
+                    // Some drivers return immediately when nothing is queued.
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                for frame in frames {
+                    if frame.is_error() {
+                        probe.error_frames += 1;
+                    } else {
+                        probe.frames += 1;
+                    }
+                }
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(_) => break,
+        }
+    }
+
+    Ok(probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use crosscan::can::CanFrame;
+    use std::collections::{HashMap, VecDeque};
+
+    const DWELL: Duration = Duration::from_millis(30);
+
+    /// Replays scripted reads per bitrate; reads at other bitrates are empty.
+    #[derive(Default)]
+    struct ScriptedDriver {
+        reads: HashMap<u32, VecDeque<Vec<CanFrame>>>,
+        unsupported: Vec<u32>,
+        bitrate: Option<u32>,
+        listening: bool,
+        probed: Vec<u32>,
+    }
+
+    impl ScriptedDriver {
+        fn script(mut self, bitrate: u32, reads: Vec<Vec<CanFrame>>) -> Self {
+            self.reads.insert(bitrate, reads.into());
+            self
+        }
+    }
+
+    #[async_trait]
+    impl CanDriver for ScriptedDriver {
+        async fn enable_timestamp(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        async fn set_bitrate(&mut self, bitrate: u32) -> io::Result<()> {
+            if self.unsupported.contains(&bitrate) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported"));
+            }
+            self.bitrate = Some(bitrate);
+            self.probed.push(bitrate);
+            Ok(())
+        }
+
+        async fn get_bitrate(&self) -> Option<u32> {
+            self.bitrate
+        }
+
+        async fn open_channel(&mut self) -> io::Result<()> {
+            panic!("a bitrate scan must only listen");
+        }
+
+        async fn open_listen_only(&mut self) -> io::Result<()> {
+            self.listening = true;
+            Ok(())
+        }
+
+        async fn send_frame(&mut self, _frame: &CanFrame) -> io::Result<()> {
+            panic!("a bitrate scan must not transmit");
+        }
+
+        async fn read_frames(&mut self) -> io::Result<Vec<CanFrame>> {
+            assert!(self.listening, "read before opening the channel");
+            let bitrate = self.bitrate.unwrap();
+            Ok(self
+                .reads
+                .get_mut(&bitrate)
+                .and_then(VecDeque::pop_front)
+                .unwrap_or_default())
+        }
+
+        async fn close_channel(&mut self) -> io::Result<()> {
+            self.listening = false;
+            Ok(())
+        }
+    }
+
+    fn frames(n: usize) -> Vec<CanFrame> {
+        (0..n)
+            .map(|i| CanFrame::new(0x100 + i as u32, &[i as u8]).unwrap())
+            .collect()
+    }
+
+    fn errors(n: usize) -> Vec<CanFrame> {
+        (0..n)
+            .map(|_| CanFrame::new_error(0x004).unwrap())
+            .collect()
+    }
+
+    fn probe(frames: usize, error_frames: usize) -> BitrateProbe {
+        BitrateProbe {
+            bitrate: 500_000,
+            frames,
+            error_frames,
+        }
+    }
+
+    #[test]
+    fn clean_allows_one_error_frame_per_ten_frames() {
+        assert!(probe(10, 1).is_clean());
+        assert!(probe(1, 0).is_clean());
+        assert!(!probe(10, 2).is_clean());
+        assert!(!probe(0, 0).is_clean());
+        assert!(!probe(0, 3).is_clean());
+    }
+
+    #[tokio::test]
+    async fn probe_stops_once_confirmed() {
+        let mut driver = ScriptedDriver::default()
+            .script(250_000, vec![frames(4), errors(1), frames(6), frames(5)]);
+        let result = probe_bitrate(&mut driver, 250_000, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(result.frames, CONFIRM_FRAMES);
+        assert_eq!(result.error_frames, 1);
+        // The last read was never needed.
+        assert_eq!(driver.reads[&250_000].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn probe_counts_until_the_dwell_time_ends() {
+        let mut driver =
+            ScriptedDriver::default().script(125_000, vec![frames(3), errors(2), frames(1)]);
+        let result = probe_bitrate(&mut driver, 125_000, DWELL).await.unwrap();
+        assert_eq!((result.frames, result.error_frames), (4, 2));
+        assert!(!result.is_clean());
+    }
+
+    #[tokio::test]
+    async fn unsupported_bitrates_probe_as_silent() {
+        let mut driver = ScriptedDriver {
+            unsupported: vec![800_000],
+            ..Default::default()
+        };
+        let result = probe_bitrate(&mut driver, 800_000, DWELL).await.unwrap();
+        assert_eq!((result.frames, result.error_frames), (0, 0));
+        assert!(!driver.listening);
+    }
+
+    #[tokio::test]
+    async fn scan_returns_the_first_confirmed_bitrate() {
+        let mut driver = ScriptedDriver::default()
+            .script(250_000, vec![frames(CONFIRM_FRAMES)])
+            .script(500_000, vec![frames(CONFIRM_FRAMES)]);
+        let found = scan_bitrate(&mut driver, &[125_000, 250_000, 500_000], DWELL)
+            .await
+            .unwrap();
+        assert_eq!(found, Some(250_000));
+        assert_eq!(driver.probed, [125_000, 250_000]);
+        assert!(!driver.listening);
+    }
+
+    #[tokio::test]
+    async fn scan_prefers_the_busiest_clean_bitrate() {
+        let mut driver = ScriptedDriver::default()
+            .script(125_000, vec![frames(2)])
+            // Most frames, but too many error frames to be the right rate.
+            .script(250_000, vec![frames(8), errors(2)])
+            .script(500_000, vec![frames(5)]);
+        let found = scan_bitrate(&mut driver, &[125_000, 250_000, 500_000], DWELL)
+            .await
+            .unwrap();
+        assert_eq!(found, Some(500_000));
+    }
+
+    #[tokio::test]
+    async fn scan_of_a_silent_bus_finds_nothing() {
+        let mut driver = ScriptedDriver::default().script(500_000, vec![errors(3)]);
+        let found = scan_bitrate(&mut driver, &[250_000, 500_000], DWELL)
+            .await
+            .unwrap();
+        assert_eq!(found, None);
+    }
+}
@@ -10,8 +10,21 @@ pub trait CanDriver: Send + Sync {
 
     async fn get_bitrate(&self) -> Option<u32>;
 
+    /// CAN FD data phase bitrate, if the driver has one configured.
+    async fn get_data_bitrate(&self) -> Option<u32> {
+        None
+    }
+
     async fn open_channel(&mut self) -> io::Result<()>;
 
+    /// Open the channel without acknowledging or transmitting frames.
+    async fn open_listen_only(&mut self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Listen-only mode is not supported by this driver",
+        ))
+    }
+
     async fn send_frame(&mut self, frame: &CanFrame) -> io::Result<()>;
 
     async fn read_frames(&mut self) -> io::Result<Vec<CanFrame>>;
@@ -0,0 +1,44 @@
+//! Linux `can_id` encoding (`<linux/can.h>`) shared by drivers and protocols
+//! that carry SocketCAN style identifiers.
+use crosscan::can::CanFrame;
+
+pub(crate) const CAN_EFF_FLAG: u32 = 0x8000_0000;
+pub(crate) const CAN_RTR_FLAG: u32 = 0x4000_0000;
+pub(crate) const CAN_ERR_FLAG: u32 = 0x2000_0000;
+
+pub(crate) const CAN_SFF_MASK: u32 = 0x0000_07FF;
+pub(crate) const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+pub(crate) const CAN_ERR_MASK: u32 = 0x1FFF_FFFF;
+
+/// Encode the identifier and EFF/RTR/ERR flags of `frame` as a `can_id`.
+pub(crate) fn raw_id(frame: &CanFrame) -> u32 {
+    if frame.is_error() {
+        return CAN_ERR_FLAG | (frame.id() & CAN_ERR_MASK);
+    }
+    let mut can_id = if frame.is_extended() {
+        CAN_EFF_FLAG | (frame.id() & CAN_EFF_MASK)
+    } else {
+        frame.id() & CAN_SFF_MASK
+    };
+    if frame.is_rtr() {
+        can_id |= CAN_RTR_FLAG;
+    }
+    can_id
+}
+
+/// Build a frame from a `can_id` and payload. For remote frames `len` is the
+/// requested length and `data` is ignored.
+pub(crate) fn frame_from_raw(can_id: u32, data: &[u8], len: usize) -> std::io::Result<CanFrame> {
+    let extended = can_id & CAN_EFF_FLAG != 0;
+    let frame = if can_id & CAN_ERR_FLAG != 0 {
+        CanFrame::new_error(can_id & CAN_ERR_MASK)
+    } else if can_id & CAN_RTR_FLAG != 0 {
+        let id = can_id & if extended { CAN_EFF_MASK } else { CAN_SFF_MASK };
+        CanFrame::new_remote(id, len.min(8), extended)
+    } else if extended {
+        CanFrame::new_eff(can_id & CAN_EFF_MASK, data)
+    } else {
+        CanFrame::new(can_id & CAN_SFF_MASK, data)
+    };
+    frame.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
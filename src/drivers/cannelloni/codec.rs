@@ -0,0 +1,124 @@
+/// Encoding and decoding of cannelloni UDP packets (protocol version 2).
+///
+/// A packet is a 5 byte header (`version`, `op_code`, `seq_no`, big endian
+/// frame `count`) followed by `count` frames, each a big endian Linux
+/// `can_id`, a length byte (with [`CANFD_FRAME`] set for CAN FD frames, which
+/// then carry an extra flags byte) and the payload.
+use crosscan::can::CanFrame;
+
+use crate::drivers::can_id::{frame_from_raw, raw_id};
+
+pub const CANNELLONI_VERSION: u8 = 2;
+pub const HEADER_LEN: usize = 5;
+
+/// Length byte flag marking a CAN FD frame.
+const CANFD_FRAME: u8 = 0x80;
+const CANFD_BRS: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    Data = 0,
+    Ack = 1,
+    Nack = 2,
+}
+
+/// Bytes `frame` occupies in a packet.
+pub fn encoded_len(frame: &CanFrame) -> usize {
+    let payload = if frame.is_rtr() {
+        0
+    } else {
+        frame.data().len()
+    };
+    let fd_flags = usize::from(frame.data().len() > 8);
+    4 + 1 + fd_flags + payload
+}
+
+/// Append `frame` to a packet body. Frames longer than 8 bytes are sent as
+/// CAN FD, with bitrate switching when `brs` is set.
+pub fn encode_frame(frame: &CanFrame, brs: bool, out: &mut Vec<u8>) -> std::io::Result<()> {
+    let data = frame.data();
+    if data.len() > 64 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("CAN FD payload too long: {} bytes", data.len()),
+        ));
+    }
+
+    out.extend_from_slice(&raw_id(frame).to_be_bytes());
+    if data.len() > 8 {
+        out.push(data.len() as u8 | CANFD_FRAME);
+        out.push(if brs { CANFD_BRS } else { 0 });
+    } else if frame.is_rtr() {
+        out.push(frame.dlc().min(8) as u8);
+    } else {
+        out.push(data.len() as u8);
+    }
+    if !frame.is_rtr() {
+        out.extend_from_slice(data);
+    }
+    Ok(())
+}
+
+/// Write a packet header in front of `count` already encoded frames.
+pub fn encode_header(op_code: OpCode, seq_no: u8, count: u16, out: &mut [u8]) {
+    out[0] = CANNELLONI_VERSION;
+    out[1] = op_code as u8;
+    out[2] = seq_no;
+    out[3..5].copy_from_slice(&count.to_be_bytes());
+}
+
+/// A decoded packet.
+#[derive(Debug)]
+pub struct Packet {
+    pub op_code: u8,
+    pub seq_no: u8,
+    pub frames: Vec<CanFrame>,
+}
+
+/// Decode a datagram. Returns `None` for packets from other protocol
+/// versions; a truncated packet yields the frames decoded before the cut.
+pub fn decode_packet(datagram: &[u8]) -> Option<Packet> {
+    let header = datagram.get(..HEADER_LEN)?;
+    if header[0] != CANNELLONI_VERSION {
+        return None;
+    }
+    let count = u16::from_be_bytes([header[3], header[4]]);
+    let mut packet = Packet {
+        op_code: header[1],
+        seq_no: header[2],
+        frames: Vec::with_capacity(count as usize),
+    };
+
+    let mut rest = &datagram[HEADER_LEN..];
+    for _ in 0..count {
+        let Some((frame, used)) = decode_frame(rest) else {
+            log::debug!("cannelloni: truncated packet {}", packet.seq_no);
+            break;
+        };
+        packet.frames.push(frame);
+        rest = &rest[used..];
+    }
+    Some(packet)
+}
+
+fn decode_frame(bytes: &[u8]) -> Option<(CanFrame, usize)> {
+    let can_id = u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?);
+    let len_byte = *bytes.get(4)?;
+    let (len, header) = if len_byte & CANFD_FRAME != 0 {
+        // The FD flags byte (BRS/ESI) has no place on CanFrame.
+        bytes.get(5)?;
+        ((len_byte & !CANFD_FRAME) as usize, 6)
+    } else {
+        (len_byte as usize, 5)
+    };
+    if len > 64 {
+        return None;
+    }
+
+    let rtr = can_id & crate::drivers::can_id::CAN_RTR_FLAG != 0;
+    let payload = if rtr { 0 } else { len };
+    let data = bytes.get(header..header + payload)?;
+    let frame = frame_from_raw(can_id, data, len).ok()?;
+    Some((frame, header + payload))
+}
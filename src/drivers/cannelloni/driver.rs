@@ -0,0 +1,441 @@
+use async_trait::async_trait;
+use crosscan::can::CanFrame;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+use super::codec::{HEADER_LEN, OpCode, decode_packet, encode_frame, encode_header, encoded_len};
+use crate::drivers::{CanDriver, RECEIVE_WAIT, timestamp_us};
+
+/// Largest datagram we send or expect; cannelloni's default MTU payload.
+const MAX_PACKET_LEN: usize = 1472;
+
+/// Connection settings for a [`CannelloniDriver`].
+#[derive(Debug, Clone)]
+pub struct CannelloniOptions {
+    /// Local UDP address to bind, e.g. `0.0.0.0:20000`.
+    pub local: SocketAddr,
+    /// Peer to send to; packets from anywhere else are ignored. When `None`
+    /// the driver waits for a peer and replies to the address of the last
+    /// packet received.
+    pub remote: Option<SocketAddr>,
+    /// How long frames may wait to be batched into one packet. Zero sends
+    /// every frame in its own packet.
+    pub batch_timeout: Duration,
+}
+
+impl Default for CannelloniOptions {
+    fn default() -> Self {
+        Self {
+            local: SocketAddr::from(([0, 0, 0, 0], 20000)),
+            remote: None,
+            batch_timeout: Duration::from_millis(1),
+        }
+    }
+}
+
+/// Counters describing the health of the tunnel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CannelloniStats {
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    /// Packets missing according to the peer's sequence numbers.
+    pub packets_lost: u64,
+    /// Datagrams that were not valid cannelloni packets.
+    pub malformed_packets: u64,
+}
+
+/// CAN over UDP using the cannelloni protocol. Both ends are peers; either
+/// may be given the other's address, or learn it from incoming traffic.
+pub struct CannelloniDriver {
+    options: CannelloniOptions,
+    socket: Option<UdpSocket>,
+    peer: Option<SocketAddr>,
+    /// Frames waiting to be sent, encoded after a reserved header.
+    batch: Vec<u8>,
+    batch_count: u16,
+    batch_started: Option<Instant>,
+    tx_seq: u8,
+    rx_seq: Option<u8>,
+    stats: CannelloniStats,
+    timestamps: bool,
+    configured_bitrate: Option<u32>,
+    configured_data_bitrate: Option<u32>,
+}
+
+impl CannelloniDriver {
+    pub async fn open(options: CannelloniOptions) -> std::io::Result<Self> {
+        Ok(Self {
+            peer: options.remote,
+            options,
+            socket: None,
+            batch: vec![0u8; HEADER_LEN],
+            batch_count: 0,
+            batch_started: None,
+            tx_seq: 0,
+            rx_seq: None,
+            stats: CannelloniStats::default(),
+            timestamps: false,
+            configured_bitrate: None,
+            configured_data_bitrate: None,
+        })
+    }
+
+    pub fn stats(&self) -> CannelloniStats {
+        self.stats
+    }
+
+    /// The peer frames are sent to, once known.
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket()?.local_addr()
+    }
+
+    /// Record the data bitrate of the remote bus; CAN FD frames are then marked for bitrate switching.
+    pub fn set_data_bitrate(&mut self, data_bitrate: u32) -> std::io::Result<()> {
+        self.configured_data_bitrate = Some(data_bitrate);
+        Ok(())
+    }
+
+    fn socket(&self) -> std::io::Result<&UdpSocket> {
+        self.socket.as_ref().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "cannelloni channel is not open",
+            )
+        })
+    }
+
+    /// Send the pending batch, if any.
+    async fn flush(&mut self) -> std::io::Result<()> {
+        if self.batch_count == 0 {
+            return Ok(());
+        }
+        let Some(peer) = self.peer else {
+            log::debug!(
+                "cannelloni: no peer yet, dropping {} frames",
+                self.batch_count
+            );
+            self.reset_batch();
+            return Ok(());
+        };
+
+        encode_header(
+            OpCode::Data,
+            self.tx_seq,
+            self.batch_count,
+            &mut self.batch[..HEADER_LEN],
+        );
+        let result = self.socket()?.send_to(&self.batch, peer).await;
+        self.tx_seq = self.tx_seq.wrapping_add(1);
+        self.reset_batch();
+        result?;
+        self.stats.packets_sent += 1;
+        Ok(())
+    }
+
+    fn reset_batch(&mut self) {
+        self.batch.truncate(HEADER_LEN);
+        self.batch_count = 0;
+        self.batch_started = None;
+    }
+
+    fn batch_due(&self) -> bool {
+        self.batch_started
+            .is_some_and(|started| started.elapsed() >= self.options.batch_timeout)
+    }
+
+    fn handle_datagram(&mut self, datagram: &[u8], from: SocketAddr, frames: &mut Vec<CanFrame>) {
+        // With a configured peer, as in cannelloni, nobody else may inject traffic.
+        if let Some(remote) = self.options.remote
+            && from != remote
+        {
+            log::debug!("cannelloni: ignoring packet from {}", from);
+            return;
+        }
+        let Some(packet) = decode_packet(datagram) else {
+            self.stats.malformed_packets += 1;
+            return;
+        };
+        if packet.op_code != OpCode::Data as u8 {
+            return;
+        }
+
+        if self.options.remote.is_none() && self.peer != Some(from) {
+            log::info!("cannelloni: peer {}", from);
+            self.peer = Some(from);
+            self.rx_seq = None;
+        }
+
+        if let Some(last) = self.rx_seq {
+            let gap = packet.seq_no.wrapping_sub(last).wrapping_sub(1);
+            // A large gap is more likely a restarted peer or reordering than loss.
+            if gap != 0 && gap < 128 {
+                log::warn!(
+                    "cannelloni: {} packet(s) lost before {}",
+                    gap,
+                    packet.seq_no
+                );
+                self.stats.packets_lost += u64::from(gap);
+            }
+        }
+        self.rx_seq = Some(packet.seq_no);
+        self.stats.packets_received += 1;
+
+        let now = timestamp_us();
+        for mut frame in packet.frames {
+            if self.timestamps {
+                frame.set_timestamp(Some(now));
+            }
+            frames.push(frame);
+        }
+    }
+}
+
+/// Windows reports an ICMP port unreachable for an earlier datagram as a
+/// failed receive (WSAECONNRESET); the peer not listening yet is no reason
+/// to stop receiving.
+fn peer_unreachable(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionRefused
+    )
+}
+
+#[async_trait]
+impl CanDriver for CannelloniDriver {
+    async fn enable_timestamp(&mut self) -> std::io::Result<()> {
+        self.timestamps = true;
+        Ok(())
+    }
+
+    async fn set_bitrate(&mut self, bitrate: u32) -> std::io::Result<()> {
+        // The remote end owns the bus; the bitrate is informational only.
+        self.configured_bitrate = Some(bitrate);
+        Ok(())
+    }
+
+    async fn get_bitrate(&self) -> Option<u32> {
+        self.configured_bitrate
+    }
+
+    async fn get_data_bitrate(&self) -> Option<u32> {
+        self.configured_data_bitrate
+    }
+
+    async fn open_channel(&mut self) -> std::io::Result<()> {
+        if self.socket.is_none() {
+            self.socket = Some(UdpSocket::bind(self.options.local).await?);
+        }
+        Ok(())
+    }
+
+    async fn send_frame(&mut self, frame: &CanFrame) -> std::io::Result<()> {
+        self.socket()?;
+        if self.batch.len() + encoded_len(frame) > MAX_PACKET_LEN || self.batch_count == u16::MAX {
+            self.flush().await?;
+        }
+
+        let brs = self.configured_data_bitrate.is_some();
+        encode_frame(frame, brs, &mut self.batch)?;
+        self.batch_count += 1;
+        self.batch_started.get_or_insert_with(Instant::now);
+
+        if self.options.batch_timeout.is_zero() {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn read_frames(&mut self) -> std::io::Result<Vec<CanFrame>> {
+        if self.batch_due() {
+            self.flush().await?;
+        }
+
+        let mut frames = Vec::new();
+        let mut buf = [0u8; MAX_PACKET_LEN];
+        // Wait briefly for the first datagram, then take whatever else is queued.
+        let wait = if self.batch_count > 0 {
+            self.options.batch_timeout.min(RECEIVE_WAIT)
+        } else {
+            RECEIVE_WAIT
+        };
+        let socket = self.socket()?;
+        let mut received = tokio::time::timeout(wait, socket.recv_from(&mut buf))
+            .await
+            .ok();
+        while let Some(result) = received {
+            match result {
+                Ok((n, from)) => self.handle_datagram(&buf[..n], from, &mut frames),
+                Err(e) if peer_unreachable(&e) => {
+                    log::debug!("cannelloni: peer unreachable: {}", e);
+                }
+                Err(e) if frames.is_empty() => return Err(e),
+                Err(e) => {
+                    log::warn!("cannelloni: receive failed: {}", e);
+                    break;
+                }
+            }
+            received = match self.socket()?.try_recv_from(&mut buf) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => None,
+                result => Some(result),
+            };
+        }
+
+        if self.batch_due()
+            && let Err(e) = self.flush().await
+        {
+            if frames.is_empty() {
+                return Err(e);
+            }
+            log::warn!("cannelloni: send failed: {}", e);
+        }
+        Ok(frames)
+    }
+
+    async fn close_channel(&mut self) -> std::io::Result<()> {
+        if self.socket.is_some() {
+            self.flush().await?;
+        }
+        self.socket = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn open(remote: Option<SocketAddr>, batch_timeout: Duration) -> CannelloniDriver {
+        open_at(SocketAddr::from(([127, 0, 0, 1], 0)), remote, batch_timeout).await
+    }
+
+    async fn open_at(
+        local: SocketAddr,
+        remote: Option<SocketAddr>,
+        batch_timeout: Duration,
+    ) -> CannelloniDriver {
+        let options = CannelloniOptions {
+            local,
+            remote,
+            batch_timeout,
+        };
+        let mut driver = CannelloniDriver::open(options).await.unwrap();
+        driver.enable_timestamp().await.unwrap();
+        driver.open_channel().await.unwrap();
+        driver
+    }
+
+    /// A loopback address nothing listens on.
+    fn unused_address() -> SocketAddr {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.local_addr().unwrap()
+    }
+
+    async fn receive(driver: &mut CannelloniDriver, count: usize) -> Vec<CanFrame> {
+        let mut frames = Vec::new();
+        for _ in 0..100 {
+            frames.extend(driver.read_frames().await.unwrap());
+            if frames.len() >= count {
+                break;
+            }
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn two_instances_exchange_frames() {
+        let mut a = open(None, Duration::ZERO).await;
+        let mut b = open(Some(a.local_addr().unwrap()), Duration::from_millis(1)).await;
+
+        // b batches its frames into one packet; a learns b as its peer from it.
+        b.send_frame(&CanFrame::new(0x123, &[1, 2, 3]).unwrap())
+            .await
+            .unwrap();
+        b.send_frame(&CanFrame::new_eff(0x1ABCDEF, &[]).unwrap())
+            .await
+            .unwrap();
+        b.send_frame(&CanFrame::new_remote(0x7FF, 4, false).unwrap())
+            .await
+            .unwrap();
+        assert!(b.read_frames().await.unwrap().is_empty());
+
+        let frames = receive(&mut a, 3).await;
+        assert_eq!(frames.len(), 3);
+        assert_eq!((frames[0].id(), frames[0].data()), (0x123, &[1, 2, 3][..]));
+        assert!(frames[0].timestamp().is_some());
+        assert!(frames[1].is_extended() && frames[1].id() == 0x1ABCDEF);
+        assert!(frames[2].is_rtr() && frames[2].dlc() == 4);
+        assert_eq!(a.peer(), Some(b.local_addr().unwrap()));
+
+        a.send_frame(&CanFrame::new(0x456, &[4]).unwrap())
+            .await
+            .unwrap();
+        let frames = receive(&mut b, 1).await;
+        assert_eq!((frames[0].id(), frames[0].data()), (0x456, &[4][..]));
+
+        assert_eq!(b.stats().packets_sent, 1);
+        assert_eq!(a.stats().packets_received, 1);
+        assert_eq!(a.stats().packets_sent, 1);
+        assert_eq!(b.stats().packets_received, 1);
+        assert_eq!(a.stats().packets_lost + b.stats().packets_lost, 0);
+
+        a.close_channel().await.unwrap();
+        b.close_channel().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_receiving_after_sending_to_a_closed_port() {
+        let closed = unused_address();
+        let mut a = open(Some(closed), Duration::ZERO).await;
+
+        // On Windows the port unreachable reply fails the next receive.
+        a.send_frame(&CanFrame::new(0x100, &[]).unwrap())
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // The peer comes up on the port afterwards.
+        let mut b = open_at(closed, Some(a.local_addr().unwrap()), Duration::ZERO).await;
+        b.send_frame(&CanFrame::new(0x200, &[]).unwrap())
+            .await
+            .unwrap();
+
+        let frames = receive(&mut a, 1).await;
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].id(), 0x200);
+    }
+
+    #[test]
+    fn only_unreachable_peers_are_ignored() {
+        use std::io::{Error, ErrorKind};
+        assert!(peer_unreachable(&Error::from(ErrorKind::ConnectionReset)));
+        assert!(peer_unreachable(&Error::from(ErrorKind::ConnectionRefused)));
+        assert!(!peer_unreachable(&Error::from(ErrorKind::PermissionDenied)));
+    }
+
+    #[tokio::test]
+    async fn ignores_packets_from_other_hosts_when_the_peer_is_given() {
+        let a_addr = unused_address();
+        let mut peer = open(Some(a_addr), Duration::ZERO).await;
+        let mut stranger = open(Some(a_addr), Duration::ZERO).await;
+        let mut a = open_at(a_addr, Some(peer.local_addr().unwrap()), Duration::ZERO).await;
+
+        stranger
+            .send_frame(&CanFrame::new(0x666, &[]).unwrap())
+            .await
+            .unwrap();
+        peer.send_frame(&CanFrame::new(0x123, &[]).unwrap())
+            .await
+            .unwrap();
+
+        let frames = receive(&mut a, 2).await;
+        let ids: Vec<u32> = frames.iter().map(|frame| frame.id()).collect();
+        assert_eq!(ids, [0x123]);
+        assert_eq!(a.stats().packets_received, 1);
+        assert_eq!(a.stats().malformed_packets, 0);
+        assert_eq!(a.peer(), Some(peer.local_addr().unwrap()));
+    }
+}
@@ -0,0 +1,5 @@
+/// CAN over UDP tunnelling compatible with cannelloni.
+pub mod codec;
+mod driver;
+
+pub use driver::{CannelloniDriver, CannelloniOptions, CannelloniStats};
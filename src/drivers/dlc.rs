@@ -0,0 +1,31 @@
+//! CAN FD data length code helpers shared by the drivers.
+
+/// Map a CAN FD DLC code (0..=15) to its payload length in bytes.
+pub(crate) fn fd_dlc_to_len(dlc: u8) -> Option<usize> {
+    Some(match dlc {
+        0..=8 => dlc as usize,
+        9 => 12,
+        10 => 16,
+        11 => 20,
+        12 => 24,
+        13 => 32,
+        14 => 48,
+        15 => 64,
+        _ => return None,
+    })
+}
+
+/// Map a payload length to the smallest CAN FD DLC code that can carry it.
+pub(crate) fn fd_len_to_dlc(len: usize) -> Option<u8> {
+    Some(match len {
+        0..=8 => len as u8,
+        9..=12 => 9,
+        13..=16 => 10,
+        17..=20 => 11,
+        21..=24 => 12,
+        25..=32 => 13,
+        33..=48 => 14,
+        49..=64 => 15,
+        _ => return None,
+    })
+}
@@ -0,0 +1,305 @@
+/// Decoding of ELM327 / STN11xx monitor output and helpers for the AT
+/// commands used to configure raw CAN access.
+///
+/// The adapter is run with headers (`ATH1`), spaces (`ATS1`) and DLC display
+/// (`ATD1`) on, so a monitored frame looks like `7E8 8 02 41 0D 00 00 00 00 00`
+/// for an 11 bit id or `18 DA F1 10 8 02 41 0D 00 00 00 00 00` for a 29 bit id.
+use crosscan::can::CanFrame;
+
+/// The prompt printed when the adapter is ready for a command.
+const PROMPT: u8 = b'>';
+
+/// Longest line kept; anything longer is not monitor output.
+const MAX_LINE_LEN: usize = 128;
+
+/// Something decoded from the adapter's byte stream.
+#[derive(Debug, Clone)]
+pub enum Elm327Event {
+    /// A monitored CAN frame.
+    Frame(CanFrame),
+    /// The `>` prompt: the adapter finished a command or stopped monitoring.
+    Prompt,
+    /// The receive buffer overflowed and monitoring stopped.
+    BufferFull,
+    /// Any other line, e.g. a command reply or `CAN ERROR`.
+    Line(String),
+}
+
+/// Incremental decoder turning chunks of serial data into [`Elm327Event`]s.
+#[derive(Debug, Default)]
+pub struct Elm327Decoder {
+    line: Vec<u8>,
+    /// Lines that looked like frames but failed to parse.
+    malformed_lines: u64,
+}
+
+impl Elm327Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn malformed_lines(&self) -> u64 {
+        self.malformed_lines
+    }
+
+    /// Drop any partially received line.
+    pub fn reset(&mut self) {
+        self.line.clear();
+    }
+
+    /// Feed raw bytes into the decoder, appending decoded events to `events`.
+    pub fn push(&mut self, bytes: &[u8], events: &mut Vec<Elm327Event>) {
+        for &b in bytes {
+            match b {
+                b'\r' | b'\n' => self.finish_line(events),
+                PROMPT => {
+                    self.finish_line(events);
+                    events.push(Elm327Event::Prompt);
+                }
+                // Some clones pad their output with NUL bytes.
+                0 => {}
+                _ if self.line.len() < MAX_LINE_LEN => self.line.push(b),
+                _ => {}
+            }
+        }
+    }
+
+    fn finish_line(&mut self, events: &mut Vec<Elm327Event>) {
+        if self.line.is_empty() {
+            return;
+        }
+        let line = String::from_utf8_lossy(&self.line).trim().to_string();
+        self.line.clear();
+        if line.is_empty() {
+            return;
+        }
+
+        if line == "BUFFER FULL" {
+            events.push(Elm327Event::BufferFull);
+        } else if let Some(frame) = parse_monitor_line(&line) {
+            events.push(Elm327Event::Frame(frame));
+        } else {
+            let first = line.split_whitespace().next().unwrap_or_default();
+            if line.contains(' ') && first.bytes().all(|b| b.is_ascii_hexdigit()) {
+                self.malformed_lines += 1;
+                log::debug!("elm327: malformed monitor line {:?}", line);
+            }
+            events.push(Elm327Event::Line(line));
+        }
+    }
+}
+
+/// Parse one monitor line printed with headers, spaces and DLC display on.
+/// Remote frames show `RTR` in place of their data.
+pub fn parse_monitor_line(line: &str) -> Option<CanFrame> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let (id, extended, rest) = match tokens.as_slice() {
+        [id, rest @ ..] if id.len() == 3 => (parse_hex(id)?, false, rest),
+        [a, b, c, d, rest @ ..] if [a, b, c, d].iter().all(|t| t.len() == 2) => {
+            (parse_hex(&format!("{}{}{}{}", a, b, c, d))?, true, rest)
+        }
+        _ => return None,
+    };
+    if id > if extended { 0x1FFF_FFFF } else { 0x7FF } {
+        return None;
+    }
+
+    let [dlc, data @ ..] = rest else {
+        return None;
+    };
+    if dlc.len() != 1 {
+        return None;
+    }
+    let dlc = parse_hex(dlc)? as usize;
+    if dlc > 8 {
+        return None;
+    }
+
+    if let ["RTR"] = data {
+        return CanFrame::new_remote(id, dlc, extended).ok();
+    }
+    if data.len() != dlc || data.iter().any(|t| t.len() != 2) {
+        return None;
+    }
+    let data = data
+        .iter()
+        .map(|t| u8::from_str_radix(t, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    if extended {
+        CanFrame::new_eff(id, &data).ok()
+    } else {
+        CanFrame::new(id, &data).ok()
+    }
+}
+
+/// Options and baud rate divisor for `ATPB` selecting raw CAN on the user
+/// defined protocol B: variable DLC, both id lengths received, no ISO 15765
+/// formatting. `extended` selects the id length used for transmission.
+pub fn protocol_b_config(bitrate: u32, extended: bool) -> std::io::Result<(u8, u8)> {
+    // Divisor of the 500 kbit/s base rate.
+    let divisor = if bitrate > 0 && 500_000 % bitrate == 0 {
+        500_000 / bitrate
+    } else {
+        0
+    };
+    if divisor == 0 || divisor > 0xFF {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Unsupported ELM327 bitrate: {} (must divide 500000)",
+                bitrate
+            ),
+        ));
+    }
+
+    let id_11_bit = if extended { 0 } else { 0x80 };
+    let options = id_11_bit | 0x40 | 0x20;
+    Ok((options, divisor as u8))
+}
+
+/// Header commands for a transmission. Returns `ATCP` (29 bit ids only) and
+/// `ATSH` arguments.
+pub fn header_commands(frame: &CanFrame) -> (Option<String>, String) {
+    if frame.is_extended() {
+        (
+            Some(format!("ATCP{:02X}", frame.id() >> 24)),
+            format!("ATSH{:06X}", frame.id() & 0x00FF_FFFF),
+        )
+    } else {
+        (None, format!("ATSH{:03X}", frame.id()))
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `ATMA` output as printed with `ATH1 ATS1 ATD1`: OBD-II traffic with
+    /// both id lengths, a remote frame, a truncated line and error reports.
+    const CAPTURE: &[u8] = b"7E8 8 02 41 0D 00 00 00 00 00 \r\
+        18 DA F1 10 8 03 41 0C 1A F8 00 00 00 \r\
+        7DF 8 02 01 0D 00 00 00 00 00 \r\
+        123 4 RTR\r\
+        7E8 8 02 41\r\
+        CAN ERROR\r\
+        BUFFER FULL\r\r>";
+
+    fn decode(chunks: &[&[u8]]) -> (Vec<Elm327Event>, Elm327Decoder) {
+        let mut decoder = Elm327Decoder::new();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            decoder.push(chunk, &mut events);
+        }
+        (events, decoder)
+    }
+
+    fn frame(event: &Elm327Event) -> &CanFrame {
+        match event {
+            Elm327Event::Frame(frame) => frame,
+            other => panic!("expected a frame, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decodes_a_monitor_capture() {
+        let (events, decoder) = decode(&[CAPTURE]);
+        assert_eq!(events.len(), 8);
+
+        let standard = frame(&events[0]);
+        assert!(!standard.is_extended());
+        assert_eq!(standard.id(), 0x7E8);
+        assert_eq!(standard.data(), &[0x02, 0x41, 0x0D, 0, 0, 0, 0, 0]);
+
+        let extended = frame(&events[1]);
+        assert!(extended.is_extended());
+        assert_eq!(extended.id(), 0x18DA_F110);
+        assert_eq!(extended.data()[..4], [0x03, 0x41, 0x0C, 0x1A]);
+
+        assert_eq!(frame(&events[2]).id(), 0x7DF);
+        let remote = frame(&events[3]);
+        assert!(remote.is_rtr() && remote.id() == 0x123 && remote.dlc() == 4);
+
+        // The short line is reported, and counted as a garbled frame.
+        assert!(matches!(&events[4], Elm327Event::Line(line) if line == "7E8 8 02 41"));
+        assert!(matches!(&events[5], Elm327Event::Line(line) if line == "CAN ERROR"));
+        assert!(matches!(events[6], Elm327Event::BufferFull));
+        assert!(matches!(events[7], Elm327Event::Prompt));
+        assert_eq!(decoder.malformed_lines(), 1);
+    }
+
+    #[test]
+    fn any_split_decodes_the_same() {
+        let whole = format!("{:?}", decode(&[CAPTURE]).0);
+        for split in 0..=CAPTURE.len() {
+            let (events, _) = decode(&[&CAPTURE[..split], &CAPTURE[split..]]);
+            assert_eq!(format!("{:?}", events), whole, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn prompts_end_partial_lines() {
+        // Interrupting ATMA prints STOPPED; a command echo may be cut off by the prompt.
+        let (events, _) = decode(&[b"7E8 1 55\r\nSTOPPED\r\n\r\n>", b"\0\0OK>"]);
+        assert_eq!(frame(&events[0]).data(), &[0x55]);
+        assert!(matches!(&events[1], Elm327Event::Line(line) if line == "STOPPED"));
+        assert!(matches!(events[2], Elm327Event::Prompt));
+        assert!(matches!(&events[3], Elm327Event::Line(line) if line == "OK"));
+        assert!(matches!(events[4], Elm327Event::Prompt));
+        assert_eq!(events.len(), 5);
+    }
+
+    #[test]
+    fn rejects_out_of_range_lines() {
+        for line in [
+            "800 1 00",
+            "20 00 00 00 1 00",
+            "7E8 9 00 00 00 00 00 00 00 00 00",
+            "7E8 2 00 000",
+            "7E8 2 00 0G",
+            "7E8",
+            "SEARCHING...",
+        ] {
+            assert!(parse_monitor_line(line).is_none(), "{}", line);
+        }
+        let frame = parse_monitor_line("1F FF FF FF 0").unwrap();
+        assert!(frame.is_extended() && frame.id() == 0x1FFF_FFFF && frame.data().is_empty());
+    }
+
+    #[test]
+    fn overlong_lines_are_cut() {
+        let mut long = vec![b'A'; MAX_LINE_LEN + 50];
+        long.push(b'\r');
+        let (events, _) = decode(&[&long]);
+        assert!(matches!(&events[0], Elm327Event::Line(line) if line.len() == MAX_LINE_LEN));
+    }
+
+    #[test]
+    fn protocol_b_divides_the_500k_base_rate() {
+        // Variable DLC, both id lengths received, no ISO 15765 formatting.
+        assert_eq!(protocol_b_config(500_000, false).unwrap(), (0xE0, 1));
+        assert_eq!(protocol_b_config(500_000, true).unwrap(), (0x60, 1));
+        assert_eq!(protocol_b_config(250_000, false).unwrap(), (0xE0, 2));
+        assert_eq!(protocol_b_config(125_000, false).unwrap(), (0xE0, 4));
+        assert_eq!(protocol_b_config(50_000, false).unwrap(), (0xE0, 10));
+        assert_eq!(protocol_b_config(2_000, false).unwrap(), (0xE0, 250));
+        for bitrate in [0, 1_000, 300_000, 1_000_000] {
+            assert!(protocol_b_config(bitrate, false).is_err(), "{}", bitrate);
+        }
+    }
+
+    #[test]
+    fn headers_for_both_id_lengths() {
+        let standard = CanFrame::new(0x7DF, &[]).unwrap();
+        assert_eq!(header_commands(&standard), (None, "ATSH7DF".to_string()));
+        let extended = CanFrame::new_eff(0x18DB_33F1, &[]).unwrap();
+        assert_eq!(
+            header_commands(&extended),
+            (Some("ATCP18".to_string()), "ATSHDB33F1".to_string())
+        );
+    }
+}
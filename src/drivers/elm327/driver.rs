@@ -0,0 +1,522 @@
+/// Provides the Elm327Driver that drives an ELM327 / STN11xx OBD adapter as a
+/// raw CAN interface.
+use async_trait::async_trait;
+use crosscan::can::CanFrame;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf, split};
+use tokio::time::{Instant, timeout};
+use tokio_serial::SerialStream;
+
+use super::codec::{Elm327Decoder, Elm327Event, header_commands, protocol_b_config};
+use crate::drivers::{CanDriver, RECEIVE_WAIT, timestamp_us};
+
+/// Factory default serial baud rate of ELM327 adapters.
+pub const ELM327_BAUD_RATE: u32 = 38_400;
+
+/// Time allowed for an AT command to return to the prompt.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// `ATZ` restarts the adapter, which takes noticeably longer.
+const RESET_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Bitrate used until one is set, the most common OBD-II rate.
+const DEFAULT_BITRATE: u32 = 500_000;
+
+/// Error class reported when the adapter's buffer overflowed (<linux/can/error.h>).
+const CAN_ERR_CRTL: u32 = 0x0000_0004;
+
+/// ELM327 compatible OBD adapter used as a raw CAN interface.
+///
+/// The adapter is put in monitor mode (`ATMA`, or `STMA` on STN11xx chips)
+/// to receive. ELM327s cannot receive while transmitting, so every transmission
+/// interrupts monitoring, sets the header with `ATSH` and sends the data;
+/// frames on the bus during that gap are lost.
+///
+/// Serial adapters are opened with [`Elm327Driver::open`]; any other stream
+/// carrying the adapter's byte protocol, such as the TCP socket of a WiFi
+/// adapter, can be used with [`Elm327Driver::from_stream`].
+pub struct Elm327Driver<S = SerialStream> {
+    reader: ReadHalf<S>,
+    writer: WriteHalf<S>,
+    decoder: Elm327Decoder,
+    /// Frames received while waiting for a command to complete.
+    pending: Vec<CanFrame>,
+    monitoring: bool,
+    stn: bool,
+    version: Option<String>,
+    listen_only: bool,
+    /// Id length protocol B is configured to transmit, once initialized.
+    tx_extended: Option<bool>,
+    /// Last `ATCP`/`ATSH` commands sent.
+    header: Option<(Option<String>, String)>,
+    buffer_overflows: u64,
+    timestamps: bool,
+    configured_bitrate: Option<u32>,
+}
+
+impl Elm327Driver {
+    /// Open the adapter's serial port. The adapter is configured when the
+    /// channel is opened.
+    pub async fn open(port_name: &str, baud_rate: u32) -> std::io::Result<Self> {
+        let port = SerialStream::open(&tokio_serial::new(port_name, baud_rate))?;
+        Ok(Self::from_stream(port))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Elm327Driver<S> {
+    /// Talk to an adapter over `stream`. The adapter is configured when the
+    /// channel is opened.
+    pub fn from_stream(stream: S) -> Self {
+        let (reader, writer) = split(stream);
+        Self {
+            reader,
+            writer,
+            decoder: Elm327Decoder::new(),
+            pending: Vec::new(),
+            monitoring: false,
+            stn: false,
+            version: None,
+            listen_only: false,
+            tx_extended: None,
+            header: None,
+            buffer_overflows: 0,
+            timestamps: false,
+            configured_bitrate: None,
+        }
+    }
+
+    /// Identification reported by `ATZ`, e.g. "ELM327 v1.5", once opened.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Whether the adapter identified itself as an STN11xx.
+    pub fn is_stn(&self) -> bool {
+        self.stn
+    }
+
+    /// Number of times monitoring stopped with `BUFFER FULL`.
+    pub fn buffer_overflows(&self) -> u64 {
+        self.buffer_overflows
+    }
+
+    async fn read_events(&mut self, wait: Duration) -> std::io::Result<Vec<Elm327Event>> {
+        let mut buf = [0u8; 1024];
+        let mut events = Vec::new();
+        let n = match timeout(wait, self.reader.read(&mut buf)).await {
+            Ok(result) => result?,
+            Err(_) => return Ok(events),
+        };
+        if n == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "ELM327 serial port closed",
+            ));
+        }
+        self.decoder.push(&buf[..n], &mut events);
+        Ok(events)
+    }
+
+    /// Wait for the prompt, keeping any frames that arrive meanwhile.
+    /// Returns the other lines printed.
+    async fn wait_prompt(&mut self, limit: Duration) -> std::io::Result<Vec<String>> {
+        let deadline = Instant::now() + limit;
+        let mut lines = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Timeout waiting for the ELM327 prompt",
+                ));
+            }
+            for event in self.read_events(remaining).await? {
+                match event {
+                    Elm327Event::Prompt => return Ok(lines),
+                    Elm327Event::Frame(frame) => {
+                        let frame = self.stamp(frame);
+                        self.pending.push(frame);
+                    }
+                    Elm327Event::BufferFull => self.note_overflow(),
+                    Elm327Event::Line(line) => lines.push(line),
+                }
+            }
+        }
+    }
+
+    /// Send a command and return the lines printed before the prompt.
+    async fn command_with_timeout(
+        &mut self,
+        command: &str,
+        limit: Duration,
+    ) -> std::io::Result<Vec<String>> {
+        self.stop_monitor().await?;
+        self.writer.write_all(command.as_bytes()).await?;
+        self.writer.write_all(b"\r").await?;
+        self.writer.flush().await?;
+        let lines = self.wait_prompt(limit).await?;
+        log::trace!("elm327: {} -> {:?}", command, lines);
+        Ok(lines)
+    }
+
+    /// Send a command that the adapter must accept.
+    async fn command(&mut self, command: &str) -> std::io::Result<Vec<String>> {
+        let lines = self.command_with_timeout(command, COMMAND_TIMEOUT).await?;
+        if let Some(error) = lines
+            .iter()
+            .find(|line| matches!(line.as_str(), "?" | "CAN ERROR" | "BUS ERROR" | "ERROR"))
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("ELM327 rejected {}: {}", command, error),
+            ));
+        }
+        Ok(lines)
+    }
+
+    async fn start_monitor(&mut self) -> std::io::Result<()> {
+        let command: &[u8] = if self.stn { b"STMA\r" } else { b"ATMA\r" };
+        self.writer.write_all(command).await?;
+        self.writer.flush().await?;
+        self.monitoring = true;
+        Ok(())
+    }
+
+    /// Any character interrupts monitoring; the adapter then prints the prompt.
+    async fn stop_monitor(&mut self) -> std::io::Result<()> {
+        if !self.monitoring {
+            return Ok(());
+        }
+        self.writer.write_all(b"\r").await?;
+        self.writer.flush().await?;
+        self.monitoring = false;
+        self.wait_prompt(COMMAND_TIMEOUT).await?;
+        Ok(())
+    }
+
+    /// Reset the adapter and configure it for raw CAN on protocol B.
+    async fn initialize(&mut self, listen_only: bool) -> std::io::Result<()> {
+        let (options, divisor) =
+            protocol_b_config(self.configured_bitrate.unwrap_or(DEFAULT_BITRATE), false)?;
+
+        // Abandon whatever the adapter was doing; a partial command line is
+        // answered with `?` and a prompt.
+        self.monitoring = false;
+        self.writer.write_all(b"\r").await?;
+        self.writer.flush().await?;
+        let _ = self.wait_prompt(COMMAND_TIMEOUT).await;
+        self.decoder.reset();
+        self.pending.clear();
+
+        let reply = self.command_with_timeout("ATZ", RESET_TIMEOUT).await?;
+        self.version = reply.into_iter().find(|line| line.starts_with("ELM"));
+        for setup in [
+            "ATE0", "ATL0", "ATS1", "ATH1", "ATD1", "ATCAF0", "ATAL", "ATR0",
+        ] {
+            self.command(setup).await?;
+        }
+        self.command(&format!("ATPB{:02X}{:02X}", options, divisor))
+            .await?;
+        self.command("ATSPB").await?;
+        // Silent monitoring keeps the adapter from acknowledging frames.
+        self.command(if listen_only { "ATCSM1" } else { "ATCSM0" })
+            .await?;
+
+        let id = self.command_with_timeout("STI", COMMAND_TIMEOUT).await?;
+        self.stn = id.iter().any(|line| line.starts_with("STN"));
+        if let Some(stn) = id.iter().find(|line| line.starts_with("STN")) {
+            self.version = Some(stn.clone());
+        }
+
+        self.listen_only = listen_only;
+        self.tx_extended = Some(false);
+        self.header = None;
+        self.start_monitor().await
+    }
+
+    fn stamp(&self, mut frame: CanFrame) -> CanFrame {
+        if self.timestamps {
+            frame.set_timestamp(Some(timestamp_us()));
+        }
+        frame
+    }
+
+    fn note_overflow(&mut self) {
+        log::warn!("elm327: adapter buffer full, frames were lost");
+        self.buffer_overflows += 1;
+        self.pending.extend(CanFrame::new_error(CAN_ERR_CRTL).ok());
+    }
+}
+
+#[async_trait]
+impl<S: AsyncRead + AsyncWrite + Send + Sync> CanDriver for Elm327Driver<S> {
+    /// The adapter has no timestamps; frames are stamped on arrival.
+    async fn enable_timestamp(&mut self) -> std::io::Result<()> {
+        self.timestamps = true;
+        Ok(())
+    }
+
+    async fn set_bitrate(&mut self, bitrate: u32) -> std::io::Result<()> {
+        protocol_b_config(bitrate, false)?;
+        self.configured_bitrate = Some(bitrate);
+        Ok(())
+    }
+
+    async fn get_bitrate(&self) -> Option<u32> {
+        self.configured_bitrate
+    }
+
+    async fn open_channel(&mut self) -> std::io::Result<()> {
+        self.initialize(false).await
+    }
+
+    async fn open_listen_only(&mut self) -> std::io::Result<()> {
+        self.initialize(true).await
+    }
+
+    async fn send_frame(&mut self, frame: &CanFrame) -> std::io::Result<()> {
+        let Some(tx_extended) = self.tx_extended else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "ELM327 channel is not open",
+            ));
+        };
+        if self.listen_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "ELM327 channel is open in listen-only mode",
+            ));
+        }
+        if frame.is_error()
+            || frame.data().len() > 8
+            || (!frame.is_rtr() && frame.data().is_empty())
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "ELM327 can only send classic CAN frames with 1 to 8 data bytes or remote frames",
+            ));
+        }
+
+        if tx_extended != frame.is_extended() {
+            let bitrate = self.configured_bitrate.unwrap_or(DEFAULT_BITRATE);
+            let (options, divisor) = protocol_b_config(bitrate, frame.is_extended())?;
+            self.command(&format!("ATPB{:02X}{:02X}", options, divisor))
+                .await?;
+            self.command("ATSPB").await?;
+            self.tx_extended = Some(frame.is_extended());
+        }
+
+        let header = header_commands(frame);
+        if self.header.as_ref() != Some(&header) {
+            if let Some(priority) = &header.0 {
+                self.command(priority).await?;
+            }
+            self.command(&header.1).await?;
+            self.header = Some(header);
+        }
+
+        let result = if frame.is_rtr() {
+            self.command("ATRTR").await.map(|_| ())
+        } else {
+            let data: String = frame.data().iter().map(|b| format!("{:02X}", b)).collect();
+            self.command(&data).await.map(|_| ())
+        };
+        self.start_monitor().await?;
+        result
+    }
+
+    async fn read_frames(&mut self) -> std::io::Result<Vec<CanFrame>> {
+        if self.tx_extended.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "ELM327 channel is not open",
+            ));
+        }
+        if !self.monitoring {
+            self.start_monitor().await?;
+        }
+
+        let mut frames = std::mem::take(&mut self.pending);
+        let wait = if frames.is_empty() {
+            RECEIVE_WAIT
+        } else {
+            Duration::ZERO
+        };
+        for event in self.read_events(wait).await? {
+            match event {
+                Elm327Event::Frame(frame) => frames.push(self.stamp(frame)),
+                // Monitoring ended (e.g. after BUFFER FULL); restart on the next call.
+                Elm327Event::Prompt => self.monitoring = false,
+                Elm327Event::BufferFull => {
+                    self.note_overflow();
+                    frames.append(&mut self.pending);
+                }
+                Elm327Event::Line(line) => log::debug!("elm327: {}", line),
+            }
+        }
+        Ok(frames)
+    }
+
+    async fn close_channel(&mut self) -> std::io::Result<()> {
+        if self.tx_extended.take().is_some() {
+            self.stop_monitor().await?;
+            self.command_with_timeout("ATPC", COMMAND_TIMEOUT).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+    use tokio::sync::mpsc;
+
+    /// A fake ELM327 on the other end of a pipe. Command lines are reported
+    /// on the returned channel. The first `ATMA` prints `monitor`; a prompt
+    /// in it ends monitoring, as `BUFFER FULL` does on a real adapter.
+    fn adapter(
+        monitor: &'static [u8],
+    ) -> (Elm327Driver<DuplexStream>, mpsc::UnboundedReceiver<String>) {
+        let (ours, mut theirs) = tokio::io::duplex(4096);
+        let (commands, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut monitor = Some(monitor);
+            let mut monitoring = false;
+            let mut line = Vec::new();
+            let mut buf = [0u8; 256];
+            loop {
+                let n = match theirs.read(&mut buf).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => n,
+                };
+                for &b in &buf[..n] {
+                    if monitoring {
+                        // Any character stops monitoring.
+                        monitoring = false;
+                        theirs.write_all(b"STOPPED\r\r>").await.unwrap();
+                        continue;
+                    }
+                    if b != b'\r' {
+                        line.push(b);
+                        continue;
+                    }
+                    let command = String::from_utf8(std::mem::take(&mut line)).unwrap();
+                    let reply: &[u8] = match command.as_str() {
+                        "" => b"\r>",
+                        "ATZ" => b"\r\rELM327 v1.5\r\r>",
+                        "STI" => b"?\r\r>",
+                        "ATMA" | "STMA" => match monitor.take() {
+                            Some(output) => {
+                                monitoring = !output.contains(&b'>');
+                                output
+                            }
+                            None => {
+                                monitoring = true;
+                                b""
+                            }
+                        },
+                        _ => b"OK\r\r>",
+                    };
+                    if !command.is_empty() {
+                        let _ = commands.send(command);
+                    }
+                    theirs.write_all(reply).await.unwrap();
+                }
+            }
+        });
+        (Elm327Driver::from_stream(ours), received)
+    }
+
+    /// The next `n` commands the adapter received.
+    async fn next(commands: &mut mpsc::UnboundedReceiver<String>, n: usize) -> Vec<String> {
+        let mut received = Vec::new();
+        for _ in 0..n {
+            received.push(commands.recv().await.unwrap());
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn initialization_configures_raw_can_on_protocol_b() {
+        let (mut driver, mut commands) = adapter(b"");
+        driver.set_bitrate(250_000).await.unwrap();
+        driver.open_channel().await.unwrap();
+
+        assert_eq!(
+            next(&mut commands, 14).await,
+            [
+                "ATZ", "ATE0", "ATL0", "ATS1", "ATH1", "ATD1", "ATCAF0", "ATAL", "ATR0",
+                "ATPBE002", "ATSPB", "ATCSM0", "STI", "ATMA",
+            ]
+        );
+        assert_eq!(driver.version(), Some("ELM327 v1.5"));
+        assert!(!driver.is_stn());
+    }
+
+    #[tokio::test]
+    async fn headers_and_id_length_are_switched_only_when_needed() {
+        let (mut driver, mut commands) = adapter(b"");
+        driver.open_channel().await.unwrap();
+        next(&mut commands, 14).await;
+
+        driver
+            .send_frame(&CanFrame::new(0x7DF, &[0x02, 0x01]).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(next(&mut commands, 3).await, ["ATSH7DF", "0201", "ATMA"]);
+
+        driver
+            .send_frame(&CanFrame::new(0x7DF, &[0x03]).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(next(&mut commands, 2).await, ["03", "ATMA"]);
+
+        driver
+            .send_frame(&CanFrame::new_eff(0x18DB_33F1, &[0xAA]).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            next(&mut commands, 6).await,
+            ["ATPB6001", "ATSPB", "ATCP18", "ATSHDB33F1", "AA", "ATMA"]
+        );
+
+        driver
+            .send_frame(&CanFrame::new_remote(0x123, 2, false).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            next(&mut commands, 5).await,
+            ["ATPBE001", "ATSPB", "ATSH123", "ATRTR", "ATMA"]
+        );
+
+        assert!(
+            driver
+                .send_frame(&CanFrame::new(0x123, &[]).unwrap())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn buffer_full_reports_an_error_frame_and_monitoring_restarts() {
+        let (mut driver, mut commands) = adapter(b"7E8 2 41 0D\rBUFFER FULL\r\r>");
+        driver.open_channel().await.unwrap();
+        next(&mut commands, 14).await;
+
+        let mut frames = Vec::new();
+        while frames.len() < 2 {
+            frames.extend(driver.read_frames().await.unwrap());
+        }
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].id(), 0x7E8);
+        assert_eq!(frames[0].data(), [0x41, 0x0D]);
+        assert!(frames[1].is_error());
+        assert_eq!(frames[1].id() & CAN_ERR_CRTL, CAN_ERR_CRTL);
+        assert_eq!(driver.buffer_overflows(), 1);
+
+        // The prompt ended monitoring; the next read starts it again.
+        assert!(driver.read_frames().await.unwrap().is_empty());
+        assert_eq!(next(&mut commands, 1).await, ["ATMA"]);
+    }
+}
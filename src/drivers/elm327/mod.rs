@@ -0,0 +1,6 @@
+/// Driver implementation for ELM327 / STN11xx OBD adapters used as raw CAN interfaces.
+pub mod codec;
+mod driver;
+
+pub use codec::{Elm327Decoder, Elm327Event};
+pub use driver::{ELM327_BAUD_RATE, Elm327Driver};
@@ -484,17 +484,6 @@ impl GsUsbDriver {
         Ok(driver)
     }
 
-    pub async fn open_listen_only(&mut self) -> io::Result<()> {
-        if self.configured_bitrate.is_none() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "set_bitrate() must be called before open_listen_only()",
-            ));
-        }
-        self.open_channel_with_flags(Some(GS_CAN_MODE_LISTEN_ONLY))
-            .await
-    }
-
     async fn open_channel_with_flags(&mut self, extra_flags: Option<u32>) -> io::Result<()> {
         let mut flags = 0u32;
         if self.timestamp_enabled {
@@ -1076,6 +1065,18 @@ impl CanDriver for GsUsbDriver {
         }
         self.open_channel_inner().await // false = not listen-only
     }
+
+    async fn open_listen_only(&mut self) -> io::Result<()> {
+        if self.configured_bitrate.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "set_bitrate() must be called before open_listen_only()",
+            ));
+        }
+        self.open_channel_with_flags(Some(GS_CAN_MODE_LISTEN_ONLY))
+            .await
+    }
+
     async fn send_frame(&mut self, frame: &CanFrame) -> io::Result<()> {
         self.send_frame(frame).await
     }
@@ -1,9 +1,40 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub mod bitrate_scan;
 pub mod can_driver;
+pub(crate) mod can_id;
+pub mod cannelloni;
+pub(crate) mod dlc;
+pub mod elm327;
 pub mod gs_usb;
 pub mod pcan;
 pub mod slcan;
+#[cfg(target_os = "linux")]
+pub mod socketcan;
+pub mod socketcand;
+pub mod vcan;
 
+pub use bitrate_scan::scan_bitrate;
 pub use can_driver::CanDriver;
+pub use cannelloni::CannelloniDriver;
+pub use elm327::Elm327Driver;
 pub use gs_usb::GsUsbDriver;
 pub use pcan::PcanDriver;
 pub use slcan::SlcanDriver;
+#[cfg(target_os = "linux")]
+pub use socketcan::SocketCanDriver;
+pub use socketcand::SocketcandDriver;
+pub use vcan::VirtualDriver;
+
+/// Longest a `read_frames` call waits for traffic, so a caller sharing the
+/// driver behind a lock still gets to transmit.
+pub(crate) const RECEIVE_WAIT: Duration = Duration::from_millis(5);
+
+/// Wall clock time in microseconds since the Unix epoch, used to stamp frames
+/// the hardware does not.
+pub fn timestamp_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,250 @@
+/// Dynamic loading of the PCAN-Basic runtime and its FFI types.
+use libloading::Library;
+use peak_can_sys::*;
+use std::ffi::{CStr, OsString};
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Environment variable overriding the PCAN-Basic library path.
+pub const PCAN_LIBRARY_ENV: &str = "PCANBASIC_PATH";
+
+#[cfg(windows)]
+const DEFAULT_PCAN_LIBRARY: &str = "PCANBasic.dll";
+#[cfg(not(windows))]
+const DEFAULT_PCAN_LIBRARY: &str = "libpcanbasic.so";
+
+/// CAN FD message as used by `CAN_ReadFD` / `CAN_WriteFD` (`TPCANMsgFD`).
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct PcanMsgFd {
+    pub(crate) id: DWORD,
+    pub(crate) msgtype: BYTE,
+    pub(crate) dlc: BYTE,
+    pub(crate) data: [BYTE; 64],
+}
+
+// TPCANMessageType flags used with CAN FD messages.
+pub(crate) const PCAN_MESSAGE_FD: u8 = 0x04;
+pub(crate) const PCAN_MESSAGE_BRS: u8 = 0x08;
+pub(crate) const PCAN_MESSAGE_ESI: u8 = 0x10;
+
+type CanInitializeFn = unsafe extern "system" fn(WORD, WORD, BYTE, DWORD, WORD) -> DWORD;
+type CanInitializeFdFn = unsafe extern "system" fn(WORD, *const c_char) -> DWORD;
+type CanUninitializeFn = unsafe extern "system" fn(WORD) -> DWORD;
+type CanWriteFn = unsafe extern "system" fn(WORD, *mut CANTPMsg) -> DWORD;
+type CanWriteFdFn = unsafe extern "system" fn(WORD, *mut PcanMsgFd) -> DWORD;
+type CanReadFn = unsafe extern "system" fn(WORD, *mut CANTPMsg, *mut CANTPTimestamp) -> DWORD;
+type CanReadFdFn = unsafe extern "system" fn(WORD, *mut PcanMsgFd, *mut u64) -> DWORD;
+type CanGetValueFn = unsafe extern "system" fn(WORD, BYTE, *mut c_void, DWORD) -> DWORD;
+type CanSetValueFn = unsafe extern "system" fn(WORD, BYTE, *mut c_void, DWORD) -> DWORD;
+type CanGetErrorTextFn = unsafe extern "system" fn(DWORD, WORD, *mut c_char) -> DWORD;
+
+pub(crate) struct PcanApi {
+    pub(crate) can_initialize: CanInitializeFn,
+    pub(crate) can_uninitialize: CanUninitializeFn,
+    pub(crate) can_write: CanWriteFn,
+    pub(crate) can_read: CanReadFn,
+    pub(crate) can_get_value: CanGetValueFn,
+    pub(crate) can_set_value: CanSetValueFn,
+    pub(crate) can_get_error_text: CanGetErrorTextFn,
+    // CAN FD entry points are missing from PCAN-Basic releases older than 4.0.
+    pub(crate) can_initialize_fd: Option<CanInitializeFdFn>,
+    pub(crate) can_write_fd: Option<CanWriteFdFn>,
+    pub(crate) can_read_fd: Option<CanReadFdFn>,
+}
+
+static PCAN_API: OnceLock<Result<PcanApi, String>> = OnceLock::new();
+static PCAN_LIBRARY_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Load PCAN-Basic from `path` instead of the platform default. Must be called
+/// before the first PCAN channel is opened; takes precedence over
+/// [`PCAN_LIBRARY_ENV`].
+pub fn set_pcan_library_path(path: impl Into<PathBuf>) -> std::io::Result<()> {
+    if PCAN_API.get().is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "PCAN-Basic library already loaded",
+        ));
+    }
+    PCAN_LIBRARY_PATH.set(path.into()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "PCAN-Basic library path already set",
+        )
+    })
+}
+
+/// Resolve the library to load: explicit path, then environment, then the
+/// platform default (`PCANBasic.dll` or `libpcanbasic.so`).
+fn pcan_library_path() -> PathBuf {
+    resolve_library_path(PCAN_LIBRARY_PATH.get(), std::env::var_os(PCAN_LIBRARY_ENV))
+}
+
+fn resolve_library_path(explicit: Option<&PathBuf>, env: Option<OsString>) -> PathBuf {
+    if let Some(path) = explicit {
+        return path.clone();
+    }
+    match env {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from(DEFAULT_PCAN_LIBRARY),
+    }
+}
+
+fn open_library(path: &Path) -> Result<Library, String> {
+    unsafe { Library::new(path) }.map_err(|e| format!("Failed to load {}: {e}", path.display()))
+}
+
+fn load_pcan_api() -> Result<PcanApi, String> {
+    let lib = open_library(&pcan_library_path())?;
+    unsafe {
+        let can_initialize = *lib
+            .get::<CanInitializeFn>(b"CAN_Initialize\0")
+            .map_err(|e| format!("Failed to load CAN_Initialize: {e}"))?;
+        let can_uninitialize = *lib
+            .get::<CanUninitializeFn>(b"CAN_Uninitialize\0")
+            .map_err(|e| format!("Failed to load CAN_Uninitialize: {e}"))?;
+        let can_write = *lib
+            .get::<CanWriteFn>(b"CAN_Write\0")
+            .map_err(|e| format!("Failed to load CAN_Write: {e}"))?;
+        let can_read = *lib
+            .get::<CanReadFn>(b"CAN_Read\0")
+            .map_err(|e| format!("Failed to load CAN_Read: {e}"))?;
+        let can_get_value = *lib
+            .get::<CanGetValueFn>(b"CAN_GetValue\0")
+            .map_err(|e| format!("Failed to load CAN_GetValue: {e}"))?;
+        let can_set_value = *lib
+            .get::<CanSetValueFn>(b"CAN_SetValue\0")
+            .map_err(|e| format!("Failed to load CAN_SetValue: {e}"))?;
+        let can_get_error_text = *lib
+            .get::<CanGetErrorTextFn>(b"CAN_GetErrorText\0")
+            .map_err(|e| format!("Failed to load CAN_GetErrorText: {e}"))?;
+        let can_initialize_fd = lib
+            .get::<CanInitializeFdFn>(b"CAN_InitializeFD\0")
+            .ok()
+            .map(|f| *f);
+        let can_write_fd = lib.get::<CanWriteFdFn>(b"CAN_WriteFD\0").ok().map(|f| *f);
+        let can_read_fd = lib.get::<CanReadFdFn>(b"CAN_ReadFD\0").ok().map(|f| *f);
+
+        // Leak the library handle so the loaded symbols remain valid for the
+        // remainder of the process. This avoids lifetime issues with the
+        // dynamically loaded module while still allowing the rest of the
+        // program to run when the DLL is absent.
+        std::mem::forget(lib);
+
+        Ok(PcanApi {
+            can_initialize,
+            can_uninitialize,
+            can_write,
+            can_read,
+            can_get_value,
+            can_set_value,
+            can_get_error_text,
+            can_initialize_fd,
+            can_write_fd,
+            can_read_fd,
+        })
+    }
+}
+
+pub(crate) fn pcan_api() -> std::io::Result<&'static PcanApi> {
+    match PCAN_API.get_or_init(load_pcan_api) {
+        Ok(api) => Ok(api),
+        Err(err) => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            err.clone(),
+        )),
+    }
+}
+
+/// Look up an optional CAN FD entry point, failing if the DLL predates CAN FD.
+pub(crate) fn fd_fn<T: Copy>(f: Option<T>, name: &str) -> std::io::Result<T> {
+    f.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("{name} not available; install PCAN-Basic 4.0 or newer for CAN FD"),
+        )
+    })
+}
+
+/// Describe a PCAN-Basic status code using `CAN_GetErrorText`, falling back to hex.
+pub(crate) fn error_text(api: &PcanApi, status: DWORD) -> String {
+    // PCAN-Basic documents 256 bytes as the maximum error text length.
+    let mut buf = [0 as c_char; 256];
+    // 0x09 = English (neutral language 0x00 follows the system locale).
+    let rc = unsafe { (api.can_get_error_text)(status, 0x09, buf.as_mut_ptr()) };
+    if rc != PEAK_ERROR_OK {
+        return format!("0x{:08X}", status);
+    }
+    let text = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    format!("{} (0x{:08X})", text.to_string_lossy(), status)
+}
+
+/// Load the stand-in PCAN-Basic built from `tests/pcan_stub`. Every test that
+/// touches the PCAN API goes through here, as the library is loaded once per
+/// process.
+#[cfg(test)]
+pub(crate) fn use_stub_library() -> &'static PcanApi {
+    static STUB: std::sync::Once = std::sync::Once::new();
+    STUB.call_once(|| set_pcan_library_path(pcan_stub::library_path()).unwrap());
+    pcan_api().expect("PCAN-Basic stub loads")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_path_beats_environment_beats_default() {
+        let explicit = PathBuf::from("/opt/peak/libpcanbasic.so");
+        let env = Some(OsString::from("C:\\PEAK\\PCANBasic.dll"));
+        assert_eq!(resolve_library_path(Some(&explicit), env.clone()), explicit);
+        assert_eq!(
+            resolve_library_path(None, env),
+            PathBuf::from("C:\\PEAK\\PCANBasic.dll")
+        );
+        assert_eq!(
+            resolve_library_path(None, Some(OsString::new())),
+            PathBuf::from(DEFAULT_PCAN_LIBRARY)
+        );
+        assert_eq!(
+            resolve_library_path(None, None),
+            PathBuf::from(DEFAULT_PCAN_LIBRARY)
+        );
+    }
+
+    #[test]
+    fn loads_every_entry_point_from_the_configured_library() {
+        let api = use_stub_library();
+        assert_eq!(pcan_library_path(), pcan_stub::library_path());
+        assert!(api.can_initialize_fd.is_some());
+        assert!(api.can_write_fd.is_some());
+        assert!(api.can_read_fd.is_some());
+    }
+
+    #[test]
+    fn path_cannot_change_once_loaded() {
+        use_stub_library();
+        let err = set_pcan_library_path("other.so").unwrap_err();
+        assert_eq!(err.to_string(), "PCAN-Basic library already loaded");
+    }
+
+    #[test]
+    fn missing_library_names_the_path() {
+        let err = open_library(Path::new("/nonexistent/libpcanbasic.so"))
+            .err()
+            .unwrap();
+        assert!(
+            err.starts_with("Failed to load /nonexistent/libpcanbasic.so: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn missing_fd_entry_points_are_unsupported() {
+        let err = fd_fn(None::<CanReadFdFn>, "CAN_ReadFD").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        assert!(err.to_string().starts_with("CAN_ReadFD not available"));
+    }
+}
@@ -0,0 +1,596 @@
+use async_trait::async_trait;
+use crosscan::can::CanFrame;
+use tokio::sync::Mutex;
+
+use peak_can_sys::*;
+use std::ffi::CString;
+use std::time::Duration;
+
+use super::api::{
+    PCAN_MESSAGE_BRS, PCAN_MESSAGE_ESI, PCAN_MESSAGE_FD, PcanApi, PcanMsgFd, error_text, fd_fn,
+    pcan_api,
+};
+use super::event::ReceiveEvent;
+use super::messages::{frame_from_message, message_type};
+use super::timing::{fd_bitrate_string, fd_phase_timing, parse_fd_bitrate_string};
+use crate::drivers::dlc::{fd_dlc_to_len, fd_len_to_dlc};
+use crate::drivers::{CanDriver, RECEIVE_WAIT};
+
+/// Polling back-off used when no receive event could be registered.
+const IDLE_WAIT_MIN: Duration = Duration::from_millis(1);
+const IDLE_WAIT_MAX: Duration = Duration::from_millis(8);
+
+/// PCAN-Basic driver backed by `PCANBasic.dll` (libpcanbasic).
+pub struct PcanDriver {
+    pub(super) channel: WORD,
+    configured_bitrate: Option<u32>,
+    /// Data phase bitrate; when set the channel is opened in CAN FD mode.
+    configured_data_bitrate: Option<u32>,
+    /// Explicit PCAN FD bitrate string, overriding the computed one.
+    fd_bitrate_string: Option<String>,
+    /// Whether the channel was initialized with `CAN_InitializeFD`.
+    fd_mode: bool,
+    /// Whether `PCAN_LISTEN_ONLY` was switched on by `open_listen_only`.
+    listen_only_set: bool,
+    /// Receive notification registered after initialization, if supported.
+    rx_event: Option<ReceiveEvent>,
+    /// Current sleep between polls when `rx_event` is unavailable.
+    idle_wait: Duration,
+    // PCAN calls are synchronous; keep a mutex to serialize access like the SLCAN driver does.
+    io_lock: Mutex<()>,
+}
+
+impl PcanDriver {
+    /// Open by channel string (e.g., "USBBUS1", "PCIBUS1", "LANBUS1").
+    pub async fn open(channel_name: &str) -> std::io::Result<Self> {
+        // Validate that the PCAN runtime is available before reporting success.
+        pcan_api()?;
+
+        let channel = parse_channel(channel_name).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Unknown PCAN channel")
+        })?;
+        Ok(Self {
+            channel,
+            configured_bitrate: None,
+            configured_data_bitrate: None,
+            fd_bitrate_string: None,
+            fd_mode: false,
+            listen_only_set: false,
+            rx_event: None,
+            idle_wait: IDLE_WAIT_MIN,
+            io_lock: Mutex::new(()),
+        })
+    }
+}
+
+impl PcanDriver {
+    /// Set the CAN FD data phase bitrate. The channel is then opened in FD mode
+    /// with a bitrate string derived from the nominal and data bitrates.
+    pub fn set_data_bitrate(&mut self, data_bitrate: u32) -> std::io::Result<()> {
+        if fd_phase_timing(data_bitrate, 32, 16, 75).is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unsupported CAN FD data bitrate: {}", data_bitrate),
+            ));
+        }
+        self.configured_data_bitrate = Some(data_bitrate);
+        Ok(())
+    }
+
+    /// Use a PCAN-Basic FD bitrate string verbatim, e.g.
+    /// `f_clock_mhz=80, nom_brp=1, nom_tseg1=127, nom_tseg2=32, nom_sjw=32, data_brp=1, data_tseg1=29, data_tseg2=10, data_sjw=10`.
+    pub fn set_fd_bitrate_string(&mut self, bitrate_fd: &str) -> std::io::Result<()> {
+        let (nominal, data) = parse_fd_bitrate_string(bitrate_fd).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid PCAN FD bitrate string: {}", bitrate_fd),
+            )
+        })?;
+        self.configured_bitrate = Some(nominal);
+        self.configured_data_bitrate = Some(data);
+        self.fd_bitrate_string = Some(bitrate_fd.to_string());
+        Ok(())
+    }
+
+    /// Initialize the channel with the configured classic or FD bitrate.
+    async fn initialize(&mut self) -> std::io::Result<()> {
+        let _g = self.io_lock.lock().await;
+        let api = pcan_api()?;
+        if let Some(event) = self.rx_event.take() {
+            event.unregister(api, self.channel);
+        }
+
+        if self.configured_data_bitrate.is_some() || self.fd_bitrate_string.is_some() {
+            self.open_fd(api)?;
+            self.fd_mode = true;
+        } else {
+            self.open_classic(api)?;
+        }
+
+        self.idle_wait = IDLE_WAIT_MIN;
+        self.rx_event = match ReceiveEvent::register(api, self.channel) {
+            Ok(event) => Some(event),
+            Err(e) => {
+                log::debug!("pcan: receive event unavailable, polling instead: {}", e);
+                None
+            }
+        };
+        Ok(())
+    }
+
+    fn open_classic(&self, api: &PcanApi) -> std::io::Result<()> {
+        let btr_const = self
+            .configured_bitrate
+            .and_then(map_bitrate_to_const)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "Bitrate not set"))?;
+
+        // For plug-and-play hardware (USB/PCI/LAN), HwType/IOPort/Interrupt are zero.
+        let status = unsafe { (api.can_initialize)(self.channel, btr_const, 0u8, 0u32, 0u16) };
+        if status != PEAK_ERROR_OK {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("CAN_Initialize failed: {}", error_text(api, status)),
+            ));
+        }
+        Ok(())
+    }
+
+    fn open_fd(&self, api: &PcanApi) -> std::io::Result<()> {
+        let initialize_fd = fd_fn(api.can_initialize_fd, "CAN_InitializeFD")?;
+        let bitrate_fd = match &self.fd_bitrate_string {
+            Some(s) => s.clone(),
+            None => {
+                let nominal = self.configured_bitrate.ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::Other, "Bitrate not set")
+                })?;
+                let data = self.configured_data_bitrate.unwrap_or(nominal);
+                fd_bitrate_string(nominal, data).ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Cannot build CAN FD timing for {} / {} bps", nominal, data),
+                    )
+                })?
+            }
+        };
+        let c_bitrate = CString::new(bitrate_fd.clone()).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid FD bitrate string",
+            )
+        })?;
+
+        log::info!("CAN_InitializeFD with \"{}\"", bitrate_fd);
+        let status = unsafe { initialize_fd(self.channel, c_bitrate.as_ptr()) };
+        if status != PEAK_ERROR_OK {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("CAN_InitializeFD failed: {}", error_text(api, status)),
+            ));
+        }
+        Ok(())
+    }
+
+    fn send_frame_fd(&self, api: &PcanApi, frame: &CanFrame) -> std::io::Result<()> {
+        let write_fd = fd_fn(api.can_write_fd, "CAN_WriteFD")?;
+        let data = frame.data();
+        let dlc = fd_len_to_dlc(data.len()).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("CAN FD payload too long: {} bytes", data.len()),
+            )
+        })?;
+
+        let mut msgtype = message_type(frame)?;
+        // Remote frames carry no payload; their DLC is the requested length.
+        let dlc = if frame.is_rtr() {
+            frame.dlc().min(8) as u8
+        } else {
+            dlc
+        };
+        if data.len() > 8 {
+            msgtype |= PCAN_MESSAGE_FD;
+            if self.configured_data_bitrate != self.configured_bitrate {
+                msgtype |= PCAN_MESSAGE_BRS;
+            }
+        }
+
+        let mut msg = PcanMsgFd {
+            id: frame.id(),
+            msgtype,
+            dlc,
+            data: [0u8; 64],
+        };
+        msg.data[..data.len()].copy_from_slice(data);
+
+        let status = unsafe { write_fd(self.channel, &mut msg as *mut PcanMsgFd) };
+        if status != PEAK_ERROR_OK {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("CAN_WriteFD failed: {}", error_text(api, status)),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Read everything currently queued on the channel.
+    fn drain(&self, api: &PcanApi) -> std::io::Result<Vec<CanFrame>> {
+        if self.fd_mode {
+            self.read_frames_fd(api)
+        } else {
+            self.read_frames_classic(api)
+        }
+    }
+
+    fn read_frames_classic(&self, api: &PcanApi) -> std::io::Result<Vec<CanFrame>> {
+        let mut frames = Vec::new();
+
+        loop {
+            // Prepare output buffers for CAN_Read
+            let mut msg: CANTPMsg = tagCANTPMsg {
+                ID: 0,
+                MSGTYPE: 0,
+                LEN: 0,
+                DATA: [0u8; 8],
+            };
+            let mut ts: CANTPTimestamp = tagCANTPTimestamp {
+                millis: 0,
+                millis_overflow: 0,
+                micros: 0,
+            };
+
+            let status = unsafe {
+                (api.can_read)(
+                    self.channel,
+                    &mut msg as *mut CANTPMsg,
+                    &mut ts as *mut CANTPTimestamp,
+                )
+            };
+            if status == PEAK_ERROR_QRCVEMPTY {
+                break; // no more frames in RX queue
+            }
+            if status != PEAK_ERROR_OK {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("CAN_Read failed: {}", error_text(api, status)),
+                ));
+            }
+
+            let dlc = (msg.LEN as usize).min(8);
+            let data = &msg.DATA[..dlc];
+
+            let Some(mut frame) = frame_from_message(msg.MSGTYPE, msg.ID, data, dlc)? else {
+                continue;
+            };
+
+            // Timestamp: ((millis_overflow << 32) | millis) * 1000 + micros
+            let ts_us = (((ts.millis_overflow as u64) << 32) | (ts.millis as u64)) * 1000
+                + (ts.micros as u64);
+            frame.set_timestamp(Some(ts_us));
+
+            frames.push(frame);
+        }
+
+        Ok(frames)
+    }
+
+    fn read_frames_fd(&self, api: &PcanApi) -> std::io::Result<Vec<CanFrame>> {
+        let read_fd = fd_fn(api.can_read_fd, "CAN_ReadFD")?;
+        let mut frames = Vec::new();
+
+        loop {
+            let mut msg = PcanMsgFd {
+                id: 0,
+                msgtype: 0,
+                dlc: 0,
+                data: [0u8; 64],
+            };
+            let mut ts_us: u64 = 0;
+
+            let status = unsafe {
+                read_fd(
+                    self.channel,
+                    &mut msg as *mut PcanMsgFd,
+                    &mut ts_us as *mut u64,
+                )
+            };
+            if status == PEAK_ERROR_QRCVEMPTY {
+                break; // no more frames in RX queue
+            }
+            if status != PEAK_ERROR_OK {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("CAN_ReadFD failed: {}", error_text(api, status)),
+                ));
+            }
+
+            if msg.msgtype & PCAN_MESSAGE_ESI != 0 {
+                log::debug!("pcan: ESI set on frame 0x{:X}", msg.id);
+            }
+
+            let len = fd_dlc_to_len(msg.dlc).unwrap_or(0);
+            let data = &msg.data[..len];
+
+            let Some(mut frame) = frame_from_message(msg.msgtype, msg.id, data, len)? else {
+                continue;
+            };
+
+            // CAN_ReadFD reports timestamps directly in microseconds.
+            frame.set_timestamp(Some(ts_us));
+            frames.push(frame);
+        }
+
+        Ok(frames)
+    }
+}
+
+/// Channel families and the handles of their channels 1..=8 and 9..=16.
+///
+/// PCAN-Basic numbers USB and PCI channels 9-16 in a separate block
+/// (e.g. `PCAN_USBBUS9` = 0x509), while LAN channels are contiguous.
+const CHANNEL_FAMILIES: [(&str, u16, u16); 3] = [
+    ("USBBUS", PEAK_USBBUS1 as u16, 0x509),
+    ("PCIBUS", PEAK_PCIBUS1 as u16, 0x409),
+    ("LANBUS", PEAK_LANBUS1 as u16, PEAK_LANBUS1 as u16 + 8),
+];
+
+fn parse_channel(s: &str) -> Option<WORD> {
+    let mut t = s.trim().to_ascii_uppercase();
+    if let Some(rest) = t.strip_prefix("PCAN_") {
+        t = rest.to_string();
+    }
+
+    for (prefix, low_base, high_base) in CHANNEL_FAMILIES {
+        let Some(i) = t.strip_prefix(prefix).and_then(|n| n.parse::<u16>().ok()) else {
+            continue;
+        };
+        return match i {
+            1..=8 => Some((low_base + (i - 1)) as WORD),
+            9..=16 => Some((high_base + (i - 9)) as WORD),
+            _ => None,
+        };
+    }
+    None
+}
+
+/// Inverse of [`parse_channel`]: `0x51` → `USBBUS1`.
+pub(super) fn channel_name(handle: WORD) -> Option<String> {
+    let handle = handle as u16;
+    for (prefix, low_base, high_base) in CHANNEL_FAMILIES {
+        if (low_base..low_base + 8).contains(&handle) {
+            return Some(format!("{}{}", prefix, handle - low_base + 1));
+        }
+        if (high_base..high_base + 8).contains(&handle) {
+            return Some(format!("{}{}", prefix, handle - high_base + 9));
+        }
+    }
+    None
+}
+
+fn map_bitrate_to_const(bps: u32) -> Option<WORD> {
+    Some(match bps {
+        5_000 => PEAK_BAUD_5K,
+        10_000 => PEAK_BAUD_10K,
+        20_000 => PEAK_BAUD_20K,
+        33_333 => PEAK_BAUD_33K,
+        47_619 => PEAK_BAUD_47K,
+        50_000 => PEAK_BAUD_50K,
+        83_333 => PEAK_BAUD_83K,
+        95_238 => PEAK_BAUD_95K,
+        100_000 => PEAK_BAUD_100K,
+        125_000 => PEAK_BAUD_125K,
+        250_000 => PEAK_BAUD_250K,
+        500_000 => PEAK_BAUD_500K,
+        800_000 => PEAK_BAUD_800K,
+        1_000_000 => PEAK_BAUD_1M,
+        _ => return None,
+    } as WORD)
+}
+
+#[async_trait]
+impl CanDriver for PcanDriver {
+    async fn enable_timestamp(&mut self) -> std::io::Result<()> {
+        // PCAN-Basic always provides timestamps via CAN_Read’s third parameter; no switch needed.
+        Ok(())
+    }
+
+    async fn set_bitrate(&mut self, bitrate: u32) -> std::io::Result<()> {
+        let _btr_const = map_bitrate_to_const(bitrate).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unsupported CAN bitrate: {}", bitrate),
+            )
+        })?;
+        self.configured_bitrate = Some(bitrate);
+        self.fd_bitrate_string = None;
+
+        // Defer actual hardware init to open_channel(), same as the SLCAN pattern.
+        // We just remember the requested bitrate here; CAN_Initialize uses it later.
+        Ok(())
+    }
+
+    async fn open_channel(&mut self) -> std::io::Result<()> {
+        // Undo a previous open_listen_only() before initializing normally.
+        if self.listen_only_set {
+            self.set_listen_only(false)?;
+            self.listen_only_set = false;
+        }
+        self.initialize().await
+    }
+
+    async fn open_listen_only(&mut self) -> std::io::Result<()> {
+        // PCAN_LISTEN_ONLY may be configured before CAN_Initialize.
+        self.set_listen_only(true)?;
+        self.listen_only_set = true;
+        self.initialize().await
+    }
+
+    async fn send_frame(&mut self, frame: &CanFrame) -> std::io::Result<()> {
+        let _g = self.io_lock.lock().await;
+        let api = pcan_api()?;
+
+        if self.fd_mode {
+            return self.send_frame_fd(api, frame);
+        }
+
+        // Build CANTPMsg (8-byte classic CAN).
+        let mut msg = tagCANTPMsg {
+            ID: frame.id(),
+            MSGTYPE: message_type(frame)?,
+            LEN: frame.dlc() as u8,
+            DATA: [0u8; 8],
+        };
+        let data = frame.data();
+        let copy_len = data.len().min(8);
+        msg.DATA[..copy_len].copy_from_slice(&data[..copy_len]);
+
+        let mut msg_alias: CANTPMsg = msg; // function takes alias pointer
+        let status = unsafe { (api.can_write)(self.channel, &mut msg_alias as *mut CANTPMsg) };
+        if status != PEAK_ERROR_OK {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("CAN_Write failed: {}", error_text(api, status)),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn read_frames(&mut self) -> std::io::Result<Vec<CanFrame>> {
+        let _g = self.io_lock.lock().await;
+        let api = pcan_api()?;
+
+        let frames = self.drain(api)?;
+        if !frames.is_empty() {
+            self.idle_wait = IDLE_WAIT_MIN;
+            return Ok(frames);
+        }
+
+        // Queue empty: wait for the driver to signal new data instead of spinning.
+        match &mut self.rx_event {
+            Some(event) => {
+                if !event.wait(RECEIVE_WAIT).await {
+                    return Ok(frames);
+                }
+            }
+            None => {
+                tokio::time::sleep(self.idle_wait).await;
+                self.idle_wait = (self.idle_wait * 2).min(IDLE_WAIT_MAX);
+            }
+        }
+        self.drain(api)
+    }
+
+    async fn close_channel(&mut self) -> std::io::Result<()> {
+        let _g = self.io_lock.lock().await;
+        let api = pcan_api()?;
+        if let Some(event) = self.rx_event.take() {
+            event.unregister(api, self.channel);
+        }
+        let status = unsafe { (api.can_uninitialize)(self.channel) };
+        self.fd_mode = false;
+        if status != PEAK_ERROR_OK {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("CAN_Uninitialize failed: {}", error_text(api, status)),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn get_bitrate(&self) -> Option<u32> {
+        self.configured_bitrate
+    }
+
+    async fn get_data_bitrate(&self) -> Option<u32> {
+        self.configured_data_bitrate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::api::use_stub_library;
+    use super::*;
+
+    /// Open `name` on the stub bus, in CAN FD mode if `data_bitrate` is given.
+    /// The stub connects channels in pairs, so each test takes its own pair:
+    /// USBBUS3/4 for the parameter tests, 5/6 and 7/8 here and 9/10 for the
+    /// receive event.
+    async fn open(name: &str, data_bitrate: Option<u32>) -> PcanDriver {
+        use_stub_library();
+        let mut driver = PcanDriver::open(name).await.unwrap();
+        driver.set_bitrate(500_000).await.unwrap();
+        if let Some(data_bitrate) = data_bitrate {
+            driver.set_data_bitrate(data_bitrate).unwrap();
+        }
+        driver.open_channel().await.unwrap();
+        driver
+    }
+
+    async fn receive(driver: &mut PcanDriver, count: usize) -> Vec<CanFrame> {
+        let mut frames = Vec::new();
+        for _ in 0..100 {
+            frames.extend(driver.read_frames().await.unwrap());
+            if frames.len() >= count {
+                break;
+            }
+        }
+        frames
+    }
+
+    #[test]
+    fn parses_and_names_channels() {
+        assert_eq!(parse_channel("USBBUS1"), Some(0x51));
+        assert_eq!(parse_channel("pcan_usbbus9"), Some(0x509));
+        assert_eq!(parse_channel("LANBUS16"), Some(0x810));
+        assert_eq!(parse_channel("USBBUS17"), None);
+        for handle in [0x51, 0x58, 0x509, 0x410, 0x801, 0x810] {
+            assert_eq!(parse_channel(&channel_name(handle).unwrap()), Some(handle));
+        }
+    }
+
+    #[tokio::test]
+    async fn classic_frames_cross_the_stub_bus() {
+        let mut tx = open("USBBUS5", None).await;
+        let mut rx = open("USBBUS6", None).await;
+
+        tx.send_frame(&CanFrame::new(0x123, &[1, 2, 3]).unwrap())
+            .await
+            .unwrap();
+        tx.send_frame(&CanFrame::new_eff(0x1ABCDEF, &[]).unwrap())
+            .await
+            .unwrap();
+        tx.send_frame(&CanFrame::new_remote(0x7FF, 4, false).unwrap())
+            .await
+            .unwrap();
+
+        let frames = receive(&mut rx, 3).await;
+        assert_eq!(frames.len(), 3);
+        assert_eq!((frames[0].id(), frames[0].data()), (0x123, &[1, 2, 3][..]));
+        assert!(frames[0].timestamp().is_some());
+        assert!(frames[1].is_extended() && frames[1].id() == 0x1ABCDEF);
+        assert!(frames[2].is_rtr() && frames[2].dlc() == 4);
+        // Nothing echoes back to the sender.
+        assert!(tx.read_frames().await.unwrap().is_empty());
+
+        tx.close_channel().await.unwrap();
+        rx.close_channel().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fd_frames_cross_the_stub_bus() {
+        let mut tx = open("USBBUS7", Some(2_000_000)).await;
+        let mut rx = open("USBBUS8", Some(2_000_000)).await;
+
+        let payload: Vec<u8> = (0..20).collect();
+        tx.send_frame(&CanFrame::new(0x456, &payload).unwrap())
+            .await
+            .unwrap();
+
+        let frames = receive(&mut rx, 1).await;
+        assert_eq!(frames.len(), 1);
+        // 20 bytes go out as DLC 11, which is 20 bytes long.
+        assert_eq!(frames[0].data(), &payload[..]);
+
+        tx.close_channel().await.unwrap();
+        rx.close_channel().await.unwrap();
+    }
+}
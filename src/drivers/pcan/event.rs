@@ -0,0 +1,319 @@
+/// Receive notification for PCAN channels (`PCAN_RECEIVE_EVENT`).
+///
+/// On Windows PCAN-Basic signals a Win32 event that we hand to the driver; on
+/// Linux it exposes a file descriptor that becomes readable when the receive
+/// queue is non-empty. One thread per channel waits on it for as long as the
+/// channel is open and wakes the driver through a [`Notify`], so the runtime
+/// stays free and no thread is spawned per read.
+use peak_can_sys::*;
+use std::os::raw::c_void;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use super::api::{PcanApi, error_text};
+
+const PCAN_RECEIVE_EVENT: BYTE = 0x03;
+
+/// How long the waiter thread blocks on the event before checking whether
+/// the channel is being closed.
+const WAITER_POLL_MS: i32 = 50;
+
+#[cfg(windows)]
+mod sys {
+    use std::os::raw::c_void;
+
+    pub type RawEvent = isize;
+
+    pub const WAIT_OBJECT_0: u32 = 0x0000_0000;
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        pub fn CreateEventW(
+            attributes: *mut c_void,
+            manual_reset: i32,
+            initial_state: i32,
+            name: *const u16,
+        ) -> RawEvent;
+        pub fn WaitForSingleObject(handle: RawEvent, millis: u32) -> u32;
+        pub fn CloseHandle(handle: RawEvent) -> i32;
+    }
+}
+
+#[cfg(not(windows))]
+mod sys {
+    pub type RawEvent = libc::c_int;
+}
+
+use sys::RawEvent;
+
+/// A registered receive event for one initialized channel.
+pub(crate) struct ReceiveEvent {
+    raw: RawEvent,
+    waiter: Waiter,
+    /// Whether the waiter has been asked for a signal it has not delivered yet.
+    armed: bool,
+}
+
+/// The thread waiting on the event. It waits only while armed: the Linux
+/// descriptor stays readable until the queue is drained, so waiting again
+/// right after a signal would spin.
+struct Waiter {
+    arm: Option<Sender<()>>,
+    notify: Arc<Notify>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Waiter {
+    fn spawn(raw: RawEvent, channel: WORD) -> std::io::Result<Self> {
+        let (arm, armed) = std::sync::mpsc::channel::<()>();
+        let notify = Arc::new(Notify::new());
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let notify = notify.clone();
+            let stop = stop.clone();
+            std::thread::Builder::new()
+                .name(format!("pcan-rx-{:X}", channel))
+                .spawn(move || {
+                    while armed.recv().is_ok() {
+                        while !stop.load(Ordering::Acquire) {
+                            if wait_raw(raw, WAITER_POLL_MS) {
+                                notify.notify_one();
+                                break;
+                            }
+                        }
+                    }
+                })?
+        };
+        Ok(Self {
+            arm: Some(arm),
+            notify,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Waiter {
+    /// Stop the thread and wait for it, so the event can be released.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        self.arm.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl ReceiveEvent {
+    /// Attach a receive event to `channel`. The channel must already be initialized.
+    #[cfg(windows)]
+    pub(crate) fn register(api: &PcanApi, channel: WORD) -> std::io::Result<Self> {
+        // Auto-reset, initially non-signalled.
+        let raw = unsafe { sys::CreateEventW(std::ptr::null_mut(), 0, 0, std::ptr::null()) };
+        if raw == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut value = raw;
+        let status = unsafe {
+            (api.can_set_value)(
+                channel,
+                PCAN_RECEIVE_EVENT,
+                &mut value as *mut RawEvent as *mut c_void,
+                std::mem::size_of::<RawEvent>() as DWORD,
+            )
+        };
+        if status != PEAK_ERROR_OK {
+            unsafe { sys::CloseHandle(raw) };
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!(
+                    "CAN_SetValue(PCAN_RECEIVE_EVENT) failed: {}",
+                    error_text(api, status)
+                ),
+            ));
+        }
+        match Waiter::spawn(raw, channel) {
+            Ok(waiter) => Ok(Self {
+                raw,
+                waiter,
+                armed: false,
+            }),
+            Err(e) => {
+                let mut none: RawEvent = 0;
+                unsafe {
+                    (api.can_set_value)(
+                        channel,
+                        PCAN_RECEIVE_EVENT,
+                        &mut none as *mut RawEvent as *mut c_void,
+                        std::mem::size_of::<RawEvent>() as DWORD,
+                    );
+                    sys::CloseHandle(raw);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Fetch the receive file descriptor of `channel`. The channel must already be initialized.
+    #[cfg(not(windows))]
+    pub(crate) fn register(api: &PcanApi, channel: WORD) -> std::io::Result<Self> {
+        let mut raw: RawEvent = -1;
+        let status = unsafe {
+            (api.can_get_value)(
+                channel,
+                PCAN_RECEIVE_EVENT,
+                &mut raw as *mut RawEvent as *mut c_void,
+                std::mem::size_of::<RawEvent>() as DWORD,
+            )
+        };
+        if status != PEAK_ERROR_OK || raw < 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!(
+                    "CAN_GetValue(PCAN_RECEIVE_EVENT) failed: {}",
+                    error_text(api, status)
+                ),
+            ));
+        }
+        Ok(Self {
+            raw,
+            waiter: Waiter::spawn(raw, channel)?,
+            armed: false,
+        })
+    }
+
+    /// Wait until the channel signals received data or `timeout` elapses.
+    /// Returns `true` when signalled. A signal arriving after a timeout is
+    /// kept for the next call.
+    pub(crate) async fn wait(&mut self, timeout: Duration) -> bool {
+        if !self.armed {
+            let arm = self.waiter.arm.as_ref();
+            if arm.is_none_or(|arm| arm.send(()).is_err()) {
+                return false;
+            }
+            self.armed = true;
+        }
+        match tokio::time::timeout(timeout, self.waiter.notify.notified()).await {
+            Ok(()) => {
+                self.armed = false;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Detach the event from `channel` before it is uninitialized.
+    pub(crate) fn unregister(self, api: &PcanApi, channel: WORD) {
+        let Self { raw, waiter, .. } = self;
+        drop(waiter);
+        #[cfg(windows)]
+        unsafe {
+            let mut none: RawEvent = 0;
+            (api.can_set_value)(
+                channel,
+                PCAN_RECEIVE_EVENT,
+                &mut none as *mut RawEvent as *mut c_void,
+                std::mem::size_of::<RawEvent>() as DWORD,
+            );
+            sys::CloseHandle(raw);
+        }
+        // The Linux descriptor belongs to libpcanbasic and is closed by CAN_Uninitialize.
+        #[cfg(not(windows))]
+        let _ = (api, channel, raw);
+    }
+}
+
+#[cfg(windows)]
+fn wait_raw(raw: RawEvent, millis: i32) -> bool {
+    unsafe { sys::WaitForSingleObject(raw, millis as u32) == sys::WAIT_OBJECT_0 }
+}
+
+#[cfg(not(windows))]
+fn wait_raw(raw: RawEvent, millis: i32) -> bool {
+    let mut pfd = libc::pollfd {
+        fd: raw,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let rc = unsafe { libc::poll(&mut pfd, 1, millis) };
+    rc > 0 && pfd.revents & libc::POLLIN != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::api::use_stub_library;
+    use super::*;
+
+    const TX: WORD = 0x509;
+    const RX: WORD = 0x50A;
+
+    fn send(api: &PcanApi) {
+        let mut msg = tagCANTPMsg {
+            ID: 0x100,
+            MSGTYPE: PEAK_MESSAGE_STANDARD as u8,
+            LEN: 1,
+            DATA: [0; 8],
+        };
+        assert_eq!(unsafe { (api.can_write)(TX, &mut msg) }, PEAK_ERROR_OK);
+    }
+
+    fn drain(api: &PcanApi) -> usize {
+        let mut count = 0;
+        loop {
+            let mut msg = tagCANTPMsg {
+                ID: 0,
+                MSGTYPE: 0,
+                LEN: 0,
+                DATA: [0; 8],
+            };
+            let mut ts = tagCANTPTimestamp {
+                millis: 0,
+                millis_overflow: 0,
+                micros: 0,
+            };
+            match unsafe { (api.can_read)(RX, &mut msg, &mut ts) } {
+                PEAK_ERROR_OK => count += 1,
+                _ => return count,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn signals_until_the_queue_is_drained() {
+        let api = use_stub_library();
+        for channel in [TX, RX] {
+            let status = unsafe { (api.can_initialize)(channel, PEAK_BAUD_500K as WORD, 0, 0, 0) };
+            assert_eq!(status, PEAK_ERROR_OK);
+        }
+        let mut event = ReceiveEvent::register(api, RX).unwrap();
+
+        assert!(!event.wait(Duration::from_millis(20)).await);
+
+        // A frame queued while nobody awaits is kept for the next wait.
+        send(api);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(event.wait(Duration::from_secs(1)).await);
+        // The Unix descriptor stays readable until the queue is read; the
+        // Windows event resets automatically when a wait returns.
+        #[cfg(unix)]
+        assert!(event.wait(Duration::from_secs(1)).await);
+
+        assert_eq!(drain(api), 1);
+        assert!(!event.wait(Duration::from_millis(20)).await);
+
+        send(api);
+        send(api);
+        assert!(event.wait(Duration::from_secs(1)).await);
+        assert_eq!(drain(api), 2);
+
+        event.unregister(api, RX);
+        for channel in [TX, RX] {
+            assert_eq!(unsafe { (api.can_uninitialize)(channel) }, PEAK_ERROR_OK);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+/// Translation of PCAN-Basic message types that are not plain data frames.
+use crosscan::can::CanFrame;
+
+// TPCANMessageType flags not exported by peak-can-sys.
+const PCAN_MESSAGE_RTR: u8 = 0x01;
+const PCAN_MESSAGE_ERRFRAME: u8 = 0x40;
+const PCAN_MESSAGE_STATUS: u8 = 0x80;
+
+// TPCANStatus bits reported in status messages.
+const PCAN_ERROR_OVERRUN: u32 = 0x0000_0002;
+const PCAN_ERROR_BUSLIGHT: u32 = 0x0000_0004;
+const PCAN_ERROR_BUSHEAVY: u32 = 0x0000_0008;
+const PCAN_ERROR_BUSOFF: u32 = 0x0000_0010;
+const PCAN_ERROR_QOVERRUN: u32 = 0x0000_0040;
+const PCAN_ERROR_BUSPASSIVE: u32 = 0x0004_0000;
+
+// Error classes carried in the id of a SocketCAN error frame (<linux/can/error.h>).
+const CAN_ERR_CRTL: u32 = 0x0000_0004;
+const CAN_ERR_PROT: u32 = 0x0000_0008;
+const CAN_ERR_BUSOFF: u32 = 0x0000_0040;
+const CAN_ERR_BUSERROR: u32 = 0x0000_0080;
+const CAN_ERR_RESTARTED: u32 = 0x0000_0100;
+
+// Error types in the id of a PCAN error frame.
+const PCAN_ERRFRAME_BIT: u32 = 0x01;
+const PCAN_ERRFRAME_FORM: u32 = 0x02;
+const PCAN_ERRFRAME_STUFF: u32 = 0x04;
+
+/// Build a frame from a received PCAN message of any type.
+///
+/// Status messages become error frames describing the bus state (`None` for
+/// status codes that do not affect it), PCAN error frames become bus error
+/// frames, and `PCAN_MESSAGE_RTR` messages become remote frames.
+pub(crate) fn frame_from_message(
+    msgtype: u8,
+    id: u32,
+    data: &[u8],
+    dlc: usize,
+) -> std::io::Result<Option<CanFrame>> {
+    let extended = (msgtype & peak_can_sys::PEAK_MESSAGE_EXTENDED as u8) != 0;
+    let to_io = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+
+    if msgtype & PCAN_MESSAGE_STATUS != 0 {
+        return status_frame(data);
+    }
+    if msgtype & PCAN_MESSAGE_ERRFRAME != 0 {
+        // DATA[2] / DATA[3] hold the RX / TX error counters.
+        log::debug!(
+            "pcan: error frame type 0x{:X} (rx errors {}, tx errors {})",
+            id,
+            data.get(2).copied().unwrap_or(0),
+            data.get(3).copied().unwrap_or(0)
+        );
+        let mut class = CAN_ERR_BUSERROR;
+        if id & (PCAN_ERRFRAME_BIT | PCAN_ERRFRAME_FORM | PCAN_ERRFRAME_STUFF) != 0 {
+            class |= CAN_ERR_PROT;
+        }
+        return CanFrame::new_error(class).map(Some).map_err(to_io);
+    }
+
+    let frame = if msgtype & PCAN_MESSAGE_RTR != 0 {
+        CanFrame::new_remote(id, dlc.min(8), extended)
+    } else if extended {
+        CanFrame::new_eff(id, data)
+    } else {
+        CanFrame::new(id, data)
+    };
+    frame.map(Some).map_err(to_io)
+}
+
+/// Map a status message (status code in DATA[0..4], big endian) to an error frame.
+fn status_frame(data: &[u8]) -> std::io::Result<Option<CanFrame>> {
+    let mut raw = [0u8; 4];
+    let n = data.len().min(4);
+    raw[..n].copy_from_slice(&data[..n]);
+    let status = u32::from_be_bytes(raw);
+
+    let class = if status & PCAN_ERROR_BUSOFF != 0 {
+        log::warn!("pcan: bus off");
+        CAN_ERR_BUSOFF
+    } else if status & (PCAN_ERROR_BUSLIGHT | PCAN_ERROR_BUSHEAVY | PCAN_ERROR_BUSPASSIVE) != 0 {
+        log::warn!("pcan: bus error state (status 0x{:08X})", status);
+        CAN_ERR_CRTL
+    } else if status & (PCAN_ERROR_OVERRUN | PCAN_ERROR_QOVERRUN) != 0 {
+        log::warn!("pcan: receive overrun (status 0x{:08X})", status);
+        CAN_ERR_CRTL
+    } else if status == 0 {
+        log::info!("pcan: bus ok");
+        CAN_ERR_RESTARTED
+    } else {
+        log::debug!("pcan: status message 0x{:08X}", status);
+        return Ok(None);
+    };
+
+    CanFrame::new_error(class)
+        .map(Some)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// PCAN message type for transmitting `frame`, without any FD flags.
+pub(crate) fn message_type(frame: &CanFrame) -> std::io::Result<u8> {
+    if frame.is_error() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "PCAN cannot transmit error frames",
+        ));
+    }
+    let mut msgtype = if frame.is_extended() {
+        peak_can_sys::PEAK_MESSAGE_EXTENDED as u8
+    } else {
+        peak_can_sys::PEAK_MESSAGE_STANDARD as u8
+    };
+    if frame.is_rtr() {
+        msgtype |= PCAN_MESSAGE_RTR;
+    }
+    Ok(msgtype)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STANDARD: u8 = peak_can_sys::PEAK_MESSAGE_STANDARD as u8;
+    const EXTENDED: u8 = peak_can_sys::PEAK_MESSAGE_EXTENDED as u8;
+
+    fn status(code: u32) -> Option<CanFrame> {
+        frame_from_message(PCAN_MESSAGE_STATUS, 0, &code.to_be_bytes(), 4).unwrap()
+    }
+
+    #[test]
+    fn status_messages_become_error_frames() {
+        for (code, class) in [
+            (PCAN_ERROR_BUSOFF, CAN_ERR_BUSOFF),
+            // Bus off wins over the warning levels reported with it.
+            (PCAN_ERROR_BUSOFF | PCAN_ERROR_BUSHEAVY, CAN_ERR_BUSOFF),
+            (PCAN_ERROR_BUSLIGHT, CAN_ERR_CRTL),
+            (PCAN_ERROR_BUSHEAVY, CAN_ERR_CRTL),
+            (PCAN_ERROR_BUSPASSIVE, CAN_ERR_CRTL),
+            (PCAN_ERROR_OVERRUN, CAN_ERR_CRTL),
+            (PCAN_ERROR_QOVERRUN, CAN_ERR_CRTL),
+            (0, CAN_ERR_RESTARTED),
+        ] {
+            let frame = status(code).unwrap();
+            assert!(frame.is_error(), "0x{:08X}", code);
+            assert_eq!(frame.id(), class, "0x{:08X}", code);
+        }
+        // Other status bits do not describe the bus.
+        assert!(status(0x0000_0020).is_none());
+    }
+
+    #[test]
+    fn error_frames_report_protocol_violations() {
+        for (kind, class) in [
+            (PCAN_ERRFRAME_BIT, CAN_ERR_BUSERROR | CAN_ERR_PROT),
+            (PCAN_ERRFRAME_FORM, CAN_ERR_BUSERROR | CAN_ERR_PROT),
+            (PCAN_ERRFRAME_STUFF, CAN_ERR_BUSERROR | CAN_ERR_PROT),
+            (0x08, CAN_ERR_BUSERROR),
+        ] {
+            let frame = frame_from_message(PCAN_MESSAGE_ERRFRAME, kind, &[0, 0, 5, 1], 4)
+                .unwrap()
+                .unwrap();
+            assert!(frame.is_error());
+            assert_eq!(frame.id(), class, "type 0x{:X}", kind);
+        }
+    }
+
+    #[test]
+    fn data_and_remote_messages_keep_their_id_length() {
+        let standard = frame_from_message(STANDARD, 0x123, &[1, 2], 2)
+            .unwrap()
+            .unwrap();
+        assert!(!standard.is_extended() && !standard.is_rtr());
+        assert_eq!((standard.id(), standard.data()), (0x123, &[1, 2][..]));
+
+        let extended = frame_from_message(EXTENDED, 0x1234_5678, &[3], 1)
+            .unwrap()
+            .unwrap();
+        assert!(extended.is_extended());
+        assert_eq!(extended.id(), 0x1234_5678);
+
+        let remote = frame_from_message(EXTENDED | PCAN_MESSAGE_RTR, 0x1ABC, &[], 15)
+            .unwrap()
+            .unwrap();
+        assert!(remote.is_rtr() && remote.is_extended());
+        assert_eq!(remote.id(), 0x1ABC);
+        assert!(remote.data().is_empty());
+    }
+
+    #[test]
+    fn message_types_for_transmission() {
+        let standard = CanFrame::new(0x123, &[1]).unwrap();
+        assert_eq!(message_type(&standard).unwrap(), STANDARD);
+        let extended = CanFrame::new_eff(0x123, &[1]).unwrap();
+        assert_eq!(message_type(&extended).unwrap(), EXTENDED);
+        let remote = CanFrame::new_remote(0x123, 4, false).unwrap();
+        assert_eq!(message_type(&remote).unwrap(), STANDARD | PCAN_MESSAGE_RTR);
+        let remote = CanFrame::new_remote(0x123, 4, true).unwrap();
+        assert_eq!(message_type(&remote).unwrap(), EXTENDED | PCAN_MESSAGE_RTR);
+        let error = CanFrame::new_error(CAN_ERR_CRTL).unwrap();
+        assert!(message_type(&error).is_err());
+    }
+}
@@ -0,0 +1,12 @@
+/// Driver implementation for PEAK-System adapters using the PCAN-Basic API.
+mod api;
+mod driver;
+mod event;
+mod messages;
+mod params;
+mod timing;
+
+pub use api::{PCAN_LIBRARY_ENV, set_pcan_library_path};
+pub use driver::PcanDriver;
+pub use params::PcanChannelInfo;
+pub use timing::fd_bitrate_string;
@@ -0,0 +1,358 @@
+/// Typed access to PCAN-Basic parameters (`CAN_GetValue` / `CAN_SetValue`).
+use peak_can_sys::*;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+
+use super::api::{PcanApi, error_text, pcan_api};
+use super::driver::{PcanDriver, channel_name};
+
+/// Handle used for parameters that are not bound to a channel.
+const PCAN_NONEBUS: WORD = 0x00;
+
+// TPCANParameter values.
+const PCAN_DEVICE_ID: BYTE = 0x01;
+const PCAN_API_VERSION: BYTE = 0x05;
+const PCAN_CHANNEL_VERSION: BYTE = 0x06;
+const PCAN_BUSOFF_AUTORESET: BYTE = 0x07;
+const PCAN_LISTEN_ONLY: BYTE = 0x08;
+const PCAN_HARDWARE_NAME: BYTE = 0x0E;
+const PCAN_ALLOW_ERROR_FRAMES: BYTE = 0x20;
+const PCAN_FIRMWARE_VERSION: BYTE = 0x29;
+const PCAN_ATTACHED_CHANNELS_COUNT: BYTE = 0x2A;
+const PCAN_ATTACHED_CHANNELS: BYTE = 0x2B;
+
+const PCAN_PARAMETER_OFF: u32 = 0x00;
+const PCAN_PARAMETER_ON: u32 = 0x01;
+
+// TPCANChannelInformation::channel_condition values.
+const PCAN_CHANNEL_AVAILABLE: DWORD = 0x01;
+const PCAN_CHANNEL_OCCUPIED: DWORD = 0x02;
+
+// TPCANChannelInformation::device_features bits.
+const FEATURE_FD_CAPABLE: DWORD = 0x01;
+
+/// Maximum length of strings returned by PCAN-Basic parameters.
+const MAX_LENGTH_VERSION_STRING: usize = 256;
+const MAX_LENGTH_HARDWARE_NAME: usize = 33;
+
+/// `TPCANChannelInformation` as returned by `PCAN_ATTACHED_CHANNELS`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawChannelInformation {
+    channel_handle: WORD,
+    device_type: BYTE,
+    controller_number: BYTE,
+    device_features: DWORD,
+    device_name: [c_char; MAX_LENGTH_HARDWARE_NAME],
+    device_id: DWORD,
+    channel_condition: DWORD,
+}
+
+/// A PCAN channel reported by the driver as attached to the system.
+#[derive(Debug, Clone)]
+pub struct PcanChannelInfo {
+    /// Channel name accepted by [`PcanDriver::open`], e.g. `USBBUS1`.
+    pub name: String,
+    pub handle: u16,
+    pub device_name: String,
+    pub device_id: u32,
+    pub controller_number: u8,
+    pub fd_capable: bool,
+    /// Channel can be initialized by this process.
+    pub available: bool,
+    /// Channel is already in use by another application.
+    pub occupied: bool,
+}
+
+fn get_value(api: &PcanApi, channel: WORD, param: BYTE, buf: &mut [u8]) -> std::io::Result<()> {
+    let status = unsafe {
+        (api.can_get_value)(
+            channel,
+            param,
+            buf.as_mut_ptr() as *mut c_void,
+            buf.len() as DWORD,
+        )
+    };
+    if status != PEAK_ERROR_OK {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!(
+                "CAN_GetValue(0x{:02X}) failed: {}",
+                param,
+                error_text(api, status)
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn set_value(api: &PcanApi, channel: WORD, param: BYTE, buf: &mut [u8]) -> std::io::Result<()> {
+    let status = unsafe {
+        (api.can_set_value)(
+            channel,
+            param,
+            buf.as_mut_ptr() as *mut c_void,
+            buf.len() as DWORD,
+        )
+    };
+    if status != PEAK_ERROR_OK {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!(
+                "CAN_SetValue(0x{:02X}) failed: {}",
+                param,
+                error_text(api, status)
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn get_u32(api: &PcanApi, channel: WORD, param: BYTE) -> std::io::Result<u32> {
+    let mut buf = [0u8; 4];
+    get_value(api, channel, param, &mut buf)?;
+    Ok(u32::from_ne_bytes(buf))
+}
+
+fn set_u32(api: &PcanApi, channel: WORD, param: BYTE, value: u32) -> std::io::Result<()> {
+    set_value(api, channel, param, &mut value.to_ne_bytes())
+}
+
+fn get_string(api: &PcanApi, channel: WORD, param: BYTE, len: usize) -> std::io::Result<String> {
+    let mut buf = vec![0u8; len];
+    get_value(api, channel, param, &mut buf)?;
+    let text = CStr::from_bytes_until_nul(&buf)
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| String::from_utf8_lossy(&buf).into_owned());
+    Ok(text)
+}
+
+fn on_off(on: bool) -> u32 {
+    if on {
+        PCAN_PARAMETER_ON
+    } else {
+        PCAN_PARAMETER_OFF
+    }
+}
+
+impl PcanDriver {
+    /// Version of the loaded PCAN-Basic API, e.g. `4.6.1.728`.
+    pub fn api_version() -> std::io::Result<String> {
+        let api = pcan_api()?;
+        get_string(
+            api,
+            PCAN_NONEBUS,
+            PCAN_API_VERSION,
+            MAX_LENGTH_VERSION_STRING,
+        )
+    }
+
+    /// List channels the PCAN driver reports as attached (`PCAN_ATTACHED_CHANNELS`).
+    pub fn attached_channels() -> std::io::Result<Vec<PcanChannelInfo>> {
+        let api = pcan_api()?;
+        let count = get_u32(api, PCAN_NONEBUS, PCAN_ATTACHED_CHANNELS_COUNT)? as usize;
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let entry_size = std::mem::size_of::<RawChannelInformation>();
+        let mut buf = vec![0u8; count * entry_size];
+        get_value(api, PCAN_NONEBUS, PCAN_ATTACHED_CHANNELS, &mut buf)?;
+
+        let channels = buf
+            .chunks_exact(entry_size)
+            .map(|chunk| {
+                // The byte buffer carries no alignment guarantee for the struct.
+                let raw = unsafe {
+                    std::ptr::read_unaligned(chunk.as_ptr() as *const RawChannelInformation)
+                };
+                let device_name = unsafe { CStr::from_ptr(raw.device_name.as_ptr()) }
+                    .to_string_lossy()
+                    .into_owned();
+                PcanChannelInfo {
+                    name: channel_name(raw.channel_handle)
+                        .unwrap_or_else(|| format!("0x{:X}", raw.channel_handle)),
+                    handle: raw.channel_handle,
+                    device_name,
+                    device_id: raw.device_id,
+                    controller_number: raw.controller_number,
+                    fd_capable: raw.device_features & FEATURE_FD_CAPABLE != 0,
+                    available: raw.channel_condition & PCAN_CHANNEL_AVAILABLE != 0,
+                    occupied: raw.channel_condition & PCAN_CHANNEL_OCCUPIED != 0,
+                }
+            })
+            .collect();
+
+        Ok(channels)
+    }
+
+    /// User configurable device identifier (`PCAN_DEVICE_ID`).
+    pub fn device_id(&self) -> std::io::Result<u32> {
+        get_u32(pcan_api()?, self.channel, PCAN_DEVICE_ID)
+    }
+
+    /// Hardware name, e.g. `PCAN-USB FD`.
+    pub fn hardware_name(&self) -> std::io::Result<String> {
+        get_string(
+            pcan_api()?,
+            self.channel,
+            PCAN_HARDWARE_NAME,
+            MAX_LENGTH_HARDWARE_NAME,
+        )
+    }
+
+    /// Version and copyright of the channel's driver.
+    pub fn channel_version(&self) -> std::io::Result<String> {
+        get_string(
+            pcan_api()?,
+            self.channel,
+            PCAN_CHANNEL_VERSION,
+            MAX_LENGTH_VERSION_STRING,
+        )
+    }
+
+    /// Firmware version of the adapter, e.g. `3.2.0`.
+    pub fn firmware_version(&self) -> std::io::Result<String> {
+        get_string(
+            pcan_api()?,
+            self.channel,
+            PCAN_FIRMWARE_VERSION,
+            MAX_LENGTH_VERSION_STRING,
+        )
+    }
+
+    pub fn listen_only(&self) -> std::io::Result<bool> {
+        Ok(get_u32(pcan_api()?, self.channel, PCAN_LISTEN_ONLY)? == PCAN_PARAMETER_ON)
+    }
+
+    /// Enable or disable listen-only mode. May be set before the channel is opened.
+    pub fn set_listen_only(&self, on: bool) -> std::io::Result<()> {
+        set_u32(pcan_api()?, self.channel, PCAN_LISTEN_ONLY, on_off(on))
+    }
+
+    pub fn bus_off_auto_reset(&self) -> std::io::Result<bool> {
+        Ok(get_u32(pcan_api()?, self.channel, PCAN_BUSOFF_AUTORESET)? == PCAN_PARAMETER_ON)
+    }
+
+    /// Let the driver automatically reset the controller after a bus-off condition.
+    pub fn set_bus_off_auto_reset(&self, on: bool) -> std::io::Result<()> {
+        set_u32(pcan_api()?, self.channel, PCAN_BUSOFF_AUTORESET, on_off(on))
+    }
+
+    pub fn error_frames(&self) -> std::io::Result<bool> {
+        Ok(get_u32(pcan_api()?, self.channel, PCAN_ALLOW_ERROR_FRAMES)? == PCAN_PARAMETER_ON)
+    }
+
+    /// Deliver bus error frames through the receive queue.
+    pub fn set_error_frames(&self, on: bool) -> std::io::Result<()> {
+        set_u32(
+            pcan_api()?,
+            self.channel,
+            PCAN_ALLOW_ERROR_FRAMES,
+            on_off(on),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::api::use_stub_library;
+    use super::*;
+
+    #[test]
+    fn reads_the_api_version() {
+        use_stub_library();
+        assert_eq!(PcanDriver::api_version().unwrap(), pcan_stub::API_VERSION);
+    }
+
+    #[test]
+    fn enumerates_attached_channels() {
+        use_stub_library();
+        let channels = PcanDriver::attached_channels().unwrap();
+        assert_eq!(channels.len(), 2);
+
+        let first = &channels[0];
+        assert_eq!(first.name, "USBBUS1");
+        assert_eq!(first.handle, pcan_stub::PCAN_USBBUS1);
+        assert_eq!(first.device_name, pcan_stub::HARDWARE_NAME);
+        assert_eq!(first.device_id, pcan_stub::DEVICE_ID);
+        assert_eq!(first.controller_number, 0);
+        assert!(first.fd_capable);
+        assert!(first.available && !first.occupied);
+
+        let second = &channels[1];
+        assert_eq!(second.name, "USBBUS2");
+        assert_eq!(second.controller_number, 1);
+        assert!(!second.fd_capable);
+        assert!(!second.available && second.occupied);
+    }
+
+    #[tokio::test]
+    async fn reads_channel_information() {
+        use_stub_library();
+        let driver = PcanDriver::open("USBBUS3").await.unwrap();
+        assert_eq!(driver.hardware_name().unwrap(), pcan_stub::HARDWARE_NAME);
+        assert_eq!(
+            driver.channel_version().unwrap(),
+            pcan_stub::CHANNEL_VERSION
+        );
+        assert_eq!(
+            driver.firmware_version().unwrap(),
+            pcan_stub::FIRMWARE_VERSION
+        );
+        assert_eq!(driver.device_id().unwrap(), pcan_stub::DEVICE_ID);
+    }
+
+    #[tokio::test]
+    async fn sets_and_reads_back_switches() {
+        use_stub_library();
+        let driver = PcanDriver::open("USBBUS4").await.unwrap();
+
+        assert!(!driver.listen_only().unwrap());
+        driver.set_listen_only(true).unwrap();
+        assert!(driver.listen_only().unwrap());
+        driver.set_listen_only(false).unwrap();
+        assert!(!driver.listen_only().unwrap());
+
+        driver.set_bus_off_auto_reset(true).unwrap();
+        assert!(driver.bus_off_auto_reset().unwrap());
+        assert!(!driver.error_frames().unwrap());
+        driver.set_error_frames(true).unwrap();
+        assert!(driver.error_frames().unwrap());
+    }
+
+    #[test]
+    fn failures_carry_the_error_text() {
+        let api = use_stub_library();
+
+        let err = get_u32(api, pcan_stub::PCAN_USBBUS1 + 4, 0x7F).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "CAN_GetValue(0x7F) failed: Parameter is not allowed or is invalid (0x00004000)"
+        );
+
+        let err = set_u32(api, 0x99, PCAN_LISTEN_ONLY, PCAN_PARAMETER_ON).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "CAN_SetValue(0x08) failed: Hardware handle is invalid (0x00001400)"
+        );
+
+        // Too small a buffer for the version string.
+        let err = get_string(api, PCAN_NONEBUS, PCAN_API_VERSION, 4).unwrap_err();
+        assert!(
+            err.to_string().contains("Parameter value is invalid"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn unknown_status_codes_fall_back_to_hex() {
+        let api = use_stub_library();
+        assert_eq!(error_text(api, 0x0001_2345), "0x00012345");
+        assert_eq!(
+            error_text(api, PEAK_ERROR_QRCVEMPTY),
+            "Receive queue is empty (0x00000020)"
+        );
+    }
+}
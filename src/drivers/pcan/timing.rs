@@ -0,0 +1,176 @@
+//! CAN FD bitrate strings for `CAN_InitializeFD`.
+
+/// Clock used for bitrate strings built by [`fd_bitrate_string`].
+const PCAN_FD_CLOCK_HZ: u32 = 80_000_000;
+
+/// Build a PCAN-Basic FD bitrate string for an 80 MHz clock.
+///
+/// Nominal timing targets an 80% sample point and data timing 75%, using the
+/// smallest prescaler that fits the PCAN-FD segment limits.
+pub fn fd_bitrate_string(nominal: u32, data: u32) -> Option<String> {
+    let (nom_brp, nom_tseg1, nom_tseg2) = fd_phase_timing(nominal, 256, 128, 80)?;
+    let (data_brp, data_tseg1, data_tseg2) = fd_phase_timing(data, 32, 16, 75)?;
+    Some(format!(
+        "f_clock_mhz={}, nom_brp={}, nom_tseg1={}, nom_tseg2={}, nom_sjw={}, data_brp={}, data_tseg1={}, data_tseg2={}, data_sjw={}",
+        PCAN_FD_CLOCK_HZ / 1_000_000,
+        nom_brp,
+        nom_tseg1,
+        nom_tseg2,
+        nom_tseg2,
+        data_brp,
+        data_tseg1,
+        data_tseg2,
+        data_tseg2
+    ))
+}
+
+/// Find `(brp, tseg1, tseg2)` producing `bitrate` exactly from [`PCAN_FD_CLOCK_HZ`].
+pub(crate) fn fd_phase_timing(
+    bitrate: u32,
+    max_tseg1: u32,
+    max_tseg2: u32,
+    sample_point_pct: u32,
+) -> Option<(u32, u32, u32)> {
+    if bitrate == 0 {
+        return None;
+    }
+    for brp in 1..=1024u32 {
+        let divisor = brp.checked_mul(bitrate)?;
+        if PCAN_FD_CLOCK_HZ % divisor != 0 {
+            continue;
+        }
+        let total_tq = PCAN_FD_CLOCK_HZ / divisor;
+        if total_tq < 4 {
+            break;
+        }
+        let tseg2 = (total_tq * (100 - sample_point_pct) / 100).max(1);
+        let tseg1 = total_tq - 1 - tseg2;
+        if (1..=max_tseg1).contains(&tseg1) && tseg2 <= max_tseg2 {
+            return Some((brp, tseg1, tseg2));
+        }
+    }
+    None
+}
+
+/// Recover the nominal and data bitrates from a PCAN-Basic FD bitrate string.
+pub(crate) fn parse_fd_bitrate_string(s: &str) -> Option<(u32, u32)> {
+    let mut clock_hz = None;
+    let (mut nom_brp, mut nom_tseg1, mut nom_tseg2) = (None, None, None);
+    let (mut data_brp, mut data_tseg1, mut data_tseg2) = (None, None, None);
+
+    for pair in s.split(',') {
+        let (key, value) = pair.split_once('=')?;
+        let value = value.trim().parse::<u32>().ok()?;
+        match key.trim() {
+            "f_clock" => clock_hz = Some(value),
+            "f_clock_mhz" => clock_hz = Some(value.checked_mul(1_000_000)?),
+            "nom_brp" => nom_brp = Some(value),
+            "nom_tseg1" => nom_tseg1 = Some(value),
+            "nom_tseg2" => nom_tseg2 = Some(value),
+            "data_brp" => data_brp = Some(value),
+            "data_tseg1" => data_tseg1 = Some(value),
+            "data_tseg2" => data_tseg2 = Some(value),
+            _ => {}
+        }
+    }
+
+    let clock_hz = clock_hz?;
+    let rate = |brp: Option<u32>, tseg1: Option<u32>, tseg2: Option<u32>| -> Option<u32> {
+        let tq = brp?.checked_mul(1 + tseg1? + tseg2?)?;
+        (tq != 0).then(|| clock_hz / tq)
+    };
+    Some((
+        rate(nom_brp, nom_tseg1, nom_tseg2)?,
+        rate(data_brp, data_tseg1, data_tseg2)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_rates_use_the_smallest_prescaler() {
+        assert_eq!(
+            fd_bitrate_string(500_000, 2_000_000).as_deref(),
+            Some(
+                "f_clock_mhz=80, nom_brp=1, nom_tseg1=127, nom_tseg2=32, nom_sjw=32, \
+                 data_brp=1, data_tseg1=29, data_tseg2=10, data_sjw=10"
+            )
+        );
+        assert_eq!(
+            fd_bitrate_string(1_000_000, 5_000_000).as_deref(),
+            Some(
+                "f_clock_mhz=80, nom_brp=1, nom_tseg1=63, nom_tseg2=16, nom_sjw=16, \
+                 data_brp=1, data_tseg1=11, data_tseg2=4, data_sjw=4"
+            )
+        );
+    }
+
+    #[test]
+    fn segment_limits_raise_the_prescaler() {
+        // 640 quanta would need a tseg1 of 511.
+        assert_eq!(fd_phase_timing(125_000, 256, 128, 80), Some((2, 255, 64)));
+        // The data phase allows a tseg2 of at most 16.
+        assert_eq!(fd_phase_timing(500_000, 32, 16, 75), Some((4, 29, 10)));
+
+        for (bitrate, max_tseg1, max_tseg2, sample_point) in [
+            (1_000_000, 256, 128, 80),
+            (125_000, 256, 128, 80),
+            (2_000_000, 32, 16, 75),
+            (8_000_000, 32, 16, 75),
+        ] {
+            let (brp, tseg1, tseg2) =
+                fd_phase_timing(bitrate, max_tseg1, max_tseg2, sample_point).unwrap();
+            assert_eq!(PCAN_FD_CLOCK_HZ / (brp * (1 + tseg1 + tseg2)), bitrate);
+            assert_eq!(PCAN_FD_CLOCK_HZ % (brp * (1 + tseg1 + tseg2)), 0);
+            assert!((1..=max_tseg1).contains(&tseg1) && (1..=max_tseg2).contains(&tseg2));
+        }
+    }
+
+    #[test]
+    fn unreachable_rates_have_no_timing() {
+        // 0, rates that do not divide 80 MHz, and too few time quanta.
+        for data in [0, 3_000_000, 12_000_000, 40_000_000] {
+            assert_eq!(fd_bitrate_string(500_000, data), None, "{}", data);
+        }
+        assert_eq!(fd_bitrate_string(0, 2_000_000), None);
+        assert_eq!(fd_bitrate_string(300_000, 2_000_000), None);
+    }
+
+    #[test]
+    fn parsing_recovers_the_rates() {
+        for (nominal, data) in [
+            (500_000, 2_000_000),
+            (1_000_000, 5_000_000),
+            (250_000, 1_000_000),
+            (125_000, 500_000),
+            (1_000_000, 8_000_000),
+        ] {
+            let string = fd_bitrate_string(nominal, data).unwrap();
+            assert_eq!(parse_fd_bitrate_string(&string), Some((nominal, data)));
+        }
+
+        // Clocks may be given in Hz, and unknown keys are ignored.
+        assert_eq!(
+            parse_fd_bitrate_string(
+                "f_clock=40000000, nom_brp=1, nom_tseg1=63, nom_tseg2=16, nom_sjw=16, \
+                 data_brp=1, data_tseg1=15, data_tseg2=4, data_sjw=4, data_ssp_offset=14"
+            ),
+            Some((500_000, 2_000_000))
+        );
+    }
+
+    #[test]
+    fn incomplete_strings_do_not_parse() {
+        for s in [
+            "",
+            "nom_brp=1, nom_tseg1=63, nom_tseg2=16, data_brp=1, data_tseg1=15, data_tseg2=4",
+            "f_clock_mhz=80, nom_brp=1, nom_tseg1=63, nom_tseg2=16, data_brp=1, data_tseg1=15",
+            "f_clock_mhz=80, nom_brp=0, nom_tseg1=63, nom_tseg2=16, data_brp=1, data_tseg1=15, data_tseg2=4",
+            "f_clock_mhz=80, nom_brp=x, nom_tseg1=63, nom_tseg2=16, data_brp=1, data_tseg1=15, data_tseg2=4",
+        ] {
+            assert_eq!(parse_fd_bitrate_string(s), None, "{}", s);
+        }
+    }
+}
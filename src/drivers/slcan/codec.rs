@@ -0,0 +1,629 @@
+/// Incremental SLCAN (LAWICEL ASCII) stream decoding and frame encoding.
+use crosscan::can::CanFrame;
+use memchr::memchr3;
+
+use crate::drivers::dlc::{fd_dlc_to_len, fd_len_to_dlc};
+
+/// ASCII BEL, sent by adapters to reject a command.
+const BEL: u8 = 0x07;
+
+/// Longest valid line: `B` + 8 digit id + DLC + 64 data bytes + 8 digit timestamp.
+const MAX_LINE_LEN: usize = 1 + 8 + 1 + 64 * 2 + 8;
+
+const CAN_SFF_MASK: u32 = 0x0000_07FF;
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+
+/// Something decoded from the adapter's byte stream.
+#[derive(Debug, Clone)]
+pub enum SlcanEvent {
+    /// A received CAN frame.
+    Frame(CanFrame),
+    /// An empty line, i.e. a positive acknowledgement of a command.
+    Ack,
+    /// A BEL byte, i.e. the adapter rejected a command.
+    Nack,
+    /// Any other printable line, e.g. a version or status reply.
+    Line(Vec<u8>),
+}
+
+/// Counters describing the health of an SLCAN byte stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SlcanStats {
+    /// Frames decoded successfully.
+    pub frames: u64,
+    /// Lines that failed to parse as a frame or contained non-printable bytes.
+    pub malformed_lines: u64,
+    /// Lines longer than any valid SLCAN line.
+    pub overlong_lines: u64,
+    /// Bytes dropped as part of malformed or overlong lines.
+    pub discarded_bytes: u64,
+    /// Commands rejected by the adapter with BEL.
+    pub nacks: u64,
+}
+
+/// Incremental decoder turning arbitrary chunks of serial data into [`SlcanEvent`]s.
+///
+/// The decoder never panics on malformed input. Bad lines are counted in
+/// [`SlcanStats`] and dropped, and decoding resumes at the next `\r` or BEL.
+#[derive(Debug, Default)]
+pub struct SlcanDecoder {
+    line: Vec<u8>,
+    /// Set while discarding the remainder of an overlong line.
+    overlong: bool,
+    /// Upper 32 bits of the 8 digit timestamp, advanced by `J` lines.
+    timestamp_high: u32,
+    stats: SlcanStats,
+}
+
+impl SlcanDecoder {
+    pub fn new() -> Self {
+        Self {
+            line: Vec::with_capacity(MAX_LINE_LEN),
+            ..Default::default()
+        }
+    }
+
+    pub fn stats(&self) -> SlcanStats {
+        self.stats
+    }
+
+    /// Drop any partially received line.
+    pub fn reset(&mut self) {
+        self.line.clear();
+        self.overlong = false;
+    }
+
+    /// Feed raw bytes into the decoder, appending decoded events to `events`.
+    pub fn push(&mut self, mut bytes: &[u8], events: &mut Vec<SlcanEvent>) {
+        while !bytes.is_empty() {
+            let (chunk, delimiter) = match memchr3(b'\r', b'\n', BEL, bytes) {
+                Some(pos) => (&bytes[..pos], Some(bytes[pos])),
+                None => (bytes, None),
+            };
+
+            self.append(chunk);
+
+            match delimiter {
+                Some(b'\r') => self.finish_line(events),
+                Some(BEL) => {
+                    if !self.line.is_empty() {
+                        self.drop_malformed();
+                    }
+                    self.overlong = false;
+                    self.stats.nacks += 1;
+                    events.push(SlcanEvent::Nack);
+                }
+                // `\n` is tolerated for adapters that terminate lines with CRLF.
+                _ => {}
+            }
+
+            bytes = &bytes[chunk.len() + delimiter.map_or(0, |_| 1)..];
+        }
+    }
+
+    fn append(&mut self, chunk: &[u8]) {
+        if chunk.is_empty() {
+            return;
+        }
+        if self.overlong {
+            self.stats.discarded_bytes += chunk.len() as u64;
+            return;
+        }
+        if self.line.len() + chunk.len() > MAX_LINE_LEN {
+            self.stats.overlong_lines += 1;
+            self.stats.discarded_bytes += (self.line.len() + chunk.len()) as u64;
+            self.line.clear();
+            self.overlong = true;
+            return;
+        }
+        self.line.extend_from_slice(chunk);
+    }
+
+    fn drop_malformed(&mut self) {
+        self.stats.malformed_lines += 1;
+        self.stats.discarded_bytes += self.line.len() as u64;
+        self.line.clear();
+    }
+
+    fn finish_line(&mut self, events: &mut Vec<SlcanEvent>) {
+        if self.overlong {
+            self.overlong = false;
+            return;
+        }
+
+        let event = match self.line.first() {
+            None => Some(SlcanEvent::Ack),
+            Some(b'J') => {
+                self.timestamp_high = self.timestamp_high.wrapping_add(1);
+                None
+            }
+            Some(b't' | b'T' | b'r' | b'R' | b'd' | b'D' | b'b' | b'B') => {
+                match parse_frame(&self.line, self.timestamp_high) {
+                    Some(frame) => {
+                        self.stats.frames += 1;
+                        Some(SlcanEvent::Frame(frame))
+                    }
+                    None => {
+                        log::debug!("slcan: malformed frame line {:02x?}", self.line);
+                        self.drop_malformed();
+                        return;
+                    }
+                }
+            }
+            Some(_) if self.line.iter().all(|b| b.is_ascii_graphic() || *b == b' ') => {
+                Some(SlcanEvent::Line(self.line.clone()))
+            }
+            Some(_) => {
+                self.drop_malformed();
+                return;
+            }
+        };
+
+        self.line.clear();
+        if let Some(event) = event {
+            events.push(event);
+        }
+    }
+}
+
+/// Parse a frame line (without its `\r`) in any of the `t`/`T`/`r`/`R` forms or
+/// the CAN FD `d`/`D` (no bitrate switch) and `b`/`B` (bitrate switch) forms.
+///
+/// A 4 digit millisecond timestamp, or an 8 digit microsecond timestamp whose
+/// upper bits are supplied by `timestamp_high`, may follow the data. The FD
+/// and bitrate switch markers are not kept in the returned frame.
+pub fn parse_frame(line: &[u8], timestamp_high: u32) -> Option<CanFrame> {
+    let (&kind, rest) = line.split_first()?;
+    let (extended, remote, fd) = match kind {
+        b't' => (false, false, false),
+        b'T' => (true, false, false),
+        b'r' => (false, true, false),
+        b'R' => (true, true, false),
+        b'd' | b'b' => (false, false, true),
+        b'D' | b'B' => (true, false, true),
+        _ => return None,
+    };
+
+    let id_len = if extended { 8 } else { 3 };
+    let id = parse_hex(rest.get(..id_len)?)?;
+    if id > if extended { CAN_EFF_MASK } else { CAN_SFF_MASK } {
+        return None;
+    }
+
+    let dlc = hex_digit(*rest.get(id_len)?)?;
+    let len = if fd {
+        fd_dlc_to_len(dlc)?
+    } else if dlc <= 8 {
+        dlc as usize
+    } else {
+        return None;
+    };
+
+    let data_start = id_len + 1;
+    let data_end = data_start + if remote { 0 } else { len * 2 };
+    let data_hex = rest.get(data_start..data_end)?;
+
+    let timestamp = match &rest[data_end..] {
+        [] => None,
+        ts @ [_, _, _, _] => Some(u64::from(parse_hex(ts)?) * 1000),
+        ts @ [_, _, _, _, _, _, _, _] => {
+            Some((u64::from(timestamp_high) << 32) | u64::from(parse_hex(ts)?))
+        }
+        _ => return None,
+    };
+
+    let mut data = Vec::with_capacity(len);
+    for pair in data_hex.chunks_exact(2) {
+        data.push((hex_digit(pair[0])? << 4) | hex_digit(pair[1])?);
+    }
+
+    let mut frame = if remote {
+        CanFrame::new_remote(id, len, extended).ok()?
+    } else if extended {
+        CanFrame::new_eff(id, &data).ok()?
+    } else {
+        CanFrame::new(id, &data).ok()?
+    };
+
+    frame.set_timestamp(timestamp);
+    Some(frame)
+}
+
+/// Encode a frame as an SLCAN command line (including the trailing `\r`).
+///
+/// Payloads longer than 8 bytes are sent as CAN FD frames, using the `b`/`B`
+/// (bitrate switch) variants when `brs` is set and `d`/`D` otherwise. FD
+/// payloads that do not match a DLC length exactly are zero padded. Since
+/// `CanFrame` has no FD flag, FD frames of up to 8 bytes go out as classic
+/// `t`/`T` frames.
+pub fn encode_frame(frame: &CanFrame, brs: bool) -> std::io::Result<String> {
+    if frame.is_error() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "SLCAN cannot transmit error frames",
+        ));
+    }
+
+    let data = frame.data();
+    let fd = data.len() > 8;
+
+    let letter = match (frame.is_rtr(), fd, brs, frame.is_extended()) {
+        (true, _, _, false) => 'r',
+        (true, _, _, true) => 'R',
+        (false, false, _, false) => 't',
+        (false, false, _, true) => 'T',
+        (false, true, false, false) => 'd',
+        (false, true, false, true) => 'D',
+        (false, true, true, false) => 'b',
+        (false, true, true, true) => 'B',
+    };
+
+    let mut cmd = String::with_capacity(20 + 64 * 2);
+    cmd.push(letter);
+
+    if frame.is_extended() {
+        cmd.push_str(&format!("{:08X}", frame.id()));
+    } else {
+        cmd.push_str(&format!("{:03X}", frame.id()));
+    }
+
+    if frame.is_rtr() {
+        cmd.push_str(&format!("{}", frame.dlc().min(8)));
+    } else if fd {
+        let dlc = fd_len_to_dlc(data.len()).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("CAN FD payload too long: {} bytes", data.len()),
+            )
+        })?;
+        cmd.push_str(&format!("{:X}", dlc));
+        for byte in data {
+            cmd.push_str(&format!("{:02X}", byte));
+        }
+        for _ in data.len()..fd_dlc_to_len(dlc).unwrap_or(data.len()) {
+            cmd.push_str("00");
+        }
+    } else {
+        cmd.push_str(&format!("{}", frame.dlc()));
+        for byte in data {
+            cmd.push_str(&format!("{:02X}", byte));
+        }
+    }
+
+    cmd.push('\r');
+    Ok(cmd)
+}
+
+/// The `Y` command selecting a CAN FD data phase bitrate, if the adapter has one.
+pub fn data_bitrate_command(bitrate: u32) -> Option<&'static [u8]> {
+    Some(match bitrate {
+        1_000_000 => b"Y1\r",
+        2_000_000 => b"Y2\r",
+        4_000_000 => b"Y4\r",
+        5_000_000 => b"Y5\r",
+        _ => return None,
+    })
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+fn parse_hex(s: &[u8]) -> Option<u32> {
+    if s.is_empty() || s.len() > 8 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0u32, |acc, &b| Some((acc << 4) | u32::from(hex_digit(b)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> CanFrame {
+        parse_frame(line.as_bytes(), 0).unwrap_or_else(|| panic!("failed to parse {:?}", line))
+    }
+
+    #[test]
+    fn parses_fd_lines_with_and_without_bitrate_switch() {
+        let data = [
+            0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC,
+        ];
+        for (line, id, extended) in [
+            ("d1239112233445566778899AABBCC", 0x123, false),
+            ("b1239112233445566778899AABBCC", 0x123, false),
+            ("D123456789112233445566778899AABBCC", 0x1234_5678, true),
+            ("B123456789112233445566778899AABBCC", 0x1234_5678, true),
+        ] {
+            let frame = parse(line);
+            assert_eq!(frame.id(), id, "{}", line);
+            assert_eq!(frame.is_extended(), extended, "{}", line);
+            assert_eq!(frame.data(), data, "{}", line);
+            assert!(!frame.is_rtr());
+        }
+    }
+
+    #[test]
+    fn maps_fd_dlc_codes_9_to_f() {
+        for (code, len) in [
+            ('9', 12),
+            ('A', 16),
+            ('B', 20),
+            ('C', 24),
+            ('D', 32),
+            ('E', 48),
+            ('F', 64),
+        ] {
+            let line = format!("d123{}{}", code, "5A".repeat(len));
+            assert_eq!(parse(&line).data(), vec![0x5A; len].as_slice(), "{}", line);
+            // Classic frames stop at DLC 8.
+            assert!(parse_frame(format!("t123{}", code).as_bytes(), 0).is_none());
+        }
+    }
+
+    #[test]
+    fn rejects_fd_lines_with_short_data() {
+        assert!(parse_frame(b"d1239112233", 0).is_none());
+        assert!(parse_frame(b"bFFF0", 0).is_none());
+    }
+
+    #[test]
+    fn parses_classic_and_remote_lines_from_a_capture() {
+        let mut decoder = SlcanDecoder::new();
+        let mut events = Vec::new();
+        decoder.push(b"t12321122\rT1FFFFFFF0\rr7FF4\rR000000018\r", &mut events);
+        let frames: Vec<_> = events
+            .into_iter()
+            .map(|event| match event {
+                SlcanEvent::Frame(frame) => frame,
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+
+        assert_eq!(frames.len(), 4);
+        assert_eq!(
+            (frames[0].id(), frames[0].data()),
+            (0x123, &[0x11, 0x22][..])
+        );
+        assert!(frames[1].is_extended() && frames[1].data().is_empty());
+        assert!(frames[2].is_rtr() && !frames[2].is_extended());
+        assert_eq!((frames[2].id(), frames[2].dlc()), (0x7FF, 4));
+        assert!(frames[3].is_rtr() && frames[3].is_extended());
+        assert_eq!(frames[3].dlc(), 8);
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse("t1231AA1234").timestamp(), Some(0x1234 * 1000));
+        assert_eq!(
+            parse_frame(b"t1231AA00001234", 2).unwrap().timestamp(),
+            Some((2 << 32) | 0x1234)
+        );
+        assert!(parse_frame(b"t1231AA123", 0).is_none());
+    }
+
+    #[test]
+    fn selects_the_data_bitrate_command() {
+        assert_eq!(data_bitrate_command(1_000_000), Some(&b"Y1\r"[..]));
+        assert_eq!(data_bitrate_command(2_000_000), Some(&b"Y2\r"[..]));
+        assert_eq!(data_bitrate_command(4_000_000), Some(&b"Y4\r"[..]));
+        assert_eq!(data_bitrate_command(5_000_000), Some(&b"Y5\r"[..]));
+        assert_eq!(data_bitrate_command(3_000_000), None);
+    }
+
+    #[test]
+    fn encodes_fd_frames_with_the_brs_letter() {
+        let data: Vec<u8> = (0..12).collect();
+        let frame = CanFrame::new(0x123, &data).unwrap();
+        assert_eq!(
+            encode_frame(&frame, false).unwrap(),
+            "d1239000102030405060708090A0B\r"
+        );
+        assert!(encode_frame(&frame, true).unwrap().starts_with("b1239"));
+
+        let frame = CanFrame::new_eff(0x1234_5678, &data).unwrap();
+        assert!(
+            encode_frame(&frame, false)
+                .unwrap()
+                .starts_with("D123456789")
+        );
+        assert!(
+            encode_frame(&frame, true)
+                .unwrap()
+                .starts_with("B123456789")
+        );
+    }
+
+    #[test]
+    fn pads_fd_payloads_to_the_next_dlc_length() {
+        let frame = CanFrame::new(0x001, &[0xFF; 13]).unwrap();
+        let line = encode_frame(&frame, false).unwrap();
+        assert_eq!(
+            line,
+            format!("d001A{}{}\r", "FF".repeat(13), "00".repeat(3))
+        );
+    }
+
+    #[test]
+    fn refuses_error_frames_and_oversized_payloads() {
+        assert!(encode_frame(&CanFrame::new_error(0x004).unwrap(), false).is_err());
+        assert!(encode_frame(&CanFrame::new(0x123, &[0; 65]).unwrap(), false).is_err());
+    }
+
+    #[test]
+    fn round_trips_through_encode_and_parse() {
+        let data: Vec<u8> = (0..64).map(|i| i as u8 * 3).collect();
+        let frames = [
+            CanFrame::new(0x000, &[]).unwrap(),
+            CanFrame::new(0x7FF, &data[..8]).unwrap(),
+            CanFrame::new_eff(0x1FFF_FFFF, &data[..3]).unwrap(),
+            CanFrame::new_remote(0x321, 6, false).unwrap(),
+            CanFrame::new_remote(0x0ABC_DEF0, 0, true).unwrap(),
+            CanFrame::new(0x456, &data[..12]).unwrap(),
+            CanFrame::new_eff(0x10, &data[..48]).unwrap(),
+            CanFrame::new(0x555, &data).unwrap(),
+        ];
+        for frame in &frames {
+            for brs in [false, true] {
+                let line = encode_frame(frame, brs).unwrap();
+                let parsed = parse_frame(line.trim_end_matches('\r').as_bytes(), 0)
+                    .unwrap_or_else(|| panic!("failed to parse {:?}", line));
+                assert_eq!(parsed.id(), frame.id(), "{}", line);
+                assert_eq!(parsed.is_extended(), frame.is_extended(), "{}", line);
+                assert_eq!(parsed.is_rtr(), frame.is_rtr(), "{}", line);
+                assert_eq!(parsed.dlc(), frame.dlc(), "{}", line);
+                assert_eq!(parsed.data(), frame.data(), "{}", line);
+            }
+        }
+    }
+
+    /// Deterministic xorshift generator, so failures reproduce.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn decode(chunks: &[&[u8]]) -> (Vec<SlcanEvent>, SlcanStats) {
+        let mut decoder = SlcanDecoder::new();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            decoder.push(chunk, &mut events);
+        }
+        (events, decoder.stats())
+    }
+
+    fn describe(events: &[SlcanEvent]) -> Vec<String> {
+        events.iter().map(|event| format!("{:?}", event)).collect()
+    }
+
+    const CAPTURE: &[u8] =
+        b"V1013\rt12321122\r\x07T1FFFFFFF0\rr7FF4\r\rb1239000102030405060708090A0B\rt1230\r";
+
+    #[test]
+    fn arbitrary_chunks_never_panic() {
+        // Bytes SLCAN gives meaning to, mixed in often enough to form lines.
+        const INTERESTING: &[u8] = b"\r\n\x07tTrRdDbBJV0123456789ABCDEFabcdef";
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+        for _ in 0..2000 {
+            let len = rng.below(600);
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| {
+                    if rng.below(4) == 0 {
+                        rng.next() as u8
+                    } else {
+                        INTERESTING[rng.below(INTERESTING.len())]
+                    }
+                })
+                .collect();
+
+            let mut decoder = SlcanDecoder::new();
+            let mut events = Vec::new();
+            let mut rest = &bytes[..];
+            while !rest.is_empty() {
+                let (chunk, tail) = rest.split_at(1 + rng.below(rest.len().min(64)));
+                decoder.push(chunk, &mut events);
+                rest = tail;
+            }
+
+            let stats = decoder.stats();
+            let frames = events
+                .iter()
+                .filter(|event| matches!(event, SlcanEvent::Frame(_)))
+                .count() as u64;
+            assert_eq!(stats.frames, frames);
+            assert!(stats.discarded_bytes <= len as u64);
+        }
+    }
+
+    #[test]
+    fn resyncs_after_garbage() {
+        let (events, stats) = decode(&[b"\x01\xFFzz\x02\rtXYZ1\rt1231AA\r"]);
+        assert_eq!(events.len(), 1, "{:?}", events);
+        assert!(matches!(&events[0], SlcanEvent::Frame(frame) if frame.id() == 0x123));
+        assert_eq!(stats.malformed_lines, 2);
+        assert_eq!(stats.discarded_bytes, 5 + 5);
+    }
+
+    #[test]
+    fn resyncs_after_an_overlong_line() {
+        let overlong = [b'7'; MAX_LINE_LEN + 10];
+        for chunk_len in [1, 7, MAX_LINE_LEN, overlong.len()] {
+            let mut chunks: Vec<&[u8]> = overlong.chunks(chunk_len).collect();
+            chunks.push(b"\rt1231AA\r");
+            let (events, stats) = decode(&chunks);
+            assert_eq!(events.len(), 1, "chunks of {}: {:?}", chunk_len, events);
+            assert!(matches!(&events[0], SlcanEvent::Frame(_)));
+            assert_eq!(stats.overlong_lines, 1);
+            assert_eq!(stats.discarded_bytes, overlong.len() as u64);
+        }
+
+        // The longest valid line still fits.
+        let line = format!("B12345678F{}12345678\r", "AB".repeat(64));
+        assert_eq!(line.len() - 1, MAX_LINE_LEN);
+        let (events, stats) = decode(&[line.as_bytes()]);
+        assert!(matches!(&events[..], [SlcanEvent::Frame(_)]));
+        assert_eq!(stats.overlong_lines, 0);
+    }
+
+    #[test]
+    fn bel_ends_a_partial_line() {
+        let (events, stats) = decode(&[b"t12", b"\x07t1230\r"]);
+        assert!(matches!(
+            &events[..],
+            [SlcanEvent::Nack, SlcanEvent::Frame(_)]
+        ));
+        assert_eq!(stats.nacks, 1);
+        assert_eq!(stats.malformed_lines, 1);
+        assert_eq!(stats.discarded_bytes, 3);
+    }
+
+    #[test]
+    fn counts_the_capture() {
+        let (events, stats) = decode(&[CAPTURE, b"t12\r", b"\x07", &[b'x'; 200], b"\r"]);
+        assert_eq!(
+            stats,
+            SlcanStats {
+                frames: 5,
+                malformed_lines: 1,
+                overlong_lines: 1,
+                discarded_bytes: 3 + 200,
+                nacks: 2,
+            }
+        );
+        assert!(matches!(&events[0], SlcanEvent::Line(line) if line == b"V1013"));
+        assert!(events.iter().any(|event| matches!(event, SlcanEvent::Ack)));
+    }
+
+    #[test]
+    fn crlf_lines_decode_like_cr_lines() {
+        let (events, _) = decode(&[b"t1231AA\r\nt4560\r\n"]);
+        assert_eq!(events.len(), 2, "{:?}", events);
+    }
+
+    #[test]
+    fn any_split_decodes_the_same() {
+        let (whole, whole_stats) = decode(&[CAPTURE]);
+        let whole = describe(&whole);
+        for i in 0..=CAPTURE.len() {
+            for j in i..=CAPTURE.len() {
+                let (events, stats) = decode(&[&CAPTURE[..i], &CAPTURE[i..j], &CAPTURE[j..]]);
+                assert_eq!(describe(&events), whole, "split at {} and {}", i, j);
+                assert_eq!(stats, whole_stats, "split at {} and {}", i, j);
+            }
+        }
+
+        let bytewise: Vec<&[u8]> = CAPTURE.chunks(1).collect();
+        assert_eq!(describe(&decode(&bytewise).0), whole);
+    }
+}
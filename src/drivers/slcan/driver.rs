@@ -0,0 +1,373 @@
+/// Provides the SlcanDriver that exposes a serial port as a CAN interface.
+use async_trait::async_trait;
+use crosscan::can::CanFrame;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, split};
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tokio_serial::{FlowControl, SerialPort, SerialStream};
+
+use super::codec::{SlcanDecoder, SlcanEvent, SlcanStats, data_bitrate_command, encode_frame};
+use crate::drivers::bitrate_scan::STANDARD_BITRATES;
+use crate::drivers::{CanDriver, RECEIVE_WAIT};
+
+/// Baud rates tried, in order, by [`SlcanDriver::open_auto_baud`].
+pub const SLCAN_PROBE_BAUD_RATES: [u32; 6] =
+    [2_500_000, 1_000_000, 921_600, 460_800, 230_400, 115_200];
+
+/// Serial port settings used when opening an SLCAN adapter.
+///
+/// USB-CDC adapters such as the CANable ignore the baud rate, but UART based
+/// adapters (USBtin, Lawicel CANUSB, CH340 boards) need it to match their firmware.
+#[derive(Debug, Clone)]
+pub struct SlcanSerialOptions {
+    pub baud_rate: u32,
+    pub flow_control: FlowControl,
+    /// Level to drive DTR to after opening, or `None` to leave it untouched.
+    pub dtr: Option<bool>,
+    /// Level to drive RTS to after opening, or `None` to leave it untouched.
+    /// Ignored when hardware flow control owns the RTS line.
+    pub rts: Option<bool>,
+    /// Size of the buffer used for each serial read.
+    pub read_buffer_size: usize,
+}
+
+impl Default for SlcanSerialOptions {
+    fn default() -> Self {
+        Self {
+            baud_rate: 2_500_000,
+            flow_control: FlowControl::None,
+            dtr: None,
+            rts: None,
+            read_buffer_size: 4096,
+        }
+    }
+}
+
+/// A CAN interface backed by an SLCAN adapter on a serial port.
+///
+/// CAN FD is limited by `CanFrame`, which carries no FD or bitrate switch
+/// flag: frames with up to 8 data bytes are always sent as classic `t`/`T`
+/// frames, and longer ones use `b`/`B` when a data bitrate is configured and
+/// `d`/`D` otherwise. Received `d`/`D`/`b`/`B` frames lose the FD and BRS
+/// distinction, so a short FD frame reads back as a classic one.
+pub struct SlcanDriver {
+    reader: Mutex<tokio::io::ReadHalf<SerialStream>>,
+    writer: Mutex<tokio::io::WriteHalf<SerialStream>>,
+    decoder: SlcanDecoder, // Holds partial incoming lines between reads
+    read_buffer_size: usize,
+    configured_bitrate: Option<u32>,
+    configured_data_bitrate: Option<u32>,
+}
+
+impl SlcanDriver {
+    /// Open serial port and initialize driver, optionally enabling SLCAN timestamp
+    pub async fn open(port_name: &str) -> std::io::Result<Self> {
+        Self::open_with_options(port_name, &SlcanSerialOptions::default()).await
+    }
+
+    /// Open serial port with explicit baud rate, flow control and line settings.
+    pub async fn open_with_options(
+        port_name: &str,
+        options: &SlcanSerialOptions,
+    ) -> std::io::Result<Self> {
+        let builder =
+            tokio_serial::new(port_name, options.baud_rate).flow_control(options.flow_control);
+        let mut port = SerialStream::open(&builder)?;
+
+        if let Some(dtr) = options.dtr {
+            port.write_data_terminal_ready(dtr)?;
+        }
+        if let Some(rts) = options.rts
+            && options.flow_control != FlowControl::Hardware
+        {
+            port.write_request_to_send(rts)?;
+        }
+
+        let (reader, writer) = split(port);
+        let read_buffer_size = options.read_buffer_size.max(64);
+
+        Ok(SlcanDriver {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+            decoder: SlcanDecoder::new(),
+            read_buffer_size,
+            configured_bitrate: None,
+            configured_data_bitrate: None,
+        })
+    }
+
+    /// Open serial port by probing each of `baud_rates` until the adapter answers
+    /// the `V` (version) command.
+    ///
+    /// Returns the opened driver together with the baud rate that worked.
+    pub async fn open_auto_baud(
+        port_name: &str,
+        options: &SlcanSerialOptions,
+        baud_rates: &[u32],
+    ) -> std::io::Result<(Self, u32)> {
+        for &baud_rate in baud_rates {
+            let probe_options = SlcanSerialOptions {
+                baud_rate,
+                ..options.clone()
+            };
+
+            let mut driver = match Self::open_with_options(port_name, &probe_options).await {
+                Ok(d) => d,
+                Err(e) => {
+                    log::debug!(
+                        "slcan: {} failed to open at {} baud: {}",
+                        port_name,
+                        baud_rate,
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            // Flush any half-received command left over from a mismatched rate
+            // so the version request is parsed cleanly.
+            {
+                let mut writer = driver.writer.lock().await;
+                writer.write_all(b"\r\r\r").await?;
+                writer.flush().await?;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+
+            // The BELs acknowledging the empty commands are skipped by the decoder.
+            match driver.get_version().await {
+                Ok(version) if !version.is_empty() => {
+                    log::info!("slcan: {} answered at {} baud", port_name, baud_rate);
+                    return Ok((driver, baud_rate));
+                }
+                _ => {}
+            }
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "No SLCAN response from {} at any probed baud rate",
+                port_name
+            ),
+        ))
+    }
+
+    /// Counters for frames decoded and malformed input discarded so far.
+    pub fn stream_stats(&self) -> SlcanStats {
+        self.decoder.stats()
+    }
+
+    /// Configure the CAN FD data phase bitrate using the `Y` command.
+    ///
+    /// Once a data bitrate is set, FD frames are transmitted with bitrate switching.
+    pub async fn set_data_bitrate(&mut self, bitrate: u32) -> std::io::Result<()> {
+        let cmd = data_bitrate_command(bitrate).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unsupported CAN FD data bitrate: {}", bitrate),
+            )
+        })?;
+
+        self.configured_data_bitrate = Some(bitrate);
+        let mut writer = self.writer.lock().await;
+        writer.write_all(cmd).await
+    }
+
+    pub async fn get_measured_bitrate(&mut self) -> std::io::Result<u32> {
+        self.decoder.reset();
+        // Request bitrate
+        {
+            let mut writer = self.writer.lock().await;
+            writer.write_all(b"B\r").await?;
+            writer.flush().await?;
+        }
+
+        let mut buf = [0u8; 4];
+        let mut received = 0;
+        let start = tokio::time::Instant::now();
+        let timeout = Duration::from_millis(500);
+
+        while received < 4 {
+            if start.elapsed() > timeout {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Timed out waiting for bitrate bytes",
+                ));
+            }
+
+            // Await instead of busy-looping
+            let num_bytes = {
+                let mut reader = self.reader.lock().await;
+                reader.read(&mut buf[received..]).await?
+            };
+
+            if num_bytes > 0 {
+                received += num_bytes;
+            } else {
+                // yield briefly
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        let actual = u32::from_le_bytes(buf);
+        if actual < 5000 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "Error detecting bitrate. Has the device been modified correctly?",
+            ));
+        }
+
+        // Find closest supported bitrate
+        let closest = *STANDARD_BITRATES
+            .iter()
+            .min_by_key(|&&rate| (rate as i64 - actual as i64).abs())
+            .unwrap();
+
+        Ok(closest)
+    }
+
+    /// Ask the adapter for its version. The reply is read through the
+    /// decoder, so frames, BEL and partial lines around it are not taken for
+    /// it, and bytes following it stay in the decoder for the next read.
+    pub async fn get_version(&mut self) -> std::io::Result<String> {
+        self.decoder.reset();
+
+        {
+            let mut writer = self.writer.lock().await;
+            writer.write_all(b"V\r").await?;
+            writer.flush().await?;
+        }
+
+        let mut buf = vec![0u8; self.read_buffer_size];
+        let mut events = Vec::new();
+
+        // Wait for response with a timeout
+        let res = timeout(Duration::from_millis(20), async {
+            loop {
+                let n = {
+                    let mut reader = self.reader.lock().await;
+                    reader.read(&mut buf).await?
+                };
+
+                if n == 0 {
+                    // EOF
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "Device closed connection",
+                    ));
+                }
+
+                events.clear();
+                self.decoder.push(&buf[..n], &mut events);
+                // Frames received meanwhile are dropped, the channel is not
+                // being read yet.
+                for event in events.drain(..) {
+                    if let SlcanEvent::Line(line) = event {
+                        return Ok(String::from_utf8_lossy(&line).trim().to_string());
+                    }
+                }
+            }
+        })
+        .await;
+
+        match res {
+            Ok(inner) => inner,
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Timeout waiting for SLCAN version response",
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl CanDriver for SlcanDriver {
+    /// Enable timestamp support on the SLCAN device
+    async fn enable_timestamp(&mut self) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer.write_all(b"Z1\r").await?;
+        Ok(())
+    }
+
+    async fn set_bitrate(&mut self, bitrate: u32) -> std::io::Result<()> {
+        let cmd = match bitrate {
+            10_000 => b"S0\r",
+            20_000 => b"S1\r",
+            50_000 => b"S2\r",
+            100_000 => b"S3\r",
+            125_000 => b"S4\r",
+            250_000 => b"S5\r",
+            500_000 => b"S6\r",
+            800_000 => b"S7\r",
+            1_000_000 => b"S8\r",
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Unsupported CAN bitrate: {}", bitrate),
+                ));
+            }
+        };
+
+        self.configured_bitrate = Some(bitrate);
+        let mut writer = self.writer.lock().await;
+        writer.write_all(cmd).await
+    }
+
+    async fn open_channel(&mut self) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer.write_all(b"O\r").await // Open CAN channel
+    }
+
+    async fn open_listen_only(&mut self) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer.write_all(b"L\r").await // Open CAN channel in listen-only mode
+    }
+
+    async fn send_frame(&mut self, frame: &CanFrame) -> std::io::Result<()> {
+        let cmd = encode_frame(frame, self.configured_data_bitrate.is_some())?;
+        let mut writer = self.writer.lock().await;
+        writer.write_all(cmd.as_bytes()).await
+    }
+
+    async fn read_frames(&mut self) -> std::io::Result<Vec<CanFrame>> {
+        let mut buf = vec![0u8; self.read_buffer_size];
+        let mut frames = Vec::new();
+
+        // Bounded, so a caller holding the driver lock between reads (the
+        // BCM cyclic sender, for one) gets to transmit on an idle bus.
+        let num_bytes = {
+            let mut reader = self.reader.lock().await;
+            match timeout(RECEIVE_WAIT, reader.read(&mut buf)).await {
+                Ok(read) => read?,
+                Err(_) => return Ok(frames),
+            }
+        };
+
+        let mut events = Vec::new();
+        self.decoder.push(&buf[..num_bytes], &mut events);
+        for event in events {
+            if let SlcanEvent::Frame(frame) = event {
+                frames.push(frame);
+            }
+        }
+
+        Ok(frames)
+    }
+
+    /// Close the CAN channel cleanly
+    async fn close_channel(&mut self) -> std::io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        writer.write_all(b"C\r").await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    async fn get_bitrate(&self) -> Option<u32> {
+        self.configured_bitrate
+    }
+
+    async fn get_data_bitrate(&self) -> Option<u32> {
+        self.configured_data_bitrate
+    }
+}
@@ -0,0 +1,6 @@
+/// Driver implementation for serial-line (SLCAN / LAWICEL) CAN adapters.
+pub mod codec;
+mod driver;
+
+pub use codec::{SlcanDecoder, SlcanEvent, SlcanStats};
+pub use driver::{SLCAN_PROBE_BAUD_RATES, SlcanDriver, SlcanSerialOptions};
@@ -0,0 +1,676 @@
+/// Linux SocketCAN driver using raw `AF_CAN` sockets.
+use async_trait::async_trait;
+use crosscan::can::CanFrame;
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+use tokio::io::unix::AsyncFd;
+
+use crate::drivers::can_id::{CAN_EFF_FLAG, CAN_ERR_FLAG, CAN_ERR_MASK, frame_from_raw, raw_id};
+use crate::drivers::{CanDriver, RECEIVE_WAIT};
+use crate::filter::{Filter, FilterSet};
+
+// <linux/can.h> and <linux/can/raw.h>; defined here rather than taken from
+// libc so the layout does not depend on the libc release.
+const CAN_RAW: libc::c_int = 1;
+const SOL_CAN_RAW: libc::c_int = 100 + CAN_RAW;
+const CAN_RAW_FILTER: libc::c_int = 1;
+const CAN_RAW_ERR_FILTER: libc::c_int = 2;
+const CAN_RAW_FD_FRAMES: libc::c_int = 5;
+const CAN_INV_FILTER: u32 = 0x2000_0000;
+
+const CAN_MTU: usize = 16;
+const CANFD_MTU: usize = 72;
+const CANFD_BRS: u8 = 0x01;
+
+/// `struct can_frame` / `struct canfd_frame`; a classic frame is the first
+/// [`CAN_MTU`] bytes with `len` holding the DLC.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawFrame {
+    can_id: u32,
+    len: u8,
+    flags: u8,
+    res0: u8,
+    res1: u8,
+    data: [u8; 64],
+}
+
+/// `struct sockaddr_can`; the protocol specific address union is unused for raw sockets.
+#[repr(C)]
+struct SockAddrCan {
+    can_family: libc::sa_family_t,
+    can_ifindex: libc::c_int,
+    can_addr: [u8; 16],
+}
+
+/// Kernel receive filter: a frame passes when `received_id & mask == id & mask`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct CanFilter {
+    /// `can_id` including the EFF/RTR flags to match.
+    pub id: u32,
+    pub mask: u32,
+}
+
+pub struct SocketCanDriver {
+    interface: String,
+    socket: Option<AsyncFd<OwnedFd>>,
+    timestamps: bool,
+    /// Error classes delivered as error frames (`CAN_RAW_ERR_FILTER`).
+    error_mask: u32,
+    filters: Vec<CanFilter>,
+    configured_bitrate: Option<u32>,
+    configured_data_bitrate: Option<u32>,
+}
+
+impl SocketCanDriver {
+    /// Prepare a driver for the network interface `interface` (e.g. "can0" or "vcan0").
+    ///
+    /// The bitrate of real interfaces is configured by the system, e.g.
+    /// `ip link set can0 type can bitrate 500000 dbitrate 2000000 fd on`.
+    pub async fn open(interface: &str) -> std::io::Result<Self> {
+        interface_index(interface)?;
+        Ok(Self {
+            interface: interface.to_string(),
+            socket: None,
+            timestamps: false,
+            error_mask: CAN_ERR_MASK,
+            filters: Vec::new(),
+            configured_bitrate: None,
+            configured_data_bitrate: None,
+        })
+    }
+
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    /// Only receive frames matching one of `filters`; an empty list receives everything.
+    /// Applied immediately when the channel is open, otherwise on the next open.
+    pub fn set_filters(&mut self, filters: &[CanFilter]) -> std::io::Result<()> {
+        self.filters = filters.to_vec();
+        if let Some(socket) = &self.socket {
+            apply_filters(socket.get_ref().as_raw_fd(), &self.filters)?;
+        }
+        Ok(())
+    }
+
+    /// Install candump style filters in the kernel: `id:mask` and `id~mask`
+    /// become receive filters and `#mask` selects the error classes reported.
+    /// The kernel ORs its filters, so a join (`j`) is rejected.
+    pub fn set_filter_set(&mut self, set: &FilterSet) -> std::io::Result<()> {
+        let (filters, error_mask) = kernel_filters(set)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        self.set_filters(&filters)?;
+        if let Some(mask) = error_mask {
+            self.set_error_mask(mask)?;
+        }
+        Ok(())
+    }
+
+    /// Select which error classes are reported as error frames (0 disables them).
+    pub fn set_error_mask(&mut self, mask: u32) -> std::io::Result<()> {
+        self.error_mask = mask & CAN_ERR_MASK;
+        if let Some(socket) = &self.socket {
+            set_option(
+                socket.get_ref().as_raw_fd(),
+                SOL_CAN_RAW,
+                CAN_RAW_ERR_FILTER,
+                &self.error_mask,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Record the data bitrate in use; CAN FD frames are then sent with bitrate switching.
+    pub fn set_data_bitrate(&mut self, data_bitrate: u32) -> std::io::Result<()> {
+        self.configured_data_bitrate = Some(data_bitrate);
+        Ok(())
+    }
+
+    fn bind(&self) -> std::io::Result<OwnedFd> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_CAN,
+                libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                CAN_RAW,
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let raw = fd.as_raw_fd();
+
+        // Enabling FD frames fails on kernels without CAN FD; classic frames still work.
+        let enable: libc::c_int = 1;
+        if let Err(e) = set_option(raw, SOL_CAN_RAW, CAN_RAW_FD_FRAMES, &enable) {
+            log::debug!("socketcan: CAN FD frames unavailable: {}", e);
+        }
+        set_option(raw, SOL_CAN_RAW, CAN_RAW_ERR_FILTER, &self.error_mask)?;
+        apply_filters(raw, &self.filters)?;
+
+        if self.timestamps {
+            let flags: libc::c_uint = libc::SOF_TIMESTAMPING_RX_HARDWARE
+                | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+                | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+                | libc::SOF_TIMESTAMPING_SOFTWARE;
+            set_option(raw, libc::SOL_SOCKET, libc::SO_TIMESTAMPING, &flags)?;
+        }
+
+        let addr = SockAddrCan {
+            can_family: libc::AF_CAN as libc::sa_family_t,
+            can_ifindex: interface_index(&self.interface)?,
+            can_addr: [0; 16],
+        };
+        let rc = unsafe {
+            libc::bind(
+                raw,
+                &addr as *const SockAddrCan as *const libc::sockaddr,
+                std::mem::size_of::<SockAddrCan>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(fd)
+    }
+
+    fn socket(&self) -> std::io::Result<&AsyncFd<OwnedFd>> {
+        self.socket.as_ref().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                format!("SocketCAN channel {} is not open", self.interface),
+            )
+        })
+    }
+
+    /// Read one frame without blocking; `Ok(None)` when the queue is empty.
+    fn receive(&self, fd: RawFd) -> std::io::Result<Option<CanFrame>> {
+        let mut raw = RawFrame {
+            can_id: 0,
+            len: 0,
+            flags: 0,
+            res0: 0,
+            res1: 0,
+            data: [0; 64],
+        };
+        // Room for one SCM_TIMESTAMPING message (three timespecs).
+        let mut control = [0u64; 16];
+        let mut iov = libc::iovec {
+            iov_base: &mut raw as *mut RawFrame as *mut libc::c_void,
+            iov_len: CANFD_MTU,
+        };
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        let n = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_DONTWAIT) };
+        if n < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            return Err(err);
+        }
+
+        let len = match n as usize {
+            CAN_MTU => (raw.len as usize).min(8),
+            CANFD_MTU => (raw.len as usize).min(64),
+            other => {
+                log::debug!("socketcan: ignoring {} byte message", other);
+                return self.receive(fd);
+            }
+        };
+        let mut frame = frame_from_raw(raw.can_id, &raw.data[..len], len)?;
+        if self.timestamps {
+            frame.set_timestamp(unsafe { timestamp_from_control(&msg) });
+        }
+        Ok(Some(frame))
+    }
+
+    fn drain(&self, socket: &AsyncFd<OwnedFd>) -> std::io::Result<Vec<CanFrame>> {
+        let fd = socket.get_ref().as_raw_fd();
+        let mut frames = Vec::new();
+        while let Some(frame) = self.receive(fd)? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+}
+
+fn interface_index(interface: &str) -> std::io::Result<libc::c_int> {
+    let name = CString::new(interface).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid interface name")
+    })?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No such CAN interface: {}", interface),
+        ));
+    }
+    Ok(index as libc::c_int)
+}
+
+fn set_option<T>(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: &T,
+) -> std::io::Result<()> {
+    set_option_raw(
+        fd,
+        level,
+        name,
+        value as *const T as *const libc::c_void,
+        std::mem::size_of::<T>(),
+    )
+}
+
+fn set_option_raw(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: *const libc::c_void,
+    len: usize,
+) -> std::io::Result<()> {
+    let rc = unsafe { libc::setsockopt(fd, level, name, value, len as libc::socklen_t) };
+    if rc < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn apply_filters(fd: RawFd, filters: &[CanFilter]) -> std::io::Result<()> {
+    if filters.is_empty() {
+        // The default filter passes every frame.
+        let all = CanFilter { id: 0, mask: 0 };
+        return set_option(fd, SOL_CAN_RAW, CAN_RAW_FILTER, &all);
+    }
+    set_option_raw(
+        fd,
+        SOL_CAN_RAW,
+        CAN_RAW_FILTER,
+        filters.as_ptr() as *const libc::c_void,
+        std::mem::size_of_val(filters),
+    )
+}
+
+/// Translate a candump filter set into kernel receive filters and an error mask.
+/// As in candump, extended ids carry `CAN_EFF_FLAG` and masks never select
+/// `CAN_ERR_FLAG`.
+fn kernel_filters(set: &FilterSet) -> Result<(Vec<CanFilter>, Option<u32>), String> {
+    let kernel_filter = |id: u32, mask: u32, extended: bool| CanFilter {
+        id: if extended { id | CAN_EFF_FLAG } else { id },
+        mask: mask & !CAN_ERR_FLAG,
+    };
+    let mut filters = Vec::new();
+    let mut error_mask = None;
+    for filter in &set.filters {
+        match *filter {
+            Filter::Match { id, mask, extended } => filters.push(kernel_filter(id, mask, extended)),
+            Filter::NotMatch { id, mask, extended } => {
+                let filter = kernel_filter(id, mask, extended);
+                filters.push(CanFilter {
+                    id: filter.id | CAN_INV_FILTER,
+                    ..filter
+                });
+            }
+            Filter::ErrorMask(mask) => error_mask = Some(error_mask.unwrap_or(0) | mask),
+            Filter::Join => return Err("SocketCAN filters cannot be joined".to_string()),
+        }
+    }
+    Ok((filters, error_mask))
+}
+
+/// Extract the receive time in microseconds from `SCM_TIMESTAMPING`, preferring
+/// the raw hardware stamp over the software one.
+unsafe fn timestamp_from_control(msg: &libc::msghdr) -> Option<u64> {
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SO_TIMESTAMPING {
+            let stamps = unsafe {
+                std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const [libc::timespec; 3])
+            };
+            // [0] software, [1] deprecated, [2] raw hardware.
+            let ts = if stamps[2].tv_sec != 0 || stamps[2].tv_nsec != 0 {
+                stamps[2]
+            } else {
+                stamps[0]
+            };
+            return Some(ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1_000);
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(msg, cmsg) };
+    }
+    None
+}
+
+#[async_trait]
+impl CanDriver for SocketCanDriver {
+    async fn enable_timestamp(&mut self) -> std::io::Result<()> {
+        self.timestamps = true;
+        Ok(())
+    }
+
+    async fn set_bitrate(&mut self, bitrate: u32) -> std::io::Result<()> {
+        // Configuring the interface needs netlink and CAP_NET_ADMIN; leave it to `ip link`.
+        self.configured_bitrate = Some(bitrate);
+        Ok(())
+    }
+
+    async fn get_bitrate(&self) -> Option<u32> {
+        self.configured_bitrate
+    }
+
+    async fn get_data_bitrate(&self) -> Option<u32> {
+        self.configured_data_bitrate
+    }
+
+    async fn open_channel(&mut self) -> std::io::Result<()> {
+        let fd = self.bind()?;
+        self.socket = Some(AsyncFd::new(fd)?);
+        Ok(())
+    }
+
+    async fn send_frame(&mut self, frame: &CanFrame) -> std::io::Result<()> {
+        let data = frame.data();
+        let fd_frame = data.len() > 8;
+        let mut raw = RawFrame {
+            can_id: raw_id(frame),
+            len: if frame.is_rtr() {
+                frame.dlc().min(8) as u8
+            } else {
+                data.len() as u8
+            },
+            flags: 0,
+            res0: 0,
+            res1: 0,
+            data: [0; 64],
+        };
+        if data.len() > 64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("CAN FD payload too long: {} bytes", data.len()),
+            ));
+        }
+        if !frame.is_rtr() {
+            raw.data[..data.len()].copy_from_slice(data);
+        }
+        if fd_frame && self.configured_data_bitrate.is_some() {
+            raw.flags |= CANFD_BRS;
+        }
+        let size = if fd_frame { CANFD_MTU } else { CAN_MTU };
+
+        let socket = self.socket()?;
+        loop {
+            let mut guard = socket.writable().await?;
+            let result = guard.try_io(|inner| {
+                let n = unsafe {
+                    libc::write(
+                        inner.get_ref().as_raw_fd(),
+                        &raw as *const RawFrame as *const libc::c_void,
+                        size,
+                    )
+                };
+                if n < 0 {
+                    Err(std::io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            });
+            match result {
+                Ok(Ok(n)) if n == size => return Ok(()),
+                Ok(Ok(n)) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::WriteZero,
+                        format!("Short write to {}: {} of {} bytes", self.interface, n, size),
+                    ));
+                }
+                // The interface queue is full; wait for it to drain.
+                Ok(Err(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    async fn read_frames(&mut self) -> std::io::Result<Vec<CanFrame>> {
+        let socket = self.socket()?;
+        let Ok(guard) = tokio::time::timeout(RECEIVE_WAIT, socket.readable()).await else {
+            return Ok(Vec::new());
+        };
+        let mut guard = guard?;
+        let frames = self.drain(socket)?;
+        // drain() reads until EAGAIN, so the socket is no longer readable.
+        guard.clear_ready();
+        Ok(frames)
+    }
+
+    async fn close_channel(&mut self) -> std::io::Result<()> {
+        self.socket = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests on a live interface share it, so they take turns.
+    static BUS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    /// The interface for the live tests: `$SOCKETCAN_TEST_INTERFACE` or `vcan0`,
+    /// created with `ip link add dev vcan0 type vcan && ip link set vcan0 mtu 72 up`.
+    /// The tests pass without running when it does not exist.
+    fn test_interface() -> Option<String> {
+        let interface =
+            std::env::var("SOCKETCAN_TEST_INTERFACE").unwrap_or_else(|_| "vcan0".to_string());
+        if interface_index(&interface).is_err() {
+            eprintln!("{} not found, skipping", interface);
+            return None;
+        }
+        Some(interface)
+    }
+
+    async fn open(interface: &str, data_bitrate: Option<u32>) -> SocketCanDriver {
+        let mut driver = SocketCanDriver::open(interface).await.unwrap();
+        if let Some(data_bitrate) = data_bitrate {
+            driver.set_data_bitrate(data_bitrate).unwrap();
+        }
+        driver.enable_timestamp().await.unwrap();
+        driver.open_channel().await.unwrap();
+        driver
+    }
+
+    async fn receive(driver: &mut SocketCanDriver, count: usize) -> Vec<CanFrame> {
+        let mut frames = Vec::new();
+        for _ in 0..100 {
+            frames.extend(driver.read_frames().await.unwrap());
+            if frames.len() >= count {
+                break;
+            }
+        }
+        frames
+    }
+
+    fn parse(spec: &str) -> Result<(Vec<CanFilter>, Option<u32>), String> {
+        kernel_filters(&FilterSet::parse(spec.split(','))?)
+    }
+
+    #[test]
+    fn translates_candump_filters() {
+        let (filters, error_mask) =
+            parse("123:7FF,200~700,12345678,00000123:1FFFFFFF,800~FFFFFFFF").unwrap();
+        assert_eq!(
+            filters,
+            [
+                CanFilter {
+                    id: 0x123,
+                    mask: 0x7FF
+                },
+                CanFilter {
+                    id: 0x200 | CAN_INV_FILTER,
+                    mask: 0x700
+                },
+                CanFilter {
+                    id: 0x9234_5678,
+                    mask: 0xDFFF_FFFF
+                },
+                CanFilter {
+                    id: 0x8000_0123,
+                    mask: 0x1FFF_FFFF
+                },
+                CanFilter {
+                    id: 0x8000_0800 | CAN_INV_FILTER,
+                    mask: 0xDFFF_FFFF
+                },
+            ]
+        );
+        assert_eq!(error_mask, None);
+    }
+
+    #[test]
+    fn error_masks_are_combined() {
+        let (filters, error_mask) = parse("#4,#10").unwrap();
+        assert!(filters.is_empty());
+        assert_eq!(error_mask, Some(0x14));
+        assert_eq!(parse("#0").unwrap().1, Some(0));
+    }
+
+    #[test]
+    fn join_is_rejected() {
+        assert!(parse("123:7FF,j,200:7FF").is_err());
+    }
+
+    #[tokio::test]
+    async fn classic_frames_cross_the_interface() {
+        let Some(interface) = test_interface() else {
+            return;
+        };
+        let _bus = BUS.lock().await;
+        let mut tx = open(&interface, None).await;
+        let mut rx = open(&interface, None).await;
+
+        tx.send_frame(&CanFrame::new(0x123, &[1, 2, 3]).unwrap())
+            .await
+            .unwrap();
+        tx.send_frame(&CanFrame::new_eff(0x1ABCDEF, &[]).unwrap())
+            .await
+            .unwrap();
+        tx.send_frame(&CanFrame::new_remote(0x7FF, 4, false).unwrap())
+            .await
+            .unwrap();
+
+        let frames = receive(&mut rx, 3).await;
+        assert_eq!(frames.len(), 3);
+        assert_eq!((frames[0].id(), frames[0].data()), (0x123, &[1, 2, 3][..]));
+        assert!(frames[0].timestamp().is_some());
+        assert!(frames[1].is_extended() && frames[1].id() == 0x1ABCDEF);
+        assert!(frames[2].is_rtr() && frames[2].dlc() == 4);
+        // Raw sockets do not receive their own frames.
+        assert!(tx.read_frames().await.unwrap().is_empty());
+
+        tx.close_channel().await.unwrap();
+        rx.close_channel().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fd_frames_cross_the_interface() {
+        let Some(interface) = test_interface() else {
+            return;
+        };
+        let mtu = std::fs::read_to_string(format!("/sys/class/net/{}/mtu", interface))
+            .ok()
+            .and_then(|mtu| mtu.trim().parse::<usize>().ok());
+        if mtu != Some(CANFD_MTU) {
+            eprintln!("{} does not carry CAN FD frames, skipping", interface);
+            return;
+        }
+        let _bus = BUS.lock().await;
+        let mut tx = open(&interface, Some(2_000_000)).await;
+        let mut rx = open(&interface, None).await;
+
+        let payload: Vec<u8> = (0..64).collect();
+        tx.send_frame(&CanFrame::new(0x456, &payload).unwrap())
+            .await
+            .unwrap();
+
+        let frames = receive(&mut rx, 1).await;
+        assert_eq!(frames.len(), 1);
+        assert_eq!((frames[0].id(), frames[0].data()), (0x456, &payload[..]));
+
+        tx.close_channel().await.unwrap();
+        rx.close_channel().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn kernel_filters_drop_other_ids() {
+        let Some(interface) = test_interface() else {
+            return;
+        };
+        let _bus = BUS.lock().await;
+        let mut tx = open(&interface, None).await;
+        let mut rx = SocketCanDriver::open(&interface).await.unwrap();
+        // Installed before the open, then replaced on the open socket.
+        rx.set_filter_set(&FilterSet::parse(["200:7FF"]).unwrap())
+            .unwrap();
+        rx.open_channel().await.unwrap();
+        rx.set_filter_set(&FilterSet::parse(["100:7FF", "300~7FF"]).unwrap())
+            .unwrap();
+
+        for id in [0x100, 0x200, 0x300] {
+            tx.send_frame(&CanFrame::new(id, &[0]).unwrap())
+                .await
+                .unwrap();
+        }
+
+        let frames = receive(&mut rx, 3).await;
+        let ids: Vec<u32> = frames.iter().map(|frame| frame.id()).collect();
+        // 0x100 passes both filters but is delivered once; 0x300 passes neither.
+        assert_eq!(ids, [0x100, 0x200]);
+
+        tx.close_channel().await.unwrap();
+        rx.close_channel().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn extended_filters_match_extended_frames() {
+        let Some(interface) = test_interface() else {
+            return;
+        };
+        let _bus = BUS.lock().await;
+        let mut tx = open(&interface, None).await;
+        let mut rx = SocketCanDriver::open(&interface).await.unwrap();
+        rx.set_filter_set(&FilterSet::parse(["12345678", "00000123:1FFFFFFF"]).unwrap())
+            .unwrap();
+        rx.open_channel().await.unwrap();
+
+        tx.send_frame(&CanFrame::new_eff(0x1234_5678, &[1]).unwrap())
+            .await
+            .unwrap();
+        tx.send_frame(&CanFrame::new_eff(0x1234_5679, &[2]).unwrap())
+            .await
+            .unwrap();
+        tx.send_frame(&CanFrame::new_eff(0x123, &[3]).unwrap())
+            .await
+            .unwrap();
+        // The same id as a standard frame lacks CAN_EFF_FLAG.
+        tx.send_frame(&CanFrame::new(0x123, &[4]).unwrap())
+            .await
+            .unwrap();
+
+        let frames = receive(&mut rx, 3).await;
+        let data: Vec<&[u8]> = frames.iter().map(|frame| frame.data()).collect();
+        assert_eq!(data, [&[1][..], &[3][..]]);
+
+        tx.close_channel().await.unwrap();
+        rx.close_channel().await.unwrap();
+    }
+}
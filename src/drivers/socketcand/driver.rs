@@ -0,0 +1,271 @@
+use async_trait::async_trait;
+use crosscan::can::CanFrame;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::protocol::{element, format_send, next_element, parse_frame};
+use crate::drivers::{CanDriver, RECEIVE_WAIT, timestamp_us};
+
+/// Time allowed for each step of the connection handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Client for a remote bus served by socketcand, used in raw mode.
+pub struct SocketcandDriver {
+    address: String,
+    bus: String,
+    stream: Option<TcpStream>,
+    buf: Vec<u8>,
+    timestamps: bool,
+    configured_bitrate: Option<u32>,
+}
+
+impl SocketcandDriver {
+    /// `address` is the socketcand `host:port`, `bus` the interface on that
+    /// host, e.g. "can0". Nothing is sent until the channel is opened.
+    pub fn new(address: &str, bus: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            bus: bus.to_string(),
+            stream: None,
+            buf: Vec::new(),
+            timestamps: false,
+            configured_bitrate: None,
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn bus(&self) -> &str {
+        &self.bus
+    }
+
+    fn stream(&mut self) -> std::io::Result<&mut TcpStream> {
+        self.stream.as_mut().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "socketcand channel is not open",
+            )
+        })
+    }
+
+    /// Read more bytes into the buffer, waiting at most `wait`.
+    /// Returns false if nothing arrived in time.
+    async fn fill(&mut self, wait: Duration) -> std::io::Result<bool> {
+        let mut chunk = [0u8; 4096];
+        let stream = self.stream()?;
+        let n = match tokio::time::timeout(wait, stream.read(&mut chunk)).await {
+            Ok(result) => result?,
+            Err(_) => return Ok(false),
+        };
+        if n == 0 {
+            self.stream = None;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "socketcand closed the connection",
+            ));
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(true)
+    }
+
+    /// Wait for the element `expected` during the handshake.
+    async fn expect(&mut self, expected: &str) -> std::io::Result<()> {
+        let deadline = tokio::time::Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            while let Some(words) = next_element(&mut self.buf) {
+                match words[0].as_str() {
+                    word if word == expected => return Ok(()),
+                    "error" => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            format!("socketcand: {}", words[1..].join(" ")),
+                        ));
+                    }
+                    _ => log::debug!("socketcand: ignoring {:?}", words),
+                }
+            }
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if remaining.is_zero() || !self.fill(remaining).await? {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("socketcand did not answer with < {} >", expected),
+                ));
+            }
+        }
+    }
+
+    async fn write(&mut self, message: &str) -> std::io::Result<()> {
+        self.stream()?.write_all(message.as_bytes()).await
+    }
+}
+
+#[async_trait]
+impl CanDriver for SocketcandDriver {
+    async fn enable_timestamp(&mut self) -> std::io::Result<()> {
+        self.timestamps = true;
+        Ok(())
+    }
+
+    async fn set_bitrate(&mut self, bitrate: u32) -> std::io::Result<()> {
+        // The interface is configured on the socketcand host; informational only.
+        self.configured_bitrate = Some(bitrate);
+        Ok(())
+    }
+
+    async fn get_bitrate(&self) -> Option<u32> {
+        self.configured_bitrate
+    }
+
+    async fn open_channel(&mut self) -> std::io::Result<()> {
+        self.stream = None;
+        self.buf.clear();
+
+        let stream = TcpStream::connect(&self.address).await?;
+        stream.set_nodelay(true)?;
+        self.stream = Some(stream);
+
+        self.expect("hi").await?;
+        self.write(&element(&format!("open {}", self.bus))).await?;
+        self.expect("ok").await?;
+        self.write(&element("rawmode")).await?;
+        self.expect("ok").await?;
+        Ok(())
+    }
+
+    async fn send_frame(&mut self, frame: &CanFrame) -> std::io::Result<()> {
+        let message = format_send(frame)?;
+        self.write(&message).await
+    }
+
+    async fn read_frames(&mut self) -> std::io::Result<Vec<CanFrame>> {
+        // Only wait when no complete element is buffered yet.
+        if !self.buf.contains(&b'>') {
+            self.fill(RECEIVE_WAIT).await?;
+        }
+
+        let mut frames = Vec::new();
+        while let Some(words) = next_element(&mut self.buf) {
+            match words[0].as_str() {
+                "frame" => match parse_frame(&words) {
+                    Ok((mut frame, timestamp)) => {
+                        if self.timestamps {
+                            frame.set_timestamp(Some(timestamp.unwrap_or_else(timestamp_us)));
+                        }
+                        frames.push(frame);
+                    }
+                    Err(e) => log::warn!("socketcand: {}", e),
+                },
+                "error" => log::warn!("socketcand: {}", words[1..].join(" ")),
+                "ok" => {}
+                _ => log::debug!("socketcand: ignoring {:?}", words),
+            }
+        }
+        Ok(frames)
+    }
+
+    async fn close_channel(&mut self) -> std::io::Result<()> {
+        if let Some(mut stream) = self.stream.take() {
+            let _ = stream.shutdown().await;
+        }
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Read one element from the client, e.g. `< open can0 >`.
+    async fn request(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> String {
+        let mut element = Vec::new();
+        reader.read_until(b'>', &mut element).await.unwrap();
+        String::from_utf8(element).unwrap().trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn handshakes_and_exchanges_frames_with_a_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut reader = BufReader::new(read);
+
+            write.write_all(b"< hi >").await.unwrap();
+            assert_eq!(request(&mut reader).await, "< open can1 >");
+            write.write_all(b"< ok >").await.unwrap();
+            assert_eq!(request(&mut reader).await, "< rawmode >");
+            write.write_all(b"< ok >").await.unwrap();
+
+            // Two frames split across writes, with an error report between them.
+            write
+                .write_all(b"< frame 123 1700000000.000042 1122 >< err")
+                .await
+                .unwrap();
+            write
+                .write_all(b"or bus off >< frame 1ABCDEF0 1.5 >")
+                .await
+                .unwrap();
+
+            let sent = request(&mut reader).await;
+            write.write_all(b"< ok >").await.unwrap();
+            sent
+        });
+
+        let mut driver = SocketcandDriver::new(&address, "can1");
+        driver.enable_timestamp().await.unwrap();
+        driver.open_channel().await.unwrap();
+
+        let mut frames = Vec::new();
+        for _ in 0..100 {
+            frames.extend(driver.read_frames().await.unwrap());
+            if frames.len() >= 2 {
+                break;
+            }
+        }
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            (frames[0].id(), frames[0].data()),
+            (0x123, &[0x11, 0x22][..])
+        );
+        assert_eq!(frames[0].timestamp(), Some(1_700_000_000_000_042));
+        assert!(frames[1].is_extended() && frames[1].id() == 0x1ABCDEF0);
+        assert_eq!(frames[1].timestamp(), Some(1_500_000));
+
+        driver
+            .send_frame(&CanFrame::new(0x321, &[0xAB]).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(server.await.unwrap(), "< send 321 1 AB >");
+        driver.close_channel().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_a_refused_open() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"< hi >").await.unwrap();
+            let mut buf = [0u8; 64];
+            let _ = stream.read(&mut buf).await;
+            stream
+                .write_all(b"< error could not open bus >")
+                .await
+                .unwrap();
+            let _ = stream.read(&mut buf).await;
+        });
+
+        let mut driver = SocketcandDriver::new(&address, "can9");
+        let error = driver.open_channel().await.unwrap_err();
+        assert!(error.to_string().contains("could not open bus"));
+    }
+}
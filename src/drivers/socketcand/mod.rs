@@ -0,0 +1,6 @@
+/// Client for remote buses served by socketcand over TCP.
+mod driver;
+pub mod protocol;
+
+pub use driver::SocketcandDriver;
+pub use protocol::SOCKETCAND_PORT;
@@ -0,0 +1,274 @@
+/// The socketcand TCP ASCII protocol.
+///
+/// Every message is an element enclosed in angle brackets, e.g. `< open can0 >`.
+/// In raw mode the server reports traffic as `< frame <id> <sec>.<usec> <data> >`
+/// and clients transmit with `< send <id> <dlc> <byte> ... >`; identifiers with
+/// eight hex digits are extended.
+use crosscan::can::CanFrame;
+
+/// Default TCP port of socketcand.
+pub const SOCKETCAND_PORT: u16 = 29536;
+
+/// Longest element accepted before the buffer is treated as garbage.
+const MAX_ELEMENT_LEN: usize = 4096;
+
+/// Take the next complete element out of `buf` and return its words.
+/// Bytes outside of an element are discarded.
+pub fn next_element(buf: &mut Vec<u8>) -> Option<Vec<String>> {
+    loop {
+        let Some(start) = buf.iter().position(|&b| b == b'<') else {
+            buf.clear();
+            return None;
+        };
+        buf.drain(..start);
+        let Some(end) = buf.iter().position(|&b| b == b'>') else {
+            if buf.len() > MAX_ELEMENT_LEN {
+                log::warn!("socketcand: element too long, discarding");
+                buf.clear();
+            }
+            return None;
+        };
+        let element: Vec<u8> = buf.drain(..=end).collect();
+        let text = String::from_utf8_lossy(&element[1..element.len() - 1]);
+        let words: Vec<String> = text.split_whitespace().map(str::to_owned).collect();
+        if !words.is_empty() {
+            return Some(words);
+        }
+    }
+}
+
+/// Wrap words into an element.
+pub fn element(words: &str) -> String {
+    format!("< {} >", words)
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn format_id(frame: &CanFrame) -> String {
+    if frame.is_extended() {
+        format!("{:08X}", frame.id())
+    } else {
+        format!("{:03X}", frame.id())
+    }
+}
+
+/// Parse a hex identifier; eight digits or a value above 0x7FF mean extended.
+pub fn parse_id(text: &str) -> std::io::Result<(u32, bool)> {
+    let id =
+        u32::from_str_radix(text, 16).map_err(|_| invalid(format!("Invalid CAN id: {}", text)))?;
+    let extended = text.len() == 8 || id > 0x7FF;
+    if id > 0x1FFF_FFFF {
+        return Err(invalid(format!("CAN id out of range: {}", text)));
+    }
+    Ok((id, extended))
+}
+
+fn parse_hex_bytes(text: &str) -> std::io::Result<Vec<u8>> {
+    if !text.is_ascii() || text.len() % 2 != 0 {
+        return Err(invalid(format!("Invalid hex data: {}", text)));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&text[i..i + 2], 16)
+                .map_err(|_| invalid(format!("Invalid hex data: {}", text)))
+        })
+        .collect()
+}
+
+fn build_frame(id: u32, extended: bool, data: &[u8]) -> std::io::Result<CanFrame> {
+    let frame = if extended {
+        CanFrame::new_eff(id, data)
+    } else {
+        CanFrame::new(id, data)
+    };
+    frame.map_err(|e| invalid(e.to_string()))
+}
+
+fn check_classic(frame: &CanFrame) -> std::io::Result<()> {
+    if frame.is_error() || frame.is_rtr() || frame.data().len() > 8 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "socketcand raw mode only carries classic CAN data frames",
+        ));
+    }
+    Ok(())
+}
+
+/// Format a transmit request: `< send 123 2 11 22 >`.
+pub fn format_send(frame: &CanFrame) -> std::io::Result<String> {
+    check_classic(frame)?;
+    let mut words = format!("send {} {}", format_id(frame), frame.data().len());
+    for byte in frame.data() {
+        words.push_str(&format!(" {:02X}", byte));
+    }
+    Ok(element(&words))
+}
+
+/// Parse the words of a `send` element.
+pub fn parse_send(words: &[String]) -> std::io::Result<CanFrame> {
+    parse_frame_words(words.get(1..).unwrap_or_default())
+}
+
+/// Parse `<id> <dlc> <byte> ...` as used by `send`, `add` and `update`.
+pub fn parse_frame_words(words: &[String]) -> std::io::Result<CanFrame> {
+    let [id, dlc, bytes @ ..] = words else {
+        return Err(invalid(format!("Malformed frame: {}", words.join(" "))));
+    };
+    let (id, extended) = parse_id(id)?;
+    let dlc: usize = dlc
+        .parse()
+        .map_err(|_| invalid(format!("Invalid DLC: {}", dlc)))?;
+    if dlc > 8 || bytes.len() < dlc {
+        return Err(invalid(format!("Malformed frame: {}", words.join(" "))));
+    }
+    let data = bytes[..dlc]
+        .iter()
+        .map(|byte| {
+            u8::from_str_radix(byte, 16).map_err(|_| invalid(format!("Invalid byte: {}", byte)))
+        })
+        .collect::<std::io::Result<Vec<u8>>>()?;
+    build_frame(id, extended, &data)
+}
+
+/// Parse a `<sec> <usec>` interval.
+pub fn parse_interval(sec: &str, usec: &str) -> std::io::Result<std::time::Duration> {
+    let sec: u64 = sec
+        .parse()
+        .map_err(|_| invalid(format!("Invalid seconds: {}", sec)))?;
+    let usec: u64 = usec
+        .parse()
+        .map_err(|_| invalid(format!("Invalid microseconds: {}", usec)))?;
+    Ok(std::time::Duration::from_secs(sec) + std::time::Duration::from_micros(usec))
+}
+
+/// Format a received frame: `< frame 123 1700000000.123456 1122 >`.
+/// Frames without a timestamp are stamped with `now_us`.
+pub fn format_frame(frame: &CanFrame, now_us: u64) -> std::io::Result<String> {
+    check_classic(frame)?;
+    let us = frame.timestamp().unwrap_or(now_us);
+    let data: String = frame.data().iter().map(|b| format!("{:02X}", b)).collect();
+    Ok(element(&format!(
+        "frame {} {}.{:06} {}",
+        format_id(frame),
+        us / 1_000_000,
+        us % 1_000_000,
+        data
+    )))
+}
+
+/// Parse the words of a `frame` element, returning the frame and its
+/// timestamp in microseconds.
+pub fn parse_frame(words: &[String]) -> std::io::Result<(CanFrame, Option<u64>)> {
+    let [_, id, time, data @ ..] = words else {
+        return Err(invalid(format!("Malformed frame: {}", words.join(" "))));
+    };
+    let (id, extended) = parse_id(id)?;
+    // Some socketcand versions separate the data bytes with spaces.
+    let data = parse_hex_bytes(&data.concat())?;
+    let frame = build_frame(id, extended, &data)?;
+
+    let timestamp = time.split_once('.').and_then(|(sec, usec)| {
+        let sec: u64 = sec.parse().ok()?;
+        let usec: u64 = format!("{:0<6}", usec).get(..6)?.parse().ok()?;
+        Some(sec * 1_000_000 + usec)
+    });
+    Ok((frame, timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn eight_digits_or_a_large_id_mean_extended() {
+        assert_eq!(parse_id("123").unwrap(), (0x123, false));
+        assert_eq!(parse_id("7FF").unwrap(), (0x7FF, false));
+        assert_eq!(parse_id("00000123").unwrap(), (0x123, true));
+        assert_eq!(parse_id("1FFFFFFF").unwrap(), (0x1FFF_FFFF, true));
+        assert_eq!(parse_id("800").unwrap(), (0x800, true));
+        assert_eq!(parse_id("12345").unwrap(), (0x12345, true));
+    }
+
+    #[test]
+    fn rejects_invalid_ids() {
+        assert!(parse_id("20000000").is_err());
+        assert!(parse_id("12G").is_err());
+        assert!(parse_id("").is_err());
+    }
+
+    #[test]
+    fn formats_frames_with_their_timestamp() {
+        let mut frame = CanFrame::new(0x123, &[0x11, 0x22]).unwrap();
+        frame.set_timestamp(Some(1_700_000_000_000_042));
+        assert_eq!(
+            format_frame(&frame, 0).unwrap(),
+            "< frame 123 1700000000.000042 1122 >"
+        );
+
+        let frame = CanFrame::new_eff(0x123, &[]).unwrap();
+        assert_eq!(
+            format_frame(&frame, 5_000_001).unwrap(),
+            "< frame 00000123 5.000001  >"
+        );
+    }
+
+    #[test]
+    fn formatted_frames_parse_back() {
+        let mut frame = CanFrame::new_eff(0x1ABCDEF, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        frame.set_timestamp(Some(12_345_678));
+        let mut buf = format_frame(&frame, 0).unwrap().into_bytes();
+        let (parsed, timestamp) = parse_frame(&next_element(&mut buf).unwrap()).unwrap();
+        assert!(parsed.is_extended());
+        assert_eq!((parsed.id(), parsed.data()), (0x1ABCDEF, frame.data()));
+        assert_eq!(timestamp, Some(12_345_678));
+    }
+
+    #[test]
+    fn only_classic_data_frames_are_formatted() {
+        let remote = CanFrame::new_remote(0x123, 2, false).unwrap();
+        let long = CanFrame::new(0x123, &[0; 12]).unwrap();
+        assert!(format_frame(&remote, 0).is_err());
+        assert!(format_frame(&long, 0).is_err());
+        assert!(format_send(&long).is_err());
+    }
+
+    #[test]
+    fn parses_spaced_data_and_short_timestamps() {
+        let (frame, timestamp) = parse_frame(&words("frame 123 1.5 11 22")).unwrap();
+        assert_eq!(frame.data(), &[0x11, 0x22]);
+        assert_eq!(timestamp, Some(1_500_000));
+
+        let (_, timestamp) = parse_frame(&words("frame 123 now")).unwrap();
+        assert_eq!(timestamp, None);
+        assert!(parse_frame(&words("frame 123 1.0 123")).is_err());
+        assert!(parse_frame(&words("frame 123")).is_err());
+    }
+
+    #[test]
+    fn send_round_trips() {
+        let frame = CanFrame::new(0x7FF, &[0xDE, 0xAD]).unwrap();
+        let message = format_send(&frame).unwrap();
+        assert_eq!(message, "< send 7FF 2 DE AD >");
+        let parsed = parse_send(&next_element(&mut message.into_bytes()).unwrap()).unwrap();
+        assert!(!parsed.is_extended());
+        assert_eq!((parsed.id(), parsed.data()), (0x7FF, &[0xDE, 0xAD][..]));
+        assert!(parse_send(&words("send 123 9 0 0 0 0 0 0 0 0 0")).is_err());
+        assert!(parse_send(&words("send 123 2 11")).is_err());
+    }
+
+    #[test]
+    fn skips_noise_between_elements() {
+        let mut buf = b"junk< hi >\n<  >< ok > < open".to_vec();
+        assert_eq!(next_element(&mut buf), Some(words("hi")));
+        assert_eq!(next_element(&mut buf), Some(words("ok")));
+        assert_eq!(next_element(&mut buf), None);
+        assert_eq!(buf, b"< open");
+    }
+}
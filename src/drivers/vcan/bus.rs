@@ -0,0 +1,230 @@
+/// Named virtual buses shared by every `VirtualDriver` in the process, and
+/// optionally with other processes over loopback multicast.
+use crosscan::can::CanFrame;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Instant;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast;
+
+/// Frames buffered per receiver before a slow reader starts losing them.
+const BUS_CAPACITY: usize = 1024;
+
+/// UDP port used by all cross-process virtual buses; buses are told apart by
+/// multicast group and name.
+pub const SHARED_BUS_PORT: u16 = 47_800;
+
+const WIRE_MAGIC: &[u8; 4] = b"VCAN";
+const WIRE_VERSION: u8 = 1;
+
+/// A frame travelling on a virtual bus.
+#[derive(Debug, Clone)]
+pub(crate) struct BusMessage {
+    /// Node that transmitted the frame, used to suppress local echo.
+    pub(crate) sender: u64,
+    pub(crate) frame: CanFrame,
+}
+
+pub(crate) struct VirtualBus {
+    name: String,
+    tx: broadcast::Sender<BusMessage>,
+    /// End of the frame currently "on the wire" when bitrate timing is simulated.
+    busy_until: Mutex<Instant>,
+    /// Open nodes that acknowledge frames, i.e. not in listen-only mode.
+    acking_nodes: AtomicUsize,
+    shared: OnceLock<Arc<UdpSocket>>,
+}
+
+static BUSES: OnceLock<Mutex<HashMap<String, Weak<VirtualBus>>>> = OnceLock::new();
+static NEXT_NODE: AtomicU64 = AtomicU64::new(1);
+
+/// Identifies this process on shared buses so its own datagrams are ignored.
+fn process_nonce() -> u64 {
+    static NONCE: OnceLock<u64> = OnceLock::new();
+    *NONCE.get_or_init(|| {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        nanos ^ ((std::process::id() as u64) << 32)
+    })
+}
+
+/// Allocate a node id unique within this process.
+pub(crate) fn next_node_id() -> u64 {
+    NEXT_NODE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Get the bus called `name`, creating it if no driver currently uses it.
+pub(crate) fn bus(name: &str) -> Arc<VirtualBus> {
+    let mut buses = BUSES
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some(bus) = buses.get(name).and_then(Weak::upgrade) {
+        return bus;
+    }
+    buses.retain(|_, bus| bus.strong_count() > 0);
+
+    let (tx, _) = broadcast::channel(BUS_CAPACITY);
+    let bus = Arc::new(VirtualBus {
+        name: name.to_string(),
+        tx,
+        busy_until: Mutex::new(Instant::now()),
+        acking_nodes: AtomicUsize::new(0),
+        shared: OnceLock::new(),
+    });
+    buses.insert(name.to_string(), Arc::downgrade(&bus));
+    bus
+}
+
+impl VirtualBus {
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<BusMessage> {
+        self.tx.subscribe()
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Register (`true`) or remove (`false`) a node that acknowledges frames.
+    pub(crate) fn set_acking(&self, acking: bool) {
+        if acking {
+            self.acking_nodes.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.acking_nodes.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of local nodes that would acknowledge a frame.
+    pub(crate) fn acking_nodes(&self) -> usize {
+        self.acking_nodes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_shared(&self) -> bool {
+        self.shared.get().is_some()
+    }
+
+    /// Reserve the bus for `duration` and return when the frame would finish.
+    pub(crate) fn reserve(&self, duration: std::time::Duration) -> Instant {
+        let mut busy = self.busy_until.lock().unwrap_or_else(|e| e.into_inner());
+        let start = (*busy).max(Instant::now());
+        *busy = start + duration;
+        *busy
+    }
+
+    /// Deliver a frame to local nodes and, for shared buses, other processes.
+    pub(crate) async fn publish(&self, sender: u64, frame: CanFrame) -> std::io::Result<()> {
+        if let Some(socket) = self.shared.get() {
+            let datagram = encode_datagram(&self.name, &frame)?;
+            socket
+                .send_to(&datagram, (multicast_group(&self.name), SHARED_BUS_PORT))
+                .await?;
+        }
+        // No receivers is not an error; the frame simply goes unheard.
+        let _ = self.tx.send(BusMessage { sender, frame });
+        Ok(())
+    }
+
+    /// Join the cross-process bus of the same name. Idempotent.
+    pub(crate) fn share(self: &Arc<Self>) -> std::io::Result<()> {
+        if self.is_shared() {
+            return Ok(());
+        }
+        let group = multicast_group(&self.name);
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, SHARED_BUS_PORT).into())?;
+        socket.join_multicast_v4(&group, &Ipv4Addr::LOCALHOST)?;
+        socket.set_multicast_if_v4(&Ipv4Addr::LOCALHOST)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(0)?;
+        let socket = Arc::new(UdpSocket::from_std(socket.into())?);
+
+        if self.shared.set(socket.clone()).is_err() {
+            return Ok(()); // another driver won the race
+        }
+        tokio::spawn(receive_shared(socket, Arc::downgrade(self)));
+        log::info!(
+            "vcan: bus {} shared on {}:{}",
+            self.name,
+            group,
+            SHARED_BUS_PORT
+        );
+        Ok(())
+    }
+}
+
+/// Relay datagrams from other processes onto the local bus until it is dropped.
+async fn receive_shared(socket: Arc<UdpSocket>, bus: Weak<VirtualBus>) {
+    let mut buf = vec![0u8; 512];
+    loop {
+        let n = match socket.recv(&mut buf).await {
+            Ok(n) => n,
+            Err(e) => {
+                log::warn!("vcan: shared bus receive failed: {}", e);
+                return;
+            }
+        };
+        let Some(bus) = bus.upgrade() else {
+            return;
+        };
+        if let Some(frame) = decode_datagram(&bus.name, &buf[..n]) {
+            // Node 0 marks frames that came from another process.
+            let _ = bus.tx.send(BusMessage { sender: 0, frame });
+        }
+    }
+}
+
+/// Map a bus name onto an administratively scoped group in 239.255.0.0/16.
+fn multicast_group(name: &str) -> Ipv4Addr {
+    // FNV-1a keeps the mapping stable across processes and builds.
+    let hash = name.bytes().fold(0x811c_9dc5u32, |h, b| {
+        (h ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    });
+    Ipv4Addr::new(239, 255, (hash >> 8) as u8, hash as u8)
+}
+
+fn encode_datagram(name: &str, frame: &CanFrame) -> std::io::Result<Vec<u8>> {
+    let name = name.as_bytes();
+    if name.len() > u8::MAX as usize {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Virtual bus name too long",
+        ));
+    }
+    let mut out = Vec::with_capacity(16 + name.len() + 80);
+    out.extend_from_slice(WIRE_MAGIC);
+    out.push(WIRE_VERSION);
+    out.extend_from_slice(&process_nonce().to_le_bytes());
+    out.push(name.len() as u8);
+    out.extend_from_slice(name);
+    let body = bincode::serde::encode_to_vec(frame, bincode::config::standard())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+fn decode_datagram(name: &str, datagram: &[u8]) -> Option<CanFrame> {
+    let rest = datagram.strip_prefix(WIRE_MAGIC)?;
+    let (&version, rest) = rest.split_first()?;
+    if version != WIRE_VERSION {
+        return None;
+    }
+    let nonce = u64::from_le_bytes(rest.get(..8)?.try_into().ok()?);
+    if nonce == process_nonce() {
+        return None; // our own transmission, already delivered locally
+    }
+    let (&name_len, rest) = rest[8..].split_first()?;
+    let (bus_name, body) = rest.split_at_checked(name_len as usize)?;
+    if bus_name != name.as_bytes() {
+        return None; // hash collision with another bus
+    }
+    bincode::serde::decode_from_slice::<CanFrame, _>(body, bincode::config::standard())
+        .ok()
+        .map(|(frame, _)| frame)
+}
@@ -0,0 +1,440 @@
+use async_trait::async_trait;
+use crosscan::can::CanFrame;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+use super::bus::{BusMessage, VirtualBus, bus, next_node_id};
+use crate::busload::{Stuffing, frame_bits};
+use crate::drivers::{CanDriver, RECEIVE_WAIT, timestamp_us};
+
+/// Bitrate assumed for timing simulation until one is set.
+const DEFAULT_BITRATE: u32 = 500_000;
+
+// Error classes carried in the id of a SocketCAN error frame (<linux/can/error.h>).
+const CAN_ERR_CRTL: u32 = 0x0000_0004;
+const CAN_ERR_PROT: u32 = 0x0000_0008;
+const CAN_ERR_ACK: u32 = 0x0000_0020;
+const CAN_ERR_BUSERROR: u32 = 0x0000_0080;
+
+/// Behaviour of a [`VirtualDriver`] node.
+#[derive(Debug, Clone)]
+pub struct VirtualOptions {
+    /// Also exchange frames with other processes on this host using the same bus name.
+    pub shared: bool,
+    /// Delay transmissions by the time the frame would occupy a real bus at
+    /// the configured bitrate. Frames from all nodes on the bus are serialized.
+    pub simulate_timing: bool,
+    /// Fail transmissions with an ACK error when no other node would
+    /// acknowledge them, as on a real bus. Off by default, like Linux `vcan`.
+    pub require_ack: bool,
+    /// Deliver the node's own transmissions back to it.
+    pub loopback: bool,
+    /// Probability (0.0 to 1.0) that a transmission is preceded by a bus error frame.
+    pub error_rate: f64,
+}
+
+impl Default for VirtualOptions {
+    fn default() -> Self {
+        Self {
+            shared: false,
+            simulate_timing: false,
+            require_ack: false,
+            loopback: false,
+            error_rate: 0.0,
+        }
+    }
+}
+
+/// Software CAN bus, similar to Linux `vcan`. Every driver opened with the
+/// same bus name receives the frames the others send.
+pub struct VirtualDriver {
+    bus: Arc<VirtualBus>,
+    node: u64,
+    options: VirtualOptions,
+    rx: Option<broadcast::Receiver<BusMessage>>,
+    listen_only: bool,
+    timestamps: bool,
+    configured_bitrate: Option<u32>,
+    configured_data_bitrate: Option<u32>,
+    /// Error frames addressed to this node only, e.g. missing ACKs.
+    pending: Vec<CanFrame>,
+    rng: u64,
+}
+
+impl VirtualDriver {
+    /// Attach to the virtual bus `name` (e.g. "vcan0"), creating it if needed.
+    pub async fn open(name: &str) -> std::io::Result<Self> {
+        Self::open_with_options(name, VirtualOptions::default()).await
+    }
+
+    pub async fn open_with_options(name: &str, options: VirtualOptions) -> std::io::Result<Self> {
+        if name.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Virtual bus name must not be empty",
+            ));
+        }
+        if !(0.0..=1.0).contains(&options.error_rate) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Error rate must be between 0 and 1: {}", options.error_rate),
+            ));
+        }
+
+        let bus = bus(name);
+        if options.shared {
+            bus.share()?;
+        }
+        let node = next_node_id();
+        Ok(Self {
+            bus,
+            node,
+            options,
+            rx: None,
+            listen_only: false,
+            timestamps: false,
+            configured_bitrate: None,
+            configured_data_bitrate: None,
+            pending: Vec::new(),
+            rng: (timestamp_us() ^ node.rotate_left(32)) | 1,
+        })
+    }
+
+    pub fn bus_name(&self) -> &str {
+        self.bus.name()
+    }
+
+    /// Set the CAN FD data phase bitrate used for timing simulation.
+    pub fn set_data_bitrate(&mut self, data_bitrate: u32) -> std::io::Result<()> {
+        if data_bitrate == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Data bitrate must be non-zero",
+            ));
+        }
+        self.configured_data_bitrate = Some(data_bitrate);
+        Ok(())
+    }
+
+    /// Put an error frame with the given SocketCAN error class on the bus.
+    pub async fn inject_error(&mut self, class: u32) -> std::io::Result<()> {
+        let frame = CanFrame::new_error(class)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        self.bus.publish(self.node, frame).await
+    }
+
+    fn attach(&mut self, listen_only: bool) {
+        self.detach();
+        self.rx = Some(self.bus.subscribe());
+        self.listen_only = listen_only;
+        if !listen_only {
+            self.bus.set_acking(true);
+        }
+    }
+
+    fn detach(&mut self) {
+        if self.rx.take().is_some() && !self.listen_only {
+            self.bus.set_acking(false);
+        }
+        self.pending.clear();
+    }
+
+    /// xorshift64; good enough to decide when to inject errors.
+    fn next_random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Time `frame` would occupy the bus, ignoring bit stuffing.
+    fn frame_duration(&self, frame: &CanFrame) -> Duration {
+        let bitrate = self.configured_bitrate.unwrap_or(DEFAULT_BITRATE);
+        frame_bits(frame, Stuffing::Ignore).duration(bitrate, self.configured_data_bitrate)
+    }
+
+    fn accept(&self, message: BusMessage, frames: &mut Vec<CanFrame>) {
+        // Error frames describe the bus and reach every node, the sender included.
+        if message.sender == self.node && !self.options.loopback && !message.frame.is_error() {
+            return;
+        }
+        let mut frame = message.frame;
+        if self.timestamps && frame.timestamp().is_none() {
+            frame.set_timestamp(Some(timestamp_us()));
+        }
+        frames.push(frame);
+    }
+}
+
+impl Drop for VirtualDriver {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+fn overrun_frame() -> Option<CanFrame> {
+    CanFrame::new_error(CAN_ERR_CRTL).ok()
+}
+
+#[async_trait]
+impl CanDriver for VirtualDriver {
+    async fn enable_timestamp(&mut self) -> std::io::Result<()> {
+        self.timestamps = true;
+        Ok(())
+    }
+
+    async fn set_bitrate(&mut self, bitrate: u32) -> std::io::Result<()> {
+        if bitrate == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Bitrate must be non-zero",
+            ));
+        }
+        self.configured_bitrate = Some(bitrate);
+        Ok(())
+    }
+
+    async fn get_bitrate(&self) -> Option<u32> {
+        self.configured_bitrate
+    }
+
+    async fn get_data_bitrate(&self) -> Option<u32> {
+        self.configured_data_bitrate
+    }
+
+    async fn open_channel(&mut self) -> std::io::Result<()> {
+        self.attach(false);
+        Ok(())
+    }
+
+    async fn open_listen_only(&mut self) -> std::io::Result<()> {
+        self.attach(true);
+        Ok(())
+    }
+
+    async fn send_frame(&mut self, frame: &CanFrame) -> std::io::Result<()> {
+        if self.rx.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "Virtual channel is not open",
+            ));
+        }
+        if self.listen_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Virtual channel is open in listen-only mode",
+            ));
+        }
+
+        if self.options.simulate_timing {
+            let done = self.bus.reserve(self.frame_duration(frame));
+            tokio::time::sleep_until(done.into()).await;
+        }
+
+        // Our own node is counted among the acknowledging nodes.
+        if self.options.require_ack && !self.bus.is_shared() && self.bus.acking_nodes() < 2 {
+            self.pending.extend(CanFrame::new_error(CAN_ERR_ACK).ok());
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("No node acknowledged the frame on {}", self.bus.name()),
+            ));
+        }
+
+        if self.options.error_rate > 0.0 && self.next_random() < self.options.error_rate {
+            self.inject_error(CAN_ERR_PROT | CAN_ERR_BUSERROR).await?;
+        }
+
+        let mut frame = frame.clone();
+        if self.timestamps {
+            frame.set_timestamp(Some(timestamp_us()));
+        }
+        self.bus.publish(self.node, frame).await
+    }
+
+    async fn read_frames(&mut self) -> std::io::Result<Vec<CanFrame>> {
+        let mut frames = std::mem::take(&mut self.pending);
+        let Some(rx) = self.rx.as_mut() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "Virtual channel is not open",
+            ));
+        };
+
+        let mut received = Vec::new();
+        if frames.is_empty() {
+            match tokio::time::timeout(RECEIVE_WAIT, rx.recv()).await {
+                Ok(Ok(message)) => received.push(message),
+                Ok(Err(broadcast::error::RecvError::Lagged(n))) => {
+                    log::warn!("vcan: receiver lagged, {} frames dropped", n);
+                    frames.extend(overrun_frame());
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => {}
+            }
+        }
+        loop {
+            match rx.try_recv() {
+                Ok(message) => received.push(message),
+                Err(TryRecvError::Lagged(n)) => {
+                    log::warn!("vcan: receiver lagged, {} frames dropped", n);
+                    frames.extend(overrun_frame());
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+
+        for message in received {
+            self.accept(message, &mut frames);
+        }
+        Ok(frames)
+    }
+
+    async fn close_channel(&mut self) -> std::io::Result<()> {
+        self.detach();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn node(bus: &str, options: VirtualOptions) -> VirtualDriver {
+        let mut driver = VirtualDriver::open_with_options(bus, options)
+            .await
+            .unwrap();
+        driver.open_channel().await.unwrap();
+        driver
+    }
+
+    /// Everything a node receives until the bus has been quiet for a read.
+    async fn drain(driver: &mut VirtualDriver) -> Vec<CanFrame> {
+        let mut frames = Vec::new();
+        loop {
+            let received = driver.read_frames().await.unwrap();
+            if received.is_empty() {
+                return frames;
+            }
+            frames.extend(received);
+        }
+    }
+
+    fn frame(id: u32) -> CanFrame {
+        CanFrame::new(id, &[id as u8]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn delivers_to_other_nodes_only() {
+        let mut a = node("test-delivery", VirtualOptions::default()).await;
+        let mut b = node("test-delivery", VirtualOptions::default()).await;
+        let mut elsewhere = node("test-delivery-other", VirtualOptions::default()).await;
+
+        a.send_frame(&frame(0x100)).await.unwrap();
+        b.send_frame(&frame(0x200)).await.unwrap();
+
+        let at_a = drain(&mut a).await;
+        let at_b = drain(&mut b).await;
+        assert_eq!(at_a.iter().map(|f| f.id()).collect::<Vec<_>>(), [0x200]);
+        assert_eq!(at_b.iter().map(|f| f.id()).collect::<Vec<_>>(), [0x100]);
+        assert!(drain(&mut elsewhere).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn loopback_returns_own_frames() {
+        let options = VirtualOptions {
+            loopback: true,
+            ..VirtualOptions::default()
+        };
+        let mut a = node("test-loopback", options).await;
+        let mut b = node("test-loopback", VirtualOptions::default()).await;
+
+        a.send_frame(&frame(0x123)).await.unwrap();
+        assert_eq!(drain(&mut a).await.len(), 1);
+        assert_eq!(drain(&mut b).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn require_ack_fails_without_another_node() {
+        let options = VirtualOptions {
+            require_ack: true,
+            ..VirtualOptions::default()
+        };
+        let mut a = node("test-ack", options).await;
+
+        assert!(a.send_frame(&frame(0x123)).await.is_err());
+        let errors = drain(&mut a).await;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].is_error());
+        assert_eq!(errors[0].id(), CAN_ERR_ACK);
+
+        // A listen-only node does not acknowledge.
+        let mut listener = VirtualDriver::open("test-ack").await.unwrap();
+        listener.open_listen_only().await.unwrap();
+        assert!(a.send_frame(&frame(0x123)).await.is_err());
+        assert!(listener.send_frame(&frame(0x456)).await.is_err());
+
+        let mut b = node("test-ack", VirtualOptions::default()).await;
+        drain(&mut a).await;
+        a.send_frame(&frame(0x123)).await.unwrap();
+        assert_eq!(drain(&mut b).await.len(), 1);
+        assert_eq!(drain(&mut listener).await.len(), 1);
+
+        // Once the other node leaves, frames go unacknowledged again.
+        b.close_channel().await.unwrap();
+        assert!(a.send_frame(&frame(0x123)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn error_rate_zero_never_injects_and_one_always_does() {
+        let never = VirtualOptions {
+            error_rate: 0.0,
+            ..VirtualOptions::default()
+        };
+        let always = VirtualOptions {
+            error_rate: 1.0,
+            ..VirtualOptions::default()
+        };
+
+        let mut a = node("test-errors-0", never).await;
+        let mut b = node("test-errors-0", VirtualOptions::default()).await;
+        for id in 0..100 {
+            a.send_frame(&frame(id)).await.unwrap();
+        }
+        let received = drain(&mut b).await;
+        assert_eq!(received.len(), 100);
+        assert!(received.iter().all(|f| !f.is_error()));
+
+        let mut a = node("test-errors-1", always).await;
+        let mut b = node("test-errors-1", VirtualOptions::default()).await;
+        for id in 0..10 {
+            a.send_frame(&frame(id)).await.unwrap();
+        }
+        let received = drain(&mut b).await;
+        assert_eq!(received.len(), 20);
+        for pair in received.chunks(2) {
+            assert!(pair[0].is_error());
+            assert_eq!(pair[0].id(), CAN_ERR_PROT | CAN_ERR_BUSERROR);
+            assert!(!pair[1].is_error());
+        }
+        // Error frames reach the sender too.
+        assert_eq!(drain(&mut a).await.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_options() {
+        let options = VirtualOptions {
+            error_rate: 1.5,
+            ..VirtualOptions::default()
+        };
+        assert!(
+            VirtualDriver::open_with_options("test-invalid", options)
+                .await
+                .is_err()
+        );
+        assert!(VirtualDriver::open("").await.is_err());
+
+        let mut closed = VirtualDriver::open("test-invalid").await.unwrap();
+        let err = closed.send_frame(&frame(1)).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotConnected);
+    }
+}
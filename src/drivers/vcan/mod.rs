@@ -0,0 +1,6 @@
+/// Virtual CAN buses for running the tools without hardware.
+mod bus;
+mod driver;
+
+pub use bus::SHARED_BUS_PORT;
+pub use driver::{VirtualDriver, VirtualOptions};
@@ -0,0 +1,239 @@
+/// The candump filter grammar: `<ifname>[,filter]*` where each filter is
+/// `<can_id>:<can_mask>` (match), `<can_id>~<can_mask>` (inverse match),
+/// `#<error_mask>` (error frame classes), a bare `<can_id>`, or `j`/`J` to
+/// require all filters to match instead of any.
+use crosscan::can::CanFrame;
+use std::num::ParseIntError;
+
+/// A parsed filter from the candump filter grammar.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    /// <can_id>:<can_mask>; `extended` if the id has 8 digits or exceeds 0x7FF.
+    Match { id: u32, mask: u32, extended: bool },
+    /// <can_id>~<can_mask>
+    NotMatch { id: u32, mask: u32, extended: bool },
+    /// #<error_mask>
+    ErrorMask(u32),
+    /// Join flag: 'j' or 'J' means join filters (logical AND)
+    Join,
+}
+
+impl Filter {
+    /// Returns true if this filter matches the given CAN ID
+    pub fn matches(&self, can_id: u32) -> bool {
+        match *self {
+            Filter::Match { id, mask, .. } => (can_id & mask) == (id & mask),
+            Filter::NotMatch { id, mask, .. } => (can_id & mask) != (id & mask),
+            Filter::ErrorMask(_) => false, // error filters are not for normal CAN IDs
+            Filter::Join => true,          // Join is logical AND, doesn't filter on its own
+        }
+    }
+}
+
+/// The filters given for one interface.
+#[derive(Debug, Clone, Default)]
+pub struct FilterSet {
+    pub filters: Vec<Filter>,
+}
+
+impl FilterSet {
+    /// Parse comma separated filter tokens, e.g. `123:7FF,j,#FFFFFFFF`.
+    pub fn parse<'a>(tokens: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut filters = Vec::new();
+        for token in tokens {
+            let token = token.trim();
+            if token.is_empty() {
+                continue;
+            }
+            if token.eq_ignore_ascii_case("j") {
+                filters.push(Filter::Join);
+                continue;
+            }
+            if let Some(rest) = token.strip_prefix('#') {
+                let val =
+                    parse_hex(rest).map_err(|e| format!("invalid error mask '{}': {}", rest, e))?;
+                filters.push(Filter::ErrorMask(val));
+                continue;
+            }
+            if let Some((a, b)) = token.split_once(':') {
+                let id = parse_hex(a).map_err(|e| format!("invalid can_id '{}': {}", a, e))?;
+                let mask = parse_hex(b).map_err(|e| format!("invalid can_mask '{}': {}", b, e))?;
+                let extended = is_extended(a, id);
+                filters.push(Filter::Match { id, mask, extended });
+                continue;
+            }
+            if let Some((a, b)) = token.split_once('~') {
+                let id = parse_hex(a).map_err(|e| format!("invalid can_id '{}': {}", a, e))?;
+                let mask = parse_hex(b).map_err(|e| format!("invalid can_mask '{}': {}", b, e))?;
+                let extended = is_extended(a, id);
+                filters.push(Filter::NotMatch { id, mask, extended });
+                continue;
+            }
+            // If token looks like plain hex (e.g. 12345678), treat as id with default mask 0xFFFFFFFF
+            if is_hex(token) {
+                let id =
+                    parse_hex(token).map_err(|e| format!("invalid hex id '{}': {}", token, e))?;
+                filters.push(Filter::Match {
+                    id,
+                    mask: 0xFFFFFFFF,
+                    extended: is_extended(token, id),
+                });
+                continue;
+            }
+
+            return Err(format!("unrecognized filter token: '{}'", token));
+        }
+        Ok(Self { filters })
+    }
+
+    /// Parse an interface specification `<ifname>[,filter]*`.
+    pub fn parse_spec(spec: &str) -> Result<(String, Self), String> {
+        let mut parts = spec.split(',');
+        let ifname = parts
+            .next()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| "empty interface specification".to_string())?
+            .to_string();
+        Ok((ifname, Self::parse(parts)?))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Returns true if the given CAN ID passes the filters: any of them, or
+    /// all of them when joined. Without id filters every ID passes.
+    pub fn filter_check(&self, can_id: u32) -> bool {
+        let join = self.filters.iter().any(|f| matches!(f, Filter::Join));
+        let mut id_filters = self
+            .filters
+            .iter()
+            .filter(|f| matches!(f, Filter::Match { .. } | Filter::NotMatch { .. }))
+            .peekable();
+        if id_filters.peek().is_none() {
+            return true;
+        }
+        if join {
+            id_filters.all(|f| f.matches(can_id))
+        } else {
+            id_filters.any(|f| f.matches(can_id))
+        }
+    }
+
+    /// Returns true if `frame` passes. Error frames pass only if their class
+    /// is in an error mask, unless no filters were given at all.
+    pub fn matches(&self, frame: &CanFrame) -> bool {
+        if self.filters.is_empty() {
+            return true;
+        }
+        if frame.is_error() {
+            return self
+                .filters
+                .iter()
+                .any(|f| matches!(f, Filter::ErrorMask(mask) if frame.id() & mask != 0));
+        }
+        self.filter_check(frame.id())
+    }
+}
+
+/// Ids written with 8 digits, like candump's, or too large for 11 bits are extended.
+fn is_extended(text: &str, id: u32) -> bool {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    digits.len() == 8 || id > 0x7FF
+}
+
+fn is_hex(s: &str) -> bool {
+    s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn parse_hex(s: &str) -> Result<u32, ParseIntError> {
+    // Accept leading 0x/0X or plain hex
+    if let Some(rest) = s.strip_prefix("0x") {
+        u32::from_str_radix(rest, 16)
+    } else if let Some(rest) = s.strip_prefix("0X") {
+        u32::from_str_radix(rest, 16)
+    } else {
+        u32::from_str_radix(s, 16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(spec: &str) -> FilterSet {
+        FilterSet::parse(spec.split(',')).unwrap()
+    }
+
+    #[test]
+    fn match_and_inverse_match() {
+        let filters = set("100:700");
+        assert!(filters.filter_check(0x100));
+        assert!(filters.filter_check(0x1FF));
+        assert!(!filters.filter_check(0x200));
+
+        let filters = set("100~7FF");
+        assert!(!filters.filter_check(0x100));
+        assert!(filters.filter_check(0x101));
+    }
+
+    #[test]
+    fn filters_are_ored_unless_joined() {
+        let any = set("100:7F0,200:7F0");
+        assert!(any.filter_check(0x105) && any.filter_check(0x205));
+        assert!(!any.filter_check(0x305));
+
+        // 0x100-0x1FF except 0x123.
+        let all = set("100:700,123~7FF,j");
+        assert!(all.filter_check(0x122));
+        assert!(!all.filter_check(0x123));
+        assert!(!all.filter_check(0x200));
+        assert!(set("J,100:700").filter_check(0x150));
+    }
+
+    #[test]
+    fn bare_ids_match_exactly() {
+        let filters = set("1ABCDEF");
+        assert!(filters.filter_check(0x1ABCDEF));
+        assert!(!filters.filter_check(0x1ABCDEE));
+    }
+
+    #[test]
+    fn error_frames_need_an_error_mask() {
+        let bus_off = CanFrame::new_error(0x40).unwrap();
+        let lost_arbitration = CanFrame::new_error(0x02).unwrap();
+        let data = CanFrame::new(0x123, &[]).unwrap();
+
+        assert!(FilterSet::default().matches(&bus_off));
+        assert!(!set("123:7FF").matches(&bus_off));
+        assert!(set("123:7FF").matches(&data));
+
+        let filters = set("#40");
+        assert!(filters.matches(&bus_off));
+        assert!(!filters.matches(&lost_arbitration));
+        // An error mask alone leaves data frames unfiltered.
+        assert!(filters.matches(&data));
+    }
+
+    #[test]
+    fn parses_interface_specifications() {
+        let (ifname, filters) = FilterSet::parse_spec("can0, 123:7FF ,,#FFFFFFFF").unwrap();
+        assert_eq!(ifname, "can0");
+        assert_eq!(filters.filters.len(), 2);
+        let (ifname, filters) = FilterSet::parse_spec("any").unwrap();
+        assert_eq!(ifname, "any");
+        assert!(filters.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        for spec in ["12G:7FF", "123:", "~7FF", "#XYZ", "k", "123:7FF:1"] {
+            assert!(FilterSet::parse([spec]).is_err(), "{}", spec);
+        }
+        assert!(FilterSet::parse_spec(",123:7FF").is_err());
+    }
+}
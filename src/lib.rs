@@ -1,5 +1,19 @@
+/// Cyclic transmission and content filtered reception run by the server.
+pub mod bcm;
+/// Bus load from exact frame lengths.
+pub mod busload;
+/// candump log file format.
+pub mod can_log;
 /// Collection of supported CAN drivers.
 pub mod drivers;
-pub use drivers::{CanDriver, GsUsbDriver, PcanDriver, SlcanDriver};
+/// candump filter grammar.
+pub mod filter;
+/// Rotating log files for long running captures.
+pub mod log_writer;
+pub use drivers::{CanDriver, GsUsbDriver, PcanDriver, SlcanDriver, VirtualDriver};
+/// SLCAN adapter emulation over TCP or a pseudo-terminal.
+pub mod slcan_bridge;
+/// socketcand compatible TCP access to a channel.
+pub mod socketcand_server;
 /// We'll create this instead of thread_manager.rs
 pub mod thread_manager_async;
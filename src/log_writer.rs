@@ -0,0 +1,409 @@
+/// Log file writer for long running captures: splits the log into segments
+/// by size or age, optionally gzips closed segments and caps the disk space
+/// they use. It only deals in text lines, so it works with any frame source.
+use flate2::{Compression, write::GzEncoder};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Buffered lines reach the disk at least this often.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When to start a new segment and what to do with closed ones.
+#[derive(Debug, Clone, Default)]
+pub struct RotationOptions {
+    /// Start a new segment before one would grow beyond this many bytes.
+    pub max_size: Option<u64>,
+    /// Start a new segment once one has been open this long.
+    pub max_age: Option<Duration>,
+    /// Gzip closed segments to `<segment>.gz`.
+    pub compress: bool,
+    /// Delete the oldest closed segments once together they take more than
+    /// this many bytes. The newest closed segment and the open one are kept.
+    pub max_total: Option<u64>,
+}
+
+impl RotationOptions {
+    /// True if the log is split into numbered segments.
+    pub fn rotates(&self) -> bool {
+        self.max_size.is_some() || self.max_age.is_some()
+    }
+}
+
+/// Writes lines to a log file, rotating it as configured.
+///
+/// Without rotation the log goes to the given path. With rotation, segments
+/// are numbered from it: `candump.log` becomes `candump-000.log`,
+/// `candump-001.log`, and so on. Closed segments are compressed and pruned on
+/// a background thread so writing never waits for them.
+pub struct LogWriter {
+    base: PathBuf,
+    options: RotationOptions,
+    index: u32,
+    path: PathBuf,
+    file: BufWriter<File>,
+    written: u64,
+    opened: Instant,
+    last_flush: Instant,
+    closed: Option<(mpsc::Sender<PathBuf>, JoinHandle<()>)>,
+}
+
+impl LogWriter {
+    pub fn create(path: impl Into<PathBuf>, options: RotationOptions) -> io::Result<Self> {
+        let base = path.into();
+        let path = segment_path(&base, &options, 0);
+        let file = BufWriter::new(File::create(&path)?);
+
+        let closed = (options.compress || options.max_total.is_some()).then(|| {
+            let (tx, rx) = mpsc::channel();
+            let options = options.clone();
+            (tx, thread::spawn(move || process_closed(rx, options)))
+        });
+
+        let now = Instant::now();
+        Ok(Self {
+            base,
+            options,
+            index: 0,
+            path,
+            file,
+            written: 0,
+            opened: now,
+            last_flush: now,
+            closed,
+        })
+    }
+
+    /// Path of the segment currently written.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one line, rotating first if it is due.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        let too_big = self
+            .options
+            .max_size
+            .is_some_and(|max| self.written + len > max);
+        let too_old = self
+            .options
+            .max_age
+            .is_some_and(|max| self.opened.elapsed() >= max);
+        // An empty segment is kept, however old, rather than left behind empty.
+        if self.written > 0 && (too_big || too_old) {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.written += len;
+
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.file.flush()
+    }
+
+    /// Flush and close the open segment and wait until closed segments are
+    /// compressed and pruned.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()?;
+        let Self {
+            path, file, closed, ..
+        } = self;
+        drop(file);
+        if let Some((tx, handle)) = closed {
+            let _ = tx.send(path);
+            drop(tx);
+            if handle.join().is_err() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "log compression thread panicked",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.index += 1;
+        let path = segment_path(&self.base, &self.options, self.index);
+        let file = BufWriter::new(File::create(&path)?);
+
+        // Replacing the writer closes the previous segment.
+        self.file = file;
+        let closed = std::mem::replace(&mut self.path, path);
+        self.written = 0;
+        self.opened = Instant::now();
+
+        if let Some((tx, _)) = &self.closed {
+            let _ = tx.send(closed);
+        }
+        Ok(())
+    }
+}
+
+fn segment_path(base: &Path, options: &RotationOptions, index: u32) -> PathBuf {
+    if !options.rotates() {
+        return base.to_path_buf();
+    }
+    let stem = base
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match base.extension() {
+        Some(ext) => format!("{}-{:03}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}-{:03}", stem, index),
+    };
+    base.with_file_name(name)
+}
+
+/// Compress and prune closed segments, oldest first, until the writer is done.
+fn process_closed(rx: mpsc::Receiver<PathBuf>, options: RotationOptions) {
+    let mut kept: VecDeque<(PathBuf, u64)> = VecDeque::new();
+
+    for path in rx {
+        let path = if options.compress {
+            match compress(&path) {
+                Ok(gz) => gz,
+                Err(e) => {
+                    log::warn!("Failed to compress {}: {}", path.display(), e);
+                    path
+                }
+            }
+        } else {
+            path
+        };
+        let size = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        kept.push_back((path, size));
+
+        if let Some(max_total) = options.max_total {
+            let mut total: u64 = kept.iter().map(|(_, size)| size).sum();
+            while total > max_total && kept.len() > 1 {
+                let Some((oldest, size)) = kept.pop_front() else {
+                    break;
+                };
+                if let Err(e) = fs::remove_file(&oldest) {
+                    log::warn!("Failed to remove {}: {}", oldest.display(), e);
+                }
+                total -= size;
+            }
+        }
+    }
+}
+
+/// Gzip `path` to `<path>.gz` and remove the original.
+fn compress(path: &Path) -> io::Result<PathBuf> {
+    let mut gz_name = path.as_os_str().to_owned();
+    gz_name.push(".gz");
+    let gz_path = PathBuf::from(gz_name);
+
+    let mut input = File::open(path)?;
+    let mut encoder = GzEncoder::new(
+        BufWriter::new(File::create(&gz_path)?),
+        Compression::default(),
+    );
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    drop(input);
+
+    fs::remove_file(path)?;
+    Ok(gz_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    /// A fresh directory for one test, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("win_can_utils-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        /// Names of the files in the directory, sorted.
+        fn files(&self) -> Vec<String> {
+            let mut names: Vec<String> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        }
+
+        fn read(&self, name: &str) -> String {
+            fs::read_to_string(self.0.join(name)).unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn by_size(max_size: u64) -> RotationOptions {
+        RotationOptions {
+            max_size: Some(max_size),
+            ..RotationOptions::default()
+        }
+    }
+
+    #[test]
+    fn names_segments_after_the_base_path() {
+        let base = Path::new("logs/candump.log");
+        let plain = RotationOptions::default();
+        assert_eq!(segment_path(base, &plain, 0), base);
+        assert_eq!(
+            segment_path(base, &by_size(1), 0),
+            Path::new("logs/candump-000.log")
+        );
+        assert_eq!(
+            segment_path(base, &by_size(1), 42),
+            Path::new("logs/candump-042.log")
+        );
+        assert_eq!(
+            segment_path(Path::new("capture"), &by_size(1), 1000),
+            Path::new("capture-1000")
+        );
+    }
+
+    #[test]
+    fn writes_to_the_path_without_rotation() {
+        let dir = TempDir::new("plain");
+        let mut writer =
+            LogWriter::create(dir.0.join("candump.log"), RotationOptions::default()).unwrap();
+        for i in 0..100 {
+            writer.write_line(&format!("line {}", i)).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(dir.files(), ["candump.log"]);
+        assert_eq!(dir.read("candump.log").lines().count(), 100);
+    }
+
+    #[test]
+    fn rotates_before_a_segment_outgrows_its_size() {
+        let dir = TempDir::new("size");
+        // Ten bytes a line with its newline, so three fit in 30.
+        let mut writer = LogWriter::create(dir.0.join("candump.log"), by_size(30)).unwrap();
+        assert_eq!(writer.path(), dir.0.join("candump-000.log"));
+        for i in 0..7 {
+            writer.write_line(&format!("line {:04}", i)).unwrap();
+        }
+        assert_eq!(writer.path(), dir.0.join("candump-002.log"));
+        writer.finish().unwrap();
+
+        assert_eq!(
+            dir.files(),
+            ["candump-000.log", "candump-001.log", "candump-002.log"]
+        );
+        assert_eq!(
+            dir.read("candump-001.log"),
+            "line 0003\nline 0004\nline 0005\n"
+        );
+        assert_eq!(dir.read("candump-002.log"), "line 0006\n");
+    }
+
+    #[test]
+    fn an_oversized_line_gets_a_segment_of_its_own() {
+        let dir = TempDir::new("oversized");
+        let mut writer = LogWriter::create(dir.0.join("candump.log"), by_size(8)).unwrap();
+        writer.write_line("0123456789ABCDEF").unwrap();
+        writer.write_line("short").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(dir.read("candump-000.log"), "0123456789ABCDEF\n");
+        assert_eq!(dir.read("candump-001.log"), "short\n");
+    }
+
+    #[test]
+    fn rotates_segments_by_age() {
+        let dir = TempDir::new("age");
+        let options = RotationOptions {
+            max_age: Some(Duration::from_millis(200)),
+            ..RotationOptions::default()
+        };
+        let mut writer = LogWriter::create(dir.0.join("candump.log"), options).unwrap();
+        writer.write_line("first").unwrap();
+        writer.write_line("second").unwrap();
+        thread::sleep(Duration::from_millis(250));
+        writer.write_line("third").unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(dir.files(), ["candump-000.log", "candump-001.log"]);
+        assert_eq!(dir.read("candump-000.log"), "first\nsecond\n");
+        assert_eq!(dir.read("candump-001.log"), "third\n");
+    }
+
+    #[test]
+    fn gzips_closed_segments() {
+        let dir = TempDir::new("gzip");
+        let options = RotationOptions {
+            compress: true,
+            ..by_size(30)
+        };
+        let mut writer = LogWriter::create(dir.0.join("candump.log"), options).unwrap();
+        for i in 0..5 {
+            writer.write_line(&format!("line {:04}", i)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(dir.files(), ["candump-000.log.gz", "candump-001.log.gz"]);
+        let mut text = String::new();
+        GzDecoder::new(File::open(dir.0.join("candump-000.log.gz")).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "line 0000\nline 0001\nline 0002\n");
+    }
+
+    #[test]
+    fn prunes_the_oldest_segments_beyond_the_total() {
+        let dir = TempDir::new("prune");
+        // One ten byte line a segment; 25 bytes keep two of them.
+        let options = RotationOptions {
+            max_total: Some(25),
+            ..by_size(10)
+        };
+        let mut writer = LogWriter::create(dir.0.join("candump.log"), options).unwrap();
+        for i in 0..6 {
+            writer.write_line(&format!("line {:04}", i)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(dir.files(), ["candump-004.log", "candump-005.log"]);
+        assert_eq!(dir.read("candump-005.log"), "line 0005\n");
+    }
+
+    #[test]
+    fn keeps_the_newest_segment_however_large() {
+        let dir = TempDir::new("prune-large");
+        let options = RotationOptions {
+            max_total: Some(1),
+            ..by_size(10)
+        };
+        let mut writer = LogWriter::create(dir.0.join("candump.log"), options).unwrap();
+        for i in 0..3 {
+            writer.write_line(&format!("line {:04}", i)).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(dir.files(), ["candump-002.log"]);
+    }
+}
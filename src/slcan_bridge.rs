@@ -0,0 +1,451 @@
+/// LAWICEL / SLCAN device emulation, exposing a channel to tools that only
+/// talk to serial SLCAN adapters.
+///
+/// The host's command lines are decoded with [`SlcanDecoder`] and frames are
+/// encoded with [`encode_frame`], the inverse of what `SlcanDriver` does. The
+/// bitrate belongs to the server; `S`/`s`/`Y` commands are acknowledged but
+/// do not reconfigure the channel.
+use crosscan::can::CanFrame;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{Mutex, broadcast};
+
+use crate::drivers::CanDriver;
+use crate::drivers::slcan::codec::encode_frame;
+use crate::drivers::slcan::{SlcanDecoder, SlcanEvent};
+
+const BEL: u8 = 0x07;
+/// Rejection reply.
+const NACK: &[u8] = &[BEL];
+const ACK: &[u8] = b"\r";
+
+/// Hardware/firmware version reported for `V`.
+const VERSION_REPLY: &[u8] = b"V1013\r";
+/// Serial number reported for `N`.
+const SERIAL_REPLY: &[u8] = b"NCSRV\r";
+
+/// Accept SLCAN clients on `listener`, one session per TCP connection.
+/// Frames received from the driver must be published on `frames`.
+pub async fn serve_tcp(
+    listener: TcpListener,
+    driver: Arc<Mutex<Box<dyn CanDriver>>>,
+    frames: broadcast::Sender<CanFrame>,
+) -> std::io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        stream.set_nodelay(true)?;
+        log::info!("slcan bridge: client {} connected", peer);
+        let driver = driver.clone();
+        let rx = frames.subscribe();
+        tokio::spawn(async move {
+            let (reader, writer) = stream.into_split();
+            if let Err(e) = serve(reader, writer, driver, rx).await {
+                log::warn!("slcan bridge: client {}: {}", peer, e);
+            }
+            log::info!("slcan bridge: client {} disconnected", peer);
+        });
+    }
+}
+
+/// Emulate an SLCAN adapter on one byte stream until it is closed.
+pub async fn serve<R, W>(
+    mut reader: R,
+    mut writer: W,
+    driver: Arc<Mutex<Box<dyn CanDriver>>>,
+    mut rx: broadcast::Receiver<CanFrame>,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut device = Device::new(driver);
+    let mut decoder = SlcanDecoder::new();
+    let mut events = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        tokio::select! {
+            n = reader.read(&mut chunk) => {
+                let n = n?;
+                if n == 0 {
+                    return Ok(());
+                }
+                let mut reply = Vec::new();
+                // One line at a time, so the rejection of a line the decoder
+                // drops goes out in order with the replies around it.
+                for line in chunk[..n].split_inclusive(|&b| b == b'\r' || b == BEL) {
+                    let stats = decoder.stats();
+                    let malformed = stats.malformed_lines + stats.overlong_lines;
+                    decoder.push(line, &mut events);
+                    for event in events.drain(..) {
+                        reply.extend_from_slice(&device.handle(event).await);
+                    }
+                    let stats = decoder.stats();
+                    if stats.malformed_lines + stats.overlong_lines > malformed {
+                        reply.extend_from_slice(NACK);
+                    }
+                }
+                writer.write_all(&reply).await?;
+                writer.flush().await?;
+            }
+            received = rx.recv() => match received {
+                Ok(frame) => {
+                    if let Some(line) = device.deliver(&frame) {
+                        writer.write_all(line.as_bytes()).await?;
+                        writer.flush().await?;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    log::warn!("slcan bridge: client lagged, {} frames dropped", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+/// State of the emulated adapter as seen by the host.
+struct Device {
+    driver: Arc<Mutex<Box<dyn CanDriver>>>,
+    open: bool,
+    listen_only: bool,
+    timestamps: bool,
+    started: Instant,
+}
+
+impl Device {
+    fn new(driver: Arc<Mutex<Box<dyn CanDriver>>>) -> Self {
+        Self {
+            driver,
+            open: false,
+            listen_only: false,
+            timestamps: false,
+            started: Instant::now(),
+        }
+    }
+
+    /// Execute one decoded host line and return the reply bytes.
+    async fn handle(&mut self, event: SlcanEvent) -> Vec<u8> {
+        match event {
+            SlcanEvent::Frame(frame) => self.transmit(&frame).await,
+            SlcanEvent::Line(line) => self.command(&line).await.to_vec(),
+            // Empty lines are used by hosts to flush the adapter's input.
+            SlcanEvent::Ack => ACK.to_vec(),
+            SlcanEvent::Nack => Vec::new(),
+        }
+    }
+
+    async fn transmit(&mut self, frame: &CanFrame) -> Vec<u8> {
+        if !self.open || self.listen_only {
+            return NACK.to_vec();
+        }
+        let result = self.driver.lock().await.send_frame(frame).await;
+        match result {
+            Ok(()) if frame.is_extended() => b"Z\r".to_vec(),
+            Ok(()) => b"z\r".to_vec(),
+            Err(e) => {
+                log::warn!("slcan bridge: send failed: {}", e);
+                NACK.to_vec()
+            }
+        }
+    }
+
+    async fn command(&mut self, line: &[u8]) -> &'static [u8] {
+        match line {
+            [b'O'] | [b'L'] if self.open => NACK,
+            [b'O'] => {
+                self.open = true;
+                self.listen_only = false;
+                ACK
+            }
+            [b'L'] => {
+                self.open = true;
+                self.listen_only = true;
+                ACK
+            }
+            [b'C'] => {
+                self.open = false;
+                ACK
+            }
+            [b'S' | b's' | b'Y', ..] if self.open => NACK,
+            [b'S', code] => match bitrate_for_code(*code) {
+                Some(bitrate) => {
+                    let current = self.driver.lock().await.get_bitrate().await;
+                    if current.is_some_and(|current| current != bitrate) {
+                        log::warn!(
+                            "slcan bridge: host asked for {} bit/s, channel runs at {:?}",
+                            bitrate,
+                            current
+                        );
+                    }
+                    ACK
+                }
+                None => NACK,
+            },
+            [b's', ..] | [b'Y', _] => ACK,
+            [b'Z', b'0'] => {
+                self.timestamps = false;
+                ACK
+            }
+            [b'Z', b'1'] => {
+                self.timestamps = true;
+                ACK
+            }
+            [b'V'] => VERSION_REPLY,
+            [b'N'] => SERIAL_REPLY,
+            // No error flags: the server reports bus errors as error frames.
+            [b'F'] => b"F00\r",
+            // Acceptance filters, auto-poll and polling are accepted but not emulated.
+            [b'M' | b'm' | b'X' | b'Q' | b'W', ..] | [b'P'] | [b'A'] => ACK,
+            _ => {
+                log::debug!(
+                    "slcan bridge: unsupported command {:?}",
+                    String::from_utf8_lossy(line)
+                );
+                NACK
+            }
+        }
+    }
+
+    /// Encode a frame from the bus for the host, if the channel is open.
+    fn deliver(&self, frame: &CanFrame) -> Option<String> {
+        if !self.open || frame.is_error() {
+            return None;
+        }
+        let mut line = encode_frame(frame, false).ok()?;
+        if self.timestamps {
+            // LAWICEL timestamps are milliseconds wrapping at one minute.
+            let ms = frame
+                .timestamp()
+                .map(|us| us / 1000)
+                .unwrap_or_else(|| self.started.elapsed().as_millis() as u64);
+            line.insert_str(line.len() - 1, &format!("{:04X}", ms % 60_000));
+        }
+        Some(line)
+    }
+}
+
+fn bitrate_for_code(code: u8) -> Option<u32> {
+    Some(match code {
+        b'0' => 10_000,
+        b'1' => 20_000,
+        b'2' => 50_000,
+        b'3' => 100_000,
+        b'4' => 125_000,
+        b'5' => 250_000,
+        b'6' => 500_000,
+        b'7' => 800_000,
+        b'8' => 1_000_000,
+        _ => return None,
+    })
+}
+
+/// A pseudo-terminal whose slave end behaves like an SLCAN adapter's serial port.
+#[cfg(unix)]
+pub struct Pty {
+    path: String,
+    master: std::fs::File,
+    /// Held open so the master does not see a hang-up between host sessions.
+    _slave: std::fs::File,
+}
+
+#[cfg(unix)]
+impl Pty {
+    /// Allocate a pseudo-terminal in raw mode.
+    pub fn open() -> std::io::Result<Self> {
+        use std::os::fd::FromRawFd;
+
+        // SAFETY: plain libc calls; every returned descriptor is checked and owned by a File.
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let master = std::fs::File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+            let path = std::ffi::CStr::from_ptr(name)
+                .to_string_lossy()
+                .into_owned();
+
+            let slave = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)?;
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            let slave_fd = std::os::fd::AsRawFd::as_raw_fd(&slave);
+            if libc::tcgetattr(slave_fd, &mut termios) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(slave_fd, libc::TCSANOW, &termios) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            Ok(Self {
+                path,
+                master,
+                _slave: slave,
+            })
+        }
+    }
+
+    /// Device path host tools should open, e.g. `/dev/pts/3`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Emulate an SLCAN adapter on the pseudo-terminal.
+    pub async fn serve(
+        self,
+        driver: Arc<Mutex<Box<dyn CanDriver>>>,
+        frames: broadcast::Sender<CanFrame>,
+    ) -> std::io::Result<()> {
+        // Separate handles so a pending read does not hold up writes.
+        let reader = tokio::fs::File::from_std(self.master.try_clone()?);
+        let writer = tokio::fs::File::from_std(self.master);
+        let _slave = self._slave;
+        serve(reader, writer, driver, frames.subscribe()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::VirtualDriver;
+    use std::time::Duration;
+    use tokio::io::DuplexStream;
+
+    struct Host {
+        stream: DuplexStream,
+        /// Another node on the bridged bus.
+        peer: VirtualDriver,
+        frames: broadcast::Sender<CanFrame>,
+    }
+
+    impl Host {
+        async fn connect(bus: &str) -> Self {
+            let mut driver = VirtualDriver::open(bus).await.unwrap();
+            driver.open_channel().await.unwrap();
+            let mut peer = VirtualDriver::open(bus).await.unwrap();
+            peer.open_channel().await.unwrap();
+            let driver: Box<dyn CanDriver> = Box::new(driver);
+
+            let (stream, device) = tokio::io::duplex(1024);
+            let (frames, rx) = broadcast::channel(16);
+            let (reader, writer) = tokio::io::split(device);
+            tokio::spawn(serve(reader, writer, Arc::new(Mutex::new(driver)), rx));
+            Self {
+                stream,
+                peer,
+                frames,
+            }
+        }
+
+        /// Send `request` and expect exactly `reply` back.
+        async fn exchange(&mut self, request: &[u8], reply: &[u8]) {
+            self.stream.write_all(request).await.unwrap();
+            self.expect(reply).await;
+        }
+
+        async fn expect(&mut self, reply: &[u8]) {
+            let mut received = vec![0u8; reply.len()];
+            tokio::time::timeout(
+                Duration::from_secs(1),
+                self.stream.read_exact(&mut received),
+            )
+            .await
+            .expect("no reply")
+            .unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&received),
+                String::from_utf8_lossy(reply)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn opens_listens_and_closes() {
+        let mut host = Host::connect("test-slcan-bridge-open").await;
+
+        host.exchange(b"O\r", b"\r").await;
+        host.exchange(b"O\r", b"\x07").await;
+        host.exchange(b"L\r", b"\x07").await;
+        host.exchange(b"C\r", b"\r").await;
+
+        host.exchange(b"L\r", b"\r").await;
+        // Listen-only refuses to transmit.
+        host.exchange(b"t1230\r", b"\x07").await;
+        host.exchange(b"C\r", b"\r").await;
+        host.exchange(b"V\rN\r", b"V1013\rNCSRV\r").await;
+    }
+
+    #[tokio::test]
+    async fn bitrate_can_only_be_set_while_closed() {
+        let mut host = Host::connect("test-slcan-bridge-bitrate").await;
+
+        host.exchange(b"S6\r", b"\r").await;
+        host.exchange(b"S9\r", b"\x07").await;
+        host.exchange(b"O\r", b"\r").await;
+        host.exchange(b"S6\r", b"\x07").await;
+        host.exchange(b"s011C\r", b"\x07").await;
+    }
+
+    #[tokio::test]
+    async fn transmits_frames_and_acknowledges_them() {
+        let mut host = Host::connect("test-slcan-bridge-transmit").await;
+
+        // Closed channels refuse to transmit.
+        host.exchange(b"t1230\r", b"\x07").await;
+        host.exchange(b"O\r", b"\r").await;
+        host.exchange(b"t1232AABB\r", b"z\r").await;
+        host.exchange(b"T123456781CC\r", b"Z\r").await;
+
+        let mut received = Vec::new();
+        while received.len() < 2 {
+            received.extend(host.peer.read_frames().await.unwrap());
+        }
+        assert_eq!(received[0].id(), 0x123);
+        assert_eq!(received[0].data(), [0xAA, 0xBB]);
+        assert!(received[1].is_extended());
+        assert_eq!(received[1].id(), 0x1234_5678);
+        assert_eq!(received[1].data(), [0xCC]);
+    }
+
+    #[tokio::test]
+    async fn malformed_lines_are_rejected_in_order() {
+        let mut host = Host::connect("test-slcan-bridge-malformed").await;
+
+        host.exchange(b"bad\rO\r", b"\x07\r").await;
+        host.exchange(b"C\rt12\rO\r", b"\r\x07\r").await;
+    }
+
+    #[tokio::test]
+    async fn delivers_frames_with_optional_timestamps() {
+        let mut host = Host::connect("test-slcan-bridge-deliver").await;
+
+        host.exchange(b"O\r", b"\r").await;
+
+        let mut frame = CanFrame::new(0x123, &[0xAA]).unwrap();
+        frame.set_timestamp(Some(61_234_567));
+        host.frames.send(frame.clone()).unwrap();
+        host.expect(b"t1231AA\r").await;
+
+        host.exchange(b"Z1\r", b"\r").await;
+        // Milliseconds, wrapping at one minute.
+        host.frames.send(frame).unwrap();
+        host.expect(b"t1231AA04D2\r").await;
+
+        host.exchange(b"Z0\r", b"\r").await;
+        host.frames
+            .send(CanFrame::new_eff(0x1234_5678, &[]).unwrap())
+            .unwrap();
+        host.expect(b"T123456780\r").await;
+    }
+}
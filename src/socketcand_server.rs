@@ -0,0 +1,443 @@
+/// socketcand compatible TCP server, exposing a channel to tools such as
+/// Kayak, SavvyCAN and python-can.
+///
+/// Clients open the bus by name and start in BCM mode, where they can
+/// transmit cyclically (`add`/`update`/`delete`) and subscribe to single
+/// identifiers (`subscribe`/`unsubscribe`). `rawmode` switches to receiving
+/// every frame; `send` works in both modes.
+use crosscan::can::CanFrame;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Mutex, broadcast};
+
+use crate::bcm::{CyclicTasks, FrameKey};
+use crate::drivers::socketcand::protocol::{
+    element, format_frame, next_element, parse_frame_words, parse_id, parse_interval, parse_send,
+};
+use crate::drivers::{CanDriver, timestamp_us};
+
+/// Accept socketcand clients on `listener` for the bus called `bus`.
+/// Frames received from the driver must be published on `frames`.
+pub async fn serve(
+    listener: TcpListener,
+    bus: String,
+    driver: Arc<Mutex<Box<dyn CanDriver>>>,
+    frames: broadcast::Sender<CanFrame>,
+) -> std::io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        log::info!("socketcand: client {} connected", peer);
+        let mut session = Session::new(bus.clone(), driver.clone(), frames.subscribe());
+        tokio::spawn(async move {
+            if let Err(e) = session.run(stream).await {
+                log::warn!("socketcand: client {}: {}", peer, e);
+            }
+            log::info!("socketcand: client {} disconnected", peer);
+        });
+    }
+}
+
+/// A BCM mode receive subscription; frames are forwarded at most once per `interval`.
+struct Subscription {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+struct Session {
+    bus: String,
+    driver: Arc<Mutex<Box<dyn CanDriver>>>,
+    rx: broadcast::Receiver<CanFrame>,
+    opened: bool,
+    raw: bool,
+    jobs: CyclicTasks,
+    subscriptions: HashMap<FrameKey, Subscription>,
+}
+
+impl Session {
+    fn new(
+        bus: String,
+        driver: Arc<Mutex<Box<dyn CanDriver>>>,
+        rx: broadcast::Receiver<CanFrame>,
+    ) -> Self {
+        Self {
+            bus,
+            jobs: CyclicTasks::new(driver.clone()),
+            driver,
+            rx,
+            opened: false,
+            raw: false,
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    async fn run(&mut self, stream: TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(true)?;
+        let (mut reader, mut writer) = stream.into_split();
+        writer.write_all(element("hi").as_bytes()).await?;
+
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            tokio::select! {
+                n = reader.read(&mut chunk) => {
+                    let n = n?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                    while let Some(words) = next_element(&mut buf) {
+                        if let Some(reply) = self.handle(&words).await {
+                            writer.write_all(reply.as_bytes()).await?;
+                        }
+                    }
+                }
+                received = self.rx.recv() => match received {
+                    Ok(frame) => {
+                        if let Some(message) = self.deliver(&frame) {
+                            writer.write_all(message.as_bytes()).await?;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log::warn!("socketcand: client lagged, {} frames dropped", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                },
+            }
+        }
+    }
+
+    /// Execute one command and return the reply, if any.
+    async fn handle(&mut self, words: &[String]) -> Option<String> {
+        let result = match words[0].as_str() {
+            "open" => self.open(words),
+            "echo" => return Some(element("echo")),
+            _ if !self.opened => Err("bus not opened".to_string()),
+            "rawmode" => {
+                self.raw = true;
+                Ok(true)
+            }
+            "bcmmode" => {
+                self.raw = false;
+                Ok(true)
+            }
+            "send" => match parse_send(words) {
+                Ok(frame) => self.send(&frame).await.map(|_| false),
+                Err(e) => Err(e.to_string()),
+            },
+            _ if self.raw => Err(format!("{} is not available in raw mode", words[0])),
+            "add" => self.add(words).map(|_| false),
+            "update" => self.update(words).map(|_| false),
+            "delete" => self.delete(words).map(|_| false),
+            "subscribe" => self.subscribe(words).map(|_| false),
+            "unsubscribe" => self.unsubscribe(words).map(|_| false),
+            other => Err(format!("unknown command {}", other)),
+        };
+        match result {
+            Ok(true) => Some(element("ok")),
+            Ok(false) => None,
+            Err(message) => {
+                log::debug!("socketcand: {:?}: {}", words, message);
+                Some(element(&format!("error {}", message)))
+            }
+        }
+    }
+
+    /// `< open <bus> >`
+    fn open(&mut self, words: &[String]) -> Result<bool, String> {
+        match words.get(1) {
+            Some(bus) if *bus == self.bus => {
+                self.opened = true;
+                Ok(true)
+            }
+            _ => Err("could not open bus".to_string()),
+        }
+    }
+
+    async fn send(&self, frame: &CanFrame) -> Result<(), String> {
+        self.driver
+            .lock()
+            .await
+            .send_frame(frame)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// `< add <sec> <usec> <id> <dlc> <data>... >`
+    fn add(&mut self, words: &[String]) -> Result<(), String> {
+        let [_, sec, usec, rest @ ..] = words else {
+            return Err("malformed add".to_string());
+        };
+        let interval = parse_interval(sec, usec).map_err(|e| e.to_string())?;
+        let frame = parse_frame_words(rest).map_err(|e| e.to_string())?;
+        // Without an interval the frame is sent once, as by the kernel BCM.
+        self.jobs.setup(frame, interval, None)
+    }
+
+    /// `< update <id> <dlc> <data>... >`
+    fn update(&mut self, words: &[String]) -> Result<(), String> {
+        let frame = parse_frame_words(&words[1..]).map_err(|e| e.to_string())?;
+        self.jobs.update(frame)
+    }
+
+    /// `< delete <id> >`
+    fn delete(&mut self, words: &[String]) -> Result<(), String> {
+        self.jobs.delete(key_from(words.get(1))?)
+    }
+
+    /// `< subscribe <sec> <usec> <id> >`
+    fn subscribe(&mut self, words: &[String]) -> Result<(), String> {
+        let [_, sec, usec, id] = words else {
+            return Err("malformed subscribe".to_string());
+        };
+        let interval = parse_interval(sec, usec).map_err(|e| e.to_string())?;
+        let key = key_from(Some(id))?;
+        self.subscriptions.insert(
+            key,
+            Subscription {
+                interval,
+                last: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// `< unsubscribe <id> >`
+    fn unsubscribe(&mut self, words: &[String]) -> Result<(), String> {
+        let key = key_from(words.get(1))?;
+        self.subscriptions
+            .remove(&key)
+            .map(|_| ())
+            .ok_or_else(|| "no such subscription".to_string())
+    }
+
+    /// Format `frame` for the client if its mode and subscriptions want it.
+    fn deliver(&mut self, frame: &CanFrame) -> Option<String> {
+        if !self.opened || frame.is_error() {
+            return None;
+        }
+        if !self.raw {
+            let subscription = self
+                .subscriptions
+                .get_mut(&(frame.id(), frame.is_extended()))?;
+            let now = Instant::now();
+            if subscription
+                .last
+                .is_some_and(|last| now.duration_since(last) < subscription.interval)
+            {
+                return None;
+            }
+            subscription.last = Some(now);
+        }
+        // Remote and CAN FD frames have no raw mode representation.
+        format_frame(frame, timestamp_us()).ok()
+    }
+}
+
+fn key_from(id: Option<&String>) -> Result<FrameKey, String> {
+    let id = id.ok_or_else(|| "missing CAN id".to_string())?;
+    parse_id(id).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::VirtualDriver;
+
+    /// A raw socketcand client connected to a server for `vcan0` on the
+    /// virtual bus `bus`, next to another node on that bus.
+    struct Client {
+        stream: TcpStream,
+        buf: Vec<u8>,
+        peer: VirtualDriver,
+        frames: broadcast::Sender<CanFrame>,
+    }
+
+    impl Client {
+        async fn connect(bus: &str) -> Self {
+            let mut driver = VirtualDriver::open(bus).await.unwrap();
+            driver.open_channel().await.unwrap();
+            let mut peer = VirtualDriver::open(bus).await.unwrap();
+            peer.open_channel().await.unwrap();
+            let driver: Box<dyn CanDriver> = Box::new(driver);
+
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let (frames, _) = broadcast::channel(16);
+            tokio::spawn(serve(
+                listener,
+                "vcan0".to_string(),
+                Arc::new(Mutex::new(driver)),
+                frames.clone(),
+            ));
+
+            let mut client = Self {
+                stream: TcpStream::connect(address).await.unwrap(),
+                buf: Vec::new(),
+                peer,
+                frames,
+            };
+            assert_eq!(client.next().await, ["hi"]);
+            client
+        }
+
+        async fn send(&mut self, words: &str) {
+            self.stream
+                .write_all(element(words).as_bytes())
+                .await
+                .unwrap();
+        }
+
+        async fn next(&mut self) -> Vec<String> {
+            let mut chunk = [0u8; 1024];
+            loop {
+                if let Some(words) = next_element(&mut self.buf) {
+                    return words;
+                }
+                let n = tokio::time::timeout(Duration::from_secs(1), self.stream.read(&mut chunk))
+                    .await
+                    .expect("no reply")
+                    .unwrap();
+                assert_ne!(n, 0, "server closed the connection");
+                self.buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+
+        /// Send `words` and return the reply.
+        async fn request(&mut self, words: &str) -> Vec<String> {
+            self.send(words).await;
+            self.next().await
+        }
+
+        /// Send a command that has no reply, and check it has none.
+        async fn command(&mut self, words: &str) {
+            self.send(words).await;
+            assert_eq!(self.request("echo").await, ["echo"], "{}", words);
+        }
+
+        async fn open(bus: &str) -> Self {
+            let mut client = Self::connect(bus).await;
+            assert_eq!(client.request("open vcan0").await, ["ok"]);
+            client
+        }
+
+        /// Frames the peer receives until `done` is satisfied.
+        async fn peer_frames(&mut self, done: impl Fn(&[CanFrame]) -> bool) -> Vec<CanFrame> {
+            let mut frames = Vec::new();
+            tokio::time::timeout(Duration::from_secs(1), async {
+                while !done(&frames) {
+                    frames.extend(self.peer.read_frames().await.unwrap());
+                }
+            })
+            .await
+            .expect("peer did not receive the frames");
+            frames
+        }
+    }
+
+    fn frame(id: u32, data: &[u8]) -> CanFrame {
+        let mut frame = CanFrame::new(id, data).unwrap();
+        frame.set_timestamp(Some(1_000_002));
+        frame
+    }
+
+    fn error(reply: &[String]) -> bool {
+        reply.first().is_some_and(|word| word == "error")
+    }
+
+    #[tokio::test]
+    async fn opens_only_the_served_bus() {
+        let mut client = Client::connect("test-socketcand-open").await;
+
+        assert!(error(&client.request("send 123 1 AA").await));
+        assert!(error(&client.request("open vcan1").await));
+        assert_eq!(client.request("open vcan0").await, ["ok"]);
+    }
+
+    #[tokio::test]
+    async fn sends_in_both_modes() {
+        let mut client = Client::open("test-socketcand-send").await;
+
+        client.command("send 123 2 AA BB").await;
+        assert_eq!(client.request("rawmode").await, ["ok"]);
+        client.command("send 12345678 1 CC").await;
+        assert!(error(&client.request("add 0 10000 123 1 AA").await));
+
+        let frames = client.peer_frames(|frames| frames.len() >= 2).await;
+        assert_eq!(frames[0].id(), 0x123);
+        assert_eq!(frames[0].data(), [0xAA, 0xBB]);
+        assert!(frames[1].is_extended());
+        assert_eq!(frames[1].id(), 0x1234_5678);
+    }
+
+    #[tokio::test]
+    async fn raw_mode_forwards_every_frame() {
+        let mut client = Client::open("test-socketcand-raw").await;
+
+        assert_eq!(client.request("rawmode").await, ["ok"]);
+        client.frames.send(frame(0x100, &[0xAA])).unwrap();
+        assert_eq!(client.next().await, ["frame", "100", "1.000002", "AA"]);
+        client.frames.send(frame(0x101, &[])).unwrap();
+        assert_eq!(client.next().await, ["frame", "101", "1.000002"]);
+
+        // Back in BCM mode only subscribed ids are forwarded.
+        assert_eq!(client.request("bcmmode").await, ["ok"]);
+        client.command("subscribe 0 0 200").await;
+        client.frames.send(frame(0x100, &[1])).unwrap();
+        client.frames.send(frame(0x200, &[2])).unwrap();
+        assert_eq!(client.next().await, ["frame", "200", "1.000002", "02"]);
+    }
+
+    #[tokio::test]
+    async fn subscriptions_are_throttled_to_their_interval() {
+        let mut client = Client::open("test-socketcand-subscribe").await;
+
+        client.command("subscribe 0 100000 200").await;
+        client.command("subscribe 0 0 201").await;
+        client.frames.send(frame(0x200, &[1])).unwrap();
+        client.frames.send(frame(0x200, &[2])).unwrap();
+        client.frames.send(frame(0x201, &[3])).unwrap();
+        assert_eq!(client.next().await, ["frame", "200", "1.000002", "01"]);
+        // The second 0x200 came within the interval.
+        assert_eq!(client.next().await, ["frame", "201", "1.000002", "03"]);
+
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        client.frames.send(frame(0x200, &[4])).unwrap();
+        assert_eq!(client.next().await, ["frame", "200", "1.000002", "04"]);
+
+        client.command("unsubscribe 200").await;
+        assert!(error(&client.request("unsubscribe 200").await));
+        client.frames.send(frame(0x200, &[5])).unwrap();
+        client.frames.send(frame(0x201, &[6])).unwrap();
+        assert_eq!(client.next().await, ["frame", "201", "1.000002", "06"]);
+    }
+
+    #[tokio::test]
+    async fn cyclic_frames_are_added_updated_and_deleted() {
+        let mut client = Client::open("test-socketcand-cyclic").await;
+
+        assert!(error(&client.request("add 0 500 123 1 AA").await));
+        client.command("add 0 0 124 1 CC").await;
+        client.command("add 0 2000 123 1 AA").await;
+        client
+            .peer_frames(|frames| frames.iter().any(|f| f.id() == 0x123))
+            .await;
+
+        client.command("update 123 1 BB").await;
+        client
+            .peer_frames(|frames| frames.iter().any(|f| f.data() == [0xBB]))
+            .await;
+
+        client.command("delete 123").await;
+        assert!(error(&client.request("delete 123").await));
+        assert!(error(&client.request("update 123 1 DD").await));
+        // Frames still queued at the peer, then silence.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        while !client.peer.read_frames().await.unwrap().is_empty() {}
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let late = client.peer.read_frames().await.unwrap();
+        assert!(late.is_empty(), "{} frames after delete", late.len());
+    }
+}
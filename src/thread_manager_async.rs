@@ -1,19 +1,238 @@
+use crosscan::can::CanFrame;
 use serde::{Deserialize, Serialize};
 use std::io::ErrorKind;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+#[cfg(windows)]
+use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient};
+#[cfg(windows)]
 use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
 use tokio::sync::mpsc::{Receiver, Sender};
 
+/// Path of the IPC endpoint of a channel, e.g. `\\.\pipe\can_can0_in` on
+/// Windows or `$XDG_RUNTIME_DIR/can_can0_in` (a Unix domain socket, in
+/// `/tmp` without a runtime directory) elsewhere.
+pub fn pipe_path(channel_name: &str, suffix: &str) -> String {
+    #[cfg(windows)]
+    {
+        format!(r"\\.\pipe\can_{}_{}", channel_name, suffix)
+    }
+    #[cfg(not(windows))]
+    {
+        let dir = std::env::var("XDG_RUNTIME_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .unwrap_or_else(|| "/tmp".to_string());
+        format!("{}/can_{}_{}", dir, channel_name, suffix)
+    }
+}
+
+/// Bind a Unix domain socket. A socket file left behind by a listener that
+/// is gone is replaced, but one that still accepts connections is not taken
+/// over.
+#[cfg(not(windows))]
+fn bind_unix(path: &str) -> std::io::Result<tokio::net::UnixListener> {
+    match tokio::net::UnixListener::bind(path) {
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(std::io::Error::new(
+                    ErrorKind::AddrInUse,
+                    format!("{} is in use by another server", path),
+                ));
+            }
+            std::fs::remove_file(path)?;
+            tokio::net::UnixListener::bind(path)
+        }
+        result => result,
+    }
+}
+
 /// Blocking helper: create a [`NamedPipeServer`] and wait for a client
+#[cfg(windows)]
 async fn create_server_and_wait(pipe_name: &str) -> std::io::Result<NamedPipeServer> {
     let server = ServerOptions::new().create(pipe_name)?;
     server.connect().await?;
     Ok(server)
 }
 
+/// Blocking helper: listen on a Unix domain socket and wait for a client
+#[cfg(not(windows))]
+async fn create_server_and_wait(pipe_name: &str) -> std::io::Result<tokio::net::UnixStream> {
+    let listener = bind_unix(pipe_name)?;
+    let (stream, _) = listener.accept().await?;
+    Ok(stream)
+}
+
+/// Accepts any number of concurrent clients on an IPC endpoint, unlike the
+/// single client frame pipes.
+#[cfg(windows)]
+pub struct IpcListener {
+    pipe_name: String,
+    next: NamedPipeServer,
+}
+
+#[cfg(windows)]
+impl IpcListener {
+    pub fn bind(pipe_name: &str) -> std::io::Result<Self> {
+        let next = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(pipe_name)?;
+        Ok(Self {
+            pipe_name: pipe_name.to_string(),
+            next,
+        })
+    }
+
+    pub async fn accept(&mut self) -> std::io::Result<NamedPipeServer> {
+        self.next.connect().await?;
+        // Create the next instance before handing this one out, so a client
+        // never finds the pipe missing.
+        let next = ServerOptions::new().create(&self.pipe_name)?;
+        Ok(std::mem::replace(&mut self.next, next))
+    }
+}
+
+/// Accepts any number of concurrent clients on an IPC endpoint, unlike the
+/// single client frame pipes.
+#[cfg(not(windows))]
+pub struct IpcListener {
+    listener: tokio::net::UnixListener,
+}
+
+#[cfg(not(windows))]
+impl IpcListener {
+    pub fn bind(pipe_name: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            listener: bind_unix(pipe_name)?,
+        })
+    }
+
+    pub async fn accept(&mut self) -> std::io::Result<tokio::net::UnixStream> {
+        let (stream, _) = self.listener.accept().await?;
+        Ok(stream)
+    }
+}
+
+/// Client end of a connection to an [`IpcListener`].
+#[cfg(windows)]
+pub type IpcClientStream = NamedPipeClient;
+#[cfg(not(windows))]
+pub type IpcClientStream = tokio::net::UnixStream;
+
+/// Connect to the IPC endpoint `suffix` of a channel as a client.
+pub async fn connect_ipc(channel_name: &str, suffix: &str) -> std::io::Result<IpcClientStream> {
+    let path = pipe_path(channel_name, suffix);
+    #[cfg(windows)]
+    {
+        ClientOptions::new().open(path)
+    }
+    #[cfg(not(windows))]
+    {
+        tokio::net::UnixStream::connect(path).await
+    }
+}
+
+/// Encode a frame for the `out` endpoint: a length byte followed by the
+/// bincode encoded frame.
+pub fn encode_frame_message(frame: &CanFrame) -> std::io::Result<Vec<u8>> {
+    let data = bincode::serde::encode_to_vec(frame, bincode::config::standard())
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+    if data.len() > (u8::MAX as usize) {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Serialized CanFrame too large: {}", data.len()),
+        ));
+    }
+    let mut msg = Vec::with_capacity(data.len() + 1);
+    msg.push(data.len() as u8);
+    msg.extend(data);
+    Ok(msg)
+}
+
+/// Take the frames written to the `in` endpoint out of `pending`. Frames are
+/// sent back to back without framing, and a read may end in the middle of
+/// one; the incomplete rest stays in `pending` for the next read. Undecodable
+/// data is dropped.
+pub fn decode_frames(pending: &mut Vec<u8>) -> Vec<CanFrame> {
+    let mut frames = Vec::new();
+    let mut used = 0;
+    while used < pending.len() {
+        match bincode::serde::decode_from_slice::<CanFrame, _>(
+            &pending[used..],
+            bincode::config::standard(),
+        ) {
+            Ok((frame, len)) => {
+                frames.push(frame);
+                used += len;
+            }
+            Err(bincode::error::DecodeError::UnexpectedEnd { .. }) => break,
+            Err(e) => {
+                eprintln!("Dropping undecodable frame data: {}", e);
+                used = pending.len();
+            }
+        }
+    }
+    pending.drain(..used);
+    frames
+}
+
+/// Client of the frame endpoints of a channel's server: reads come from the
+/// `out` endpoint and writes go to the `in` endpoint.
+pub struct FrameClient {
+    reader: Option<BufReader<IpcClientStream>>,
+    writer: Option<IpcClientStream>,
+}
+
+impl FrameClient {
+    /// Receive the frames of the server for `channel_name`.
+    pub async fn open_read_only(channel_name: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            reader: Some(BufReader::new(connect_ipc(channel_name, "out").await?)),
+            writer: None,
+        })
+    }
+
+    /// Send frames through the server for `channel_name`.
+    pub async fn open_write_only(channel_name: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            reader: None,
+            writer: Some(connect_ipc(channel_name, "in").await?),
+        })
+    }
+
+    pub async fn open(channel_name: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            reader: Some(BufReader::new(connect_ipc(channel_name, "out").await?)),
+            writer: Some(connect_ipc(channel_name, "in").await?),
+        })
+    }
+
+    pub async fn read_frame(&mut self) -> std::io::Result<CanFrame> {
+        let reader = self.reader.as_mut().ok_or_else(|| {
+            std::io::Error::new(ErrorKind::Unsupported, "Frame client is write-only")
+        })?;
+        let len = reader.read_u8().await?;
+        let mut data = vec![0u8; len as usize];
+        reader.read_exact(&mut data).await?;
+        let (frame, _) =
+            bincode::serde::decode_from_slice::<CanFrame, _>(&data, bincode::config::standard())
+                .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        Ok(frame)
+    }
+
+    pub async fn write_frame(&mut self, frame: &CanFrame) -> std::io::Result<()> {
+        let writer = self.writer.as_mut().ok_or_else(|| {
+            std::io::Error::new(ErrorKind::Unsupported, "Frame client is read-only")
+        })?;
+        let data = bincode::serde::encode_to_vec(frame, bincode::config::standard())
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        writer.write_all(&data).await?;
+        writer.flush().await
+    }
+}
+
 /// Start the IPC reader
 pub async fn start_ipc_reader(channel_name: String, tx: Sender<Vec<u8>>) -> std::io::Result<()> {
-    let pipe_name = format!(r"\\.\pipe\can_{}_in", channel_name);
+    let pipe_name = pipe_path(&channel_name, "in");
 
     loop {
         let server = create_server_and_wait(&pipe_name).await?;
@@ -40,7 +259,7 @@ pub async fn start_ipc_writer(
     channel_name: String,
     mut rx: Receiver<Vec<u8>>,
 ) -> std::io::Result<()> {
-    let pipe_name = format!(r"\\.\pipe\can_{}_out", channel_name);
+    let pipe_name = pipe_path(&channel_name, "out");
 
     let mut server = create_server_and_wait(&pipe_name).await?;
     println!("Client connected to IPC Writer");
@@ -72,6 +291,8 @@ pub async fn start_ipc_writer(
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CanServerConfig {
     pub bitrate: Option<u32>,
+    #[serde(default)]
+    pub data_bitrate: Option<u32>,
     pub version: String,
 }
 
@@ -79,7 +300,7 @@ pub async fn start_ipc_config_handler(
     channel_name: String,
     config: CanServerConfig,
 ) -> std::io::Result<()> {
-    let pipe_name = format!(r"\\.\pipe\can_{}_config_out", channel_name);
+    let pipe_name = pipe_path(&channel_name, "config_out");
     loop {
         let mut server = create_server_and_wait(&pipe_name).await?;
 
@@ -97,3 +318,133 @@ pub async fn start_ipc_config_handler(
         server.shutdown().await?;
     }
 }
+
+/// Read the configuration the server for `channel_name` advertises.
+pub async fn read_server_config(channel_name: &str) -> std::io::Result<CanServerConfig> {
+    let mut client = connect_ipc(channel_name, "config_out").await?;
+    let mut data = Vec::new();
+    client.read_to_end(&mut data).await?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn frames() -> Vec<CanFrame> {
+        vec![
+            CanFrame::new(0x123, &[1, 2, 3]).unwrap(),
+            CanFrame::new_eff(0x1ABCDEF, &[]).unwrap(),
+            CanFrame::new(0x7FF, &[0xAA; 8]).unwrap(),
+        ]
+    }
+
+    fn describe(frames: &[CanFrame]) -> Vec<(u32, bool, Vec<u8>)> {
+        frames
+            .iter()
+            .map(|f| (f.id(), f.is_extended(), f.data().to_vec()))
+            .collect()
+    }
+
+    fn encode(frame: &CanFrame) -> Vec<u8> {
+        bincode::serde::encode_to_vec(frame, bincode::config::standard()).unwrap()
+    }
+
+    #[test]
+    fn decodes_back_to_back_frames_split_anywhere() {
+        let bytes: Vec<u8> = frames().iter().flat_map(encode).collect();
+        for split in 0..=bytes.len() {
+            let mut pending = bytes[..split].to_vec();
+            let mut decoded = decode_frames(&mut pending);
+            pending.extend_from_slice(&bytes[split..]);
+            decoded.extend(decode_frames(&mut pending));
+            assert_eq!(
+                describe(&decoded),
+                describe(&frames()),
+                "split at {}",
+                split
+            );
+            assert!(pending.is_empty());
+        }
+    }
+
+    #[test]
+    fn prefixes_out_messages_with_their_length() {
+        let frame = &frames()[0];
+        let msg = encode_frame_message(frame).unwrap();
+        assert_eq!(msg[0] as usize, msg.len() - 1);
+        assert_eq!(&msg[1..], encode(frame));
+    }
+
+    #[tokio::test]
+    async fn frame_client_talks_to_the_server_endpoints() {
+        let channel = format!("test{}", std::process::id());
+        let (tx_in, mut rx_in) = tokio::sync::mpsc::channel(16);
+        let (tx_out, rx_out) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(start_ipc_reader(channel.clone(), tx_in));
+        tokio::spawn(start_ipc_writer(channel.clone(), rx_out));
+
+        let mut client = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match FrameClient::open(&channel).await {
+                    Ok(client) => return client,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        })
+        .await
+        .expect("server endpoints come up");
+
+        for frame in frames() {
+            client.write_frame(&frame).await.unwrap();
+        }
+        let mut pending = Vec::new();
+        let mut received = Vec::new();
+        while received.len() < frames().len() {
+            pending.extend(rx_in.recv().await.unwrap());
+            received.extend(decode_frames(&mut pending));
+        }
+        assert_eq!(describe(&received), describe(&frames()));
+
+        for frame in frames() {
+            tx_out
+                .send(encode_frame_message(&frame).unwrap())
+                .await
+                .unwrap();
+        }
+        let mut read = Vec::new();
+        for _ in frames() {
+            read.push(client.read_frame().await.unwrap());
+        }
+        assert_eq!(describe(&read), describe(&frames()));
+
+        #[cfg(not(windows))]
+        for suffix in ["in", "out"] {
+            let _ = std::fs::remove_file(pipe_path(&channel, suffix));
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test]
+    async fn rebinds_stale_sockets_but_not_live_ones() {
+        let path = std::env::temp_dir()
+            .join(format!("can_test_bind_{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        let listener = IpcListener::bind(&path).unwrap();
+        let error = IpcListener::bind(&path).err().expect("live socket rebound");
+        assert_eq!(error.kind(), ErrorKind::AddrInUse);
+
+        // Dropping a listener leaves its socket file behind.
+        drop(listener);
+        assert!(std::path::Path::new(&path).exists());
+        let mut listener = IpcListener::bind(&path).unwrap();
+        let client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        listener.accept().await.unwrap();
+        drop(client);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
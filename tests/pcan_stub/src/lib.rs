@@ -0,0 +1,598 @@
+//! Stand-in for PCAN-Basic (`PCANBasic.dll` / `libpcanbasic.so`) exporting the
+//! entry points the PCAN driver loads, with the same ABI.
+//!
+//! Channels `USBBUS1`..`USBBUS16` are wired up in pairs, `USBBUS1` with
+//! `USBBUS2`, `USBBUS3` with `USBBUS4` and so on: a frame written on one
+//! initialized channel is queued on its partner if that is initialized.
+//! Tests running in parallel each take their own pair. The
+//! parameters behave like a PCAN-USB FD with the values below, and
+//! `PCAN_ATTACHED_CHANNELS` reports `USBBUS1` (available, FD capable) and
+//! `USBBUS2` (occupied). `PCAN_RECEIVE_EVENT` is a pipe on Unix that is
+//! readable while the receive queue is non-empty, and on Windows an event
+//! that is set whenever a message is queued.
+//!
+//! The crate is also built as an rlib so tests can find the shared library
+//! with [`library_path`].
+#![allow(clippy::missing_safety_doc)]
+
+use std::collections::{HashMap, VecDeque};
+use std::ffi::c_void;
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+pub const PCAN_NONEBUS: u16 = 0x00;
+pub const PCAN_USBBUS1: u16 = 0x51;
+pub const PCAN_USBBUS2: u16 = 0x52;
+
+fn is_usb_channel(handle: u16) -> bool {
+    (0x51..=0x58).contains(&handle) || (0x509..=0x510).contains(&handle)
+}
+
+/// The other channel on the bus of `USBBUS<n>`: `USBBUS<n+1>` for odd `n`,
+/// `USBBUS<n-1>` for even `n`.
+fn partner(handle: u16) -> u16 {
+    let index = match handle {
+        0x51..=0x58 => handle - 0x51,
+        _ => handle - 0x509 + 8,
+    } ^ 1;
+    if index < 8 { 0x51 + index } else { 0x509 + index - 8 }
+}
+
+pub const PCAN_ERROR_OK: u32 = 0x00000;
+pub const PCAN_ERROR_QRCVEMPTY: u32 = 0x00020;
+pub const PCAN_ERROR_ILLHW: u32 = 0x01400;
+pub const PCAN_ERROR_ILLPARAMTYPE: u32 = 0x04000;
+pub const PCAN_ERROR_ILLPARAMVAL: u32 = 0x08000;
+pub const PCAN_ERROR_INITIALIZE: u32 = 0x4000000;
+pub const PCAN_ERROR_ILLOPERATION: u32 = 0x8000000;
+
+const PCAN_DEVICE_ID: u8 = 0x01;
+const PCAN_RECEIVE_EVENT: u8 = 0x03;
+const PCAN_API_VERSION: u8 = 0x05;
+const PCAN_CHANNEL_VERSION: u8 = 0x06;
+const PCAN_BUSOFF_AUTORESET: u8 = 0x07;
+const PCAN_LISTEN_ONLY: u8 = 0x08;
+const PCAN_HARDWARE_NAME: u8 = 0x0E;
+const PCAN_ALLOW_ERROR_FRAMES: u8 = 0x20;
+const PCAN_FIRMWARE_VERSION: u8 = 0x29;
+const PCAN_ATTACHED_CHANNELS_COUNT: u8 = 0x2A;
+const PCAN_ATTACHED_CHANNELS: u8 = 0x2B;
+
+pub const API_VERSION: &str = "4.9.0.942";
+pub const HARDWARE_NAME: &str = "PCAN-USB FD";
+pub const CHANNEL_VERSION: &str = "PCAN-Basic stub";
+pub const FIRMWARE_VERSION: &str = "3.2.0";
+/// `PCAN_DEVICE_ID` of every channel until it is set.
+pub const DEVICE_ID: u32 = 0x2A;
+
+const PCAN_MESSAGE_FD: u8 = 0x04;
+
+/// `TPCANMsg`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Msg {
+    pub id: u32,
+    pub msgtype: u8,
+    pub len: u8,
+    pub data: [u8; 8],
+}
+
+/// `TPCANTimestamp`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Timestamp {
+    pub millis: u32,
+    pub millis_overflow: u16,
+    pub micros: u16,
+}
+
+/// `TPCANMsgFD`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MsgFd {
+    pub id: u32,
+    pub msgtype: u8,
+    pub dlc: u8,
+    pub data: [u8; 64],
+}
+
+/// `TPCANChannelInformation`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ChannelInformation {
+    pub channel_handle: u16,
+    pub device_type: u8,
+    pub controller_number: u8,
+    pub device_features: u32,
+    pub device_name: [c_char; 33],
+    pub device_id: u32,
+    pub channel_condition: u32,
+}
+
+#[derive(Default)]
+struct Channel {
+    initialized: bool,
+    fd: bool,
+    params: HashMap<u8, u32>,
+    queue: VecDeque<(MsgFd, u64)>,
+    event: Option<event::Event>,
+}
+
+impl Channel {
+    fn push(&mut self, msg: MsgFd, micros: u64) {
+        self.queue.push_back((msg, micros));
+        if let Some(event) = &self.event {
+            event.signal();
+        }
+    }
+
+    fn pop(&mut self) -> Option<(MsgFd, u64)> {
+        let next = self.queue.pop_front();
+        if self.queue.is_empty()
+            && let Some(event) = &self.event
+        {
+            event.clear();
+        }
+        next
+    }
+}
+
+#[cfg(unix)]
+mod event {
+    use std::os::raw::c_int;
+
+    /// A pipe whose read end is readable while the queue holds messages.
+    pub(crate) struct Event {
+        pub(crate) read: c_int,
+        write: c_int,
+    }
+
+    impl Event {
+        pub(crate) fn new() -> Option<Self> {
+            let mut fds = [0 as c_int; 2];
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+                return None;
+            }
+            for fd in fds {
+                unsafe { libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK) };
+            }
+            Some(Self {
+                read: fds[0],
+                write: fds[1],
+            })
+        }
+
+        pub(crate) fn signal(&self) {
+            unsafe { libc::write(self.write, [1u8].as_ptr() as *const _, 1) };
+        }
+
+        pub(crate) fn clear(&self) {
+            let mut buf = [0u8; 64];
+            while unsafe { libc::read(self.read, buf.as_mut_ptr() as *mut _, buf.len()) } > 0 {}
+        }
+    }
+
+    impl Drop for Event {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.read);
+                libc::close(self.write);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod event {
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn SetEvent(handle: isize) -> i32;
+    }
+
+    /// An event handle handed over by the application.
+    pub(crate) struct Event {
+        pub(crate) handle: isize,
+    }
+
+    impl Event {
+        pub(crate) fn signal(&self) {
+            unsafe { SetEvent(self.handle) };
+        }
+
+        pub(crate) fn clear(&self) {}
+    }
+}
+
+struct Bus {
+    started: Instant,
+    channels: HashMap<u16, Channel>,
+}
+
+static BUS: Mutex<Option<Bus>> = Mutex::new(None);
+
+fn bus() -> MutexGuard<'static, Option<Bus>> {
+    let mut bus = BUS.lock().unwrap_or_else(|e| e.into_inner());
+    bus.get_or_insert_with(|| Bus {
+        started: Instant::now(),
+        channels: HashMap::new(),
+    });
+    bus
+}
+
+fn with_channel(handle: u16, f: impl FnOnce(&mut Channel) -> u32) -> u32 {
+    if !is_usb_channel(handle) {
+        return PCAN_ERROR_ILLHW;
+    }
+    let mut bus = bus();
+    let bus = bus.as_mut().unwrap();
+    f(bus.channels.entry(handle).or_default())
+}
+
+/// Locate the shared library built next to the running test executable
+/// (`target/<profile>/deps`).
+pub fn library_path() -> PathBuf {
+    let exe = std::env::current_exe().expect("current executable");
+    let mut dirs = vec![exe.parent().unwrap().to_path_buf()];
+    if let Some(parent) = dirs[0].parent() {
+        dirs.push(parent.to_path_buf());
+    }
+    let prefix = format!("{}pcan_stub", std::env::consts::DLL_PREFIX);
+    for dir in &dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(&prefix) && name.ends_with(std::env::consts::DLL_SUFFIX) {
+                return entry.path();
+            }
+        }
+    }
+    panic!(
+        "pcan_stub shared library not found next to {}",
+        exe.display()
+    );
+}
+
+fn initialize(handle: u16, fd: bool) -> u32 {
+    with_channel(handle, |channel| {
+        if channel.initialized {
+            return PCAN_ERROR_ILLOPERATION;
+        }
+        channel.initialized = true;
+        channel.fd = fd;
+        channel.queue.clear();
+        #[cfg(unix)]
+        {
+            channel.event = event::Event::new();
+        }
+        PCAN_ERROR_OK
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn CAN_Initialize(
+    channel: u16,
+    _btr0btr1: u16,
+    _hw_type: u8,
+    _io_port: u32,
+    _interrupt: u16,
+) -> u32 {
+    initialize(channel, false)
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn CAN_InitializeFD(channel: u16, bitrate_fd: *const c_char) -> u32 {
+    if bitrate_fd.is_null() {
+        return PCAN_ERROR_ILLPARAMVAL;
+    }
+    initialize(channel, true)
+}
+
+#[unsafe(no_mangle)]
+pub extern "system" fn CAN_Uninitialize(channel: u16) -> u32 {
+    with_channel(channel, |channel| {
+        if !channel.initialized {
+            return PCAN_ERROR_INITIALIZE;
+        }
+        channel.initialized = false;
+        channel.queue.clear();
+        channel.event = None;
+        PCAN_ERROR_OK
+    })
+}
+
+/// Queue `msg` on the partner of `from`, if it is initialized.
+fn transmit(from: u16, msg: MsgFd) -> u32 {
+    if !is_usb_channel(from) {
+        return PCAN_ERROR_ILLHW;
+    }
+    let mut bus = bus();
+    let bus = bus.as_mut().unwrap();
+    if !bus.channels.get(&from).is_some_and(|c| c.initialized) {
+        return PCAN_ERROR_INITIALIZE;
+    }
+    let micros = bus.started.elapsed().as_micros() as u64;
+    if let Some(channel) = bus.channels.get_mut(&partner(from))
+        && channel.initialized
+    {
+        channel.push(msg, micros);
+    }
+    PCAN_ERROR_OK
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn CAN_Write(channel: u16, msg: *mut Msg) -> u32 {
+    let Some(msg) = (unsafe { msg.as_ref() }) else {
+        return PCAN_ERROR_ILLPARAMVAL;
+    };
+    let mut fd = MsgFd {
+        id: msg.id,
+        msgtype: msg.msgtype,
+        dlc: msg.len.min(8),
+        data: [0; 64],
+    };
+    fd.data[..8].copy_from_slice(&msg.data);
+    transmit(channel, fd)
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn CAN_WriteFD(channel: u16, msg: *mut MsgFd) -> u32 {
+    match unsafe { msg.as_ref() } {
+        Some(msg) => transmit(channel, *msg),
+        None => PCAN_ERROR_ILLPARAMVAL,
+    }
+}
+
+/// Take the next queued message a channel in its mode can receive.
+fn receive(handle: u16, fd: bool) -> Result<(MsgFd, u64), u32> {
+    let mut result = Err(PCAN_ERROR_QRCVEMPTY);
+    let status = with_channel(handle, |channel| {
+        if !channel.initialized || channel.fd != fd {
+            return PCAN_ERROR_INITIALIZE;
+        }
+        while let Some((msg, micros)) = channel.pop() {
+            // Classic channels do not see CAN FD frames.
+            if fd || msg.msgtype & PCAN_MESSAGE_FD == 0 {
+                result = Ok((msg, micros));
+                break;
+            }
+        }
+        PCAN_ERROR_OK
+    });
+    if status != PCAN_ERROR_OK {
+        return Err(status);
+    }
+    result
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn CAN_Read(
+    channel: u16,
+    msg: *mut Msg,
+    timestamp: *mut Timestamp,
+) -> u32 {
+    if msg.is_null() {
+        return PCAN_ERROR_ILLPARAMVAL;
+    }
+    match receive(channel, false) {
+        Ok((fd, micros)) => {
+            let mut data = [0u8; 8];
+            data.copy_from_slice(&fd.data[..8]);
+            unsafe {
+                *msg = Msg {
+                    id: fd.id,
+                    msgtype: fd.msgtype,
+                    len: fd.dlc,
+                    data,
+                };
+                if !timestamp.is_null() {
+                    let millis = micros / 1000;
+                    *timestamp = Timestamp {
+                        millis: millis as u32,
+                        millis_overflow: (millis >> 32) as u16,
+                        micros: (micros % 1000) as u16,
+                    };
+                }
+            }
+            PCAN_ERROR_OK
+        }
+        Err(status) => status,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn CAN_ReadFD(
+    channel: u16,
+    msg: *mut MsgFd,
+    timestamp: *mut u64,
+) -> u32 {
+    if msg.is_null() {
+        return PCAN_ERROR_ILLPARAMVAL;
+    }
+    match receive(channel, true) {
+        Ok((fd, micros)) => {
+            unsafe {
+                *msg = fd;
+                if !timestamp.is_null() {
+                    *timestamp = micros;
+                }
+            }
+            PCAN_ERROR_OK
+        }
+        Err(status) => status,
+    }
+}
+
+/// Copy `value` into the caller's buffer, failing if it does not fit.
+unsafe fn write_bytes(buffer: *mut c_void, len: u32, value: &[u8]) -> u32 {
+    if buffer.is_null() || (len as usize) < value.len() {
+        return PCAN_ERROR_ILLPARAMVAL;
+    }
+    unsafe { std::ptr::copy_nonoverlapping(value.as_ptr(), buffer as *mut u8, value.len()) };
+    PCAN_ERROR_OK
+}
+
+unsafe fn write_string(buffer: *mut c_void, len: u32, value: &str) -> u32 {
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.push(0);
+    unsafe { write_bytes(buffer, len, &bytes) }
+}
+
+fn attached_channels() -> Vec<ChannelInformation> {
+    let mut device_name = [0 as c_char; 33];
+    for (i, &b) in HARDWARE_NAME.as_bytes().iter().enumerate() {
+        device_name[i] = b as c_char;
+    }
+    vec![
+        ChannelInformation {
+            channel_handle: PCAN_USBBUS1,
+            device_type: 0x05,
+            controller_number: 0,
+            device_features: 0x01,
+            device_name,
+            device_id: DEVICE_ID,
+            channel_condition: 0x01,
+        },
+        ChannelInformation {
+            channel_handle: PCAN_USBBUS2,
+            device_type: 0x05,
+            controller_number: 1,
+            device_features: 0x00,
+            device_name,
+            device_id: DEVICE_ID + 1,
+            channel_condition: 0x02,
+        },
+    ]
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn CAN_GetValue(
+    channel: u16,
+    parameter: u8,
+    buffer: *mut c_void,
+    len: u32,
+) -> u32 {
+    if channel == PCAN_NONEBUS {
+        return match parameter {
+            PCAN_API_VERSION => unsafe { write_string(buffer, len, API_VERSION) },
+            PCAN_ATTACHED_CHANNELS_COUNT => {
+                let count = attached_channels().len() as u32;
+                unsafe { write_bytes(buffer, len, &count.to_ne_bytes()) }
+            }
+            PCAN_ATTACHED_CHANNELS => {
+                let channels = attached_channels();
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(
+                        channels.as_ptr() as *const u8,
+                        std::mem::size_of_val(channels.as_slice()),
+                    )
+                };
+                unsafe { write_bytes(buffer, len, bytes) }
+            }
+            _ => PCAN_ERROR_ILLPARAMTYPE,
+        };
+    }
+
+    let mut value = None;
+    let status = with_channel(channel, |channel| {
+        #[cfg(unix)]
+        if parameter == PCAN_RECEIVE_EVENT {
+            value = match &channel.event {
+                Some(event) if channel.initialized => Some(event.read as u32),
+                _ => return PCAN_ERROR_INITIALIZE,
+            };
+            return PCAN_ERROR_OK;
+        }
+        value = match parameter {
+            PCAN_DEVICE_ID | PCAN_BUSOFF_AUTORESET | PCAN_LISTEN_ONLY | PCAN_ALLOW_ERROR_FRAMES => {
+                let default = if parameter == PCAN_DEVICE_ID {
+                    DEVICE_ID
+                } else {
+                    0
+                };
+                Some(channel.params.get(&parameter).copied().unwrap_or(default))
+            }
+            _ => None,
+        };
+        PCAN_ERROR_OK
+    });
+    if status != PCAN_ERROR_OK {
+        return status;
+    }
+
+    match (parameter, value) {
+        (_, Some(value)) => unsafe { write_bytes(buffer, len, &value.to_ne_bytes()) },
+        (PCAN_HARDWARE_NAME, _) => unsafe { write_string(buffer, len, HARDWARE_NAME) },
+        (PCAN_CHANNEL_VERSION, _) => unsafe { write_string(buffer, len, CHANNEL_VERSION) },
+        (PCAN_FIRMWARE_VERSION, _) => unsafe { write_string(buffer, len, FIRMWARE_VERSION) },
+        _ => PCAN_ERROR_ILLPARAMTYPE,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn CAN_SetValue(
+    channel: u16,
+    parameter: u8,
+    buffer: *mut c_void,
+    len: u32,
+) -> u32 {
+    #[cfg(windows)]
+    if parameter == PCAN_RECEIVE_EVENT {
+        if buffer.is_null() || (len as usize) < std::mem::size_of::<isize>() {
+            return PCAN_ERROR_ILLPARAMVAL;
+        }
+        let handle = unsafe { (buffer as *const isize).read_unaligned() };
+        return with_channel(channel, |channel| {
+            if !channel.initialized {
+                return PCAN_ERROR_INITIALIZE;
+            }
+            channel.event = (handle != 0).then_some(event::Event { handle });
+            PCAN_ERROR_OK
+        });
+    }
+    if buffer.is_null() || len < 4 {
+        return PCAN_ERROR_ILLPARAMVAL;
+    }
+    let value = unsafe { (buffer as *const u32).read_unaligned() };
+    with_channel(channel, |channel| match parameter {
+        PCAN_DEVICE_ID => {
+            channel.params.insert(parameter, value);
+            PCAN_ERROR_OK
+        }
+        PCAN_BUSOFF_AUTORESET | PCAN_LISTEN_ONLY | PCAN_ALLOW_ERROR_FRAMES => {
+            if value > 1 {
+                return PCAN_ERROR_ILLPARAMVAL;
+            }
+            channel.params.insert(parameter, value);
+            PCAN_ERROR_OK
+        }
+        _ => PCAN_ERROR_ILLPARAMTYPE,
+    })
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn CAN_GetErrorText(
+    error: u32,
+    language: u16,
+    buffer: *mut c_char,
+) -> u32 {
+    // Only English (0x09) and the neutral language (0x00) are provided.
+    if buffer.is_null() || (language != 0x00 && language != 0x09) {
+        return PCAN_ERROR_ILLPARAMVAL;
+    }
+    let text = match error {
+        PCAN_ERROR_OK => "No error",
+        PCAN_ERROR_QRCVEMPTY => "Receive queue is empty",
+        PCAN_ERROR_ILLHW => "Hardware handle is invalid",
+        PCAN_ERROR_ILLPARAMTYPE => "Parameter is not allowed or is invalid",
+        PCAN_ERROR_ILLPARAMVAL => "Parameter value is invalid",
+        PCAN_ERROR_INITIALIZE => "Channel is not initialized",
+        PCAN_ERROR_ILLOPERATION => "Operation is not allowed",
+        _ => return PCAN_ERROR_ILLPARAMVAL,
+    };
+    // The caller's buffer is documented to hold 256 characters.
+    unsafe { write_string(buffer as *mut c_void, 256, text) }
+}